@@ -0,0 +1,24 @@
+//! Pump-fluence series of Co L3 scans. The scans cover only 773.5–782 eV,
+//! so there is neither a pre- and post-edge for normalization nor an L2
+//! edge for the sum rules; the table reports the peak position and the
+//! dichroic asymmetry at the peak, and leaves the XMCD quantities empty.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use xmcd_rs::config::ElementConfig;
+use xmcd_rs::pipeline::Pipeline;
+use xmcd_rs::plot::PlotOptions;
+use xmcd_rs::series::Series;
+
+fn main() -> Result<(), xmcd_rs::Error> {
+    let base = Path::new("data");
+    let config = ElementConfig::load(base.join("element.ini"), "Co")?;
+    let manifest = io::BufReader::new(fs::File::open(base.join("fluence.txt"))?);
+    let series = Series::from_manifest("fluence", manifest, base, &config, &Pipeline::new())?;
+    let table = series.table()?;
+    table.write(io::stdout().lock())?;
+    io::stdout().flush()?;
+    table.plot(&PlotOptions::new("fluence.png")?)
+}
@@ -18,18 +18,20 @@ impl Model {
         let (left, right) = (rect.left(), rect.right());
         let (top, _bottom) = (rect.top(), rect.bottom());
 
-        let size = self.xas.energy.len() - 1;
-        let range = (self.xas.energy[0], self.xas.energy[size]);
+        let size = self.xas.spectrum.len() - 1;
+        let range = self.xas.spectrum.range();
         let x = self
             .xas
-            .energy
+            .spectrum
+            .energy()
             .iter()
             .map(|&x| map_range(x, range.0, range.1, left, right) as f32)
             .collect::<Vec<f32>>();
 
         let y = self
             .xas
-            .mui
+            .spectrum
+            .values()
             .iter()
             .map(|&x| map_range(x, self.bounds.0, self.bounds.1, 0., top) as f32)
             .collect::<Vec<f32>>();
@@ -68,11 +70,15 @@ fn model(app: &App) -> Model {
     let buffer = std::io::BufReader::new(file);
     let xas = Xas::new(buffer).unwrap();
     let bounds = (
-        *xas.mui
+        *xas
+            .spectrum
+            .values()
             .iter()
             .min_by(|a, b| a.partial_cmp(b).unwrap())
             .unwrap(),
-        *xas.mui
+        *xas
+            .spectrum
+            .values()
             .iter()
             .max_by(|a, b| a.partial_cmp(b).unwrap())
             .unwrap(),
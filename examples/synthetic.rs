@@ -0,0 +1,22 @@
+use std::io;
+
+use xmcd_rs::config::ElementConfig;
+use xmcd_rs::elem::Database;
+use xmcd_rs::sumrules::SumRules;
+use xmcd_rs::synthetic::{LineShape, Synthetic};
+
+fn main() -> Result<(), xmcd_rs::Error> {
+    let db = Database::load("data/elem.dat")?;
+    let config = ElementConfig::load("data/element.ini", "Co")?;
+    let mut synthetic = Synthetic::new(&db, &config)?;
+    synthetic.shape = LineShape::Voigt { sigma: 0.3 };
+    synthetic.counts = Some(1e5);
+
+    let spectra = synthetic.generate()?;
+    let sum_rules = SumRules::new(&spectra.xmcd()?);
+    eprintln!(
+        "m_orb  = {} (true {})\nm_spin = {} (true {})",
+        sum_rules.m_orb, synthetic.m_orb, sum_rules.m_spin, synthetic.m_spin
+    );
+    spectra.write(io::stdout())
+}
@@ -0,0 +1,263 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use crate::spectrum::{Metadata, Spectrum};
+use crate::xdi::{self, Xdi};
+use crate::Error;
+
+/// How μ follows from the incident intensity `I0` and the detected signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Detection {
+    /// Transmitted intensity, μ = ln(I0 / I).
+    Transmission,
+    /// Fluorescence yield, μ ∝ I / I0.
+    Fluorescence,
+    /// Total electron yield, μ ∝ I / I0.
+    Yield,
+    /// The signal already is μ; no I0 column.
+    Mu,
+}
+
+impl Detection {
+    pub fn mu(self, i0: f64, signal: f64) -> f64 {
+        match self {
+            Detection::Transmission => (i0 / signal).ln(),
+            Detection::Fluorescence | Detection::Yield => signal / i0,
+            Detection::Mu => signal,
+        }
+    }
+}
+
+impl std::fmt::Display for Detection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Transmission => write!(f, "transmission"),
+            Self::Fluorescence => write!(f, "fluorescence"),
+            Self::Yield => write!(f, "yield"),
+            Self::Mu => write!(f, "mu"),
+        }
+    }
+}
+
+impl std::str::FromStr for Detection {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mode = match s.to_lowercase().as_str() {
+            "transmission" | "trans" => Detection::Transmission,
+            "fluorescence" | "fluor" | "fy" => Detection::Fluorescence,
+            "yield" | "tey" => Detection::Yield,
+            "mu" => Detection::Mu,
+            _ => bail!(
+                "Unknown detection mode {:?}; use transmission, fluorescence, yield or mu",
+                s
+            ),
+        };
+        Ok(mode)
+    }
+}
+
+/// Numeric table read from whitespace-, tab- or comma-separated text, one
+/// vector per column.
+#[derive(Debug, Clone, Default)]
+pub struct Columns {
+    /// Column names; empty if the file has no usable header.
+    pub names: Vec<String>,
+    pub data: Vec<Vec<f64>>,
+    /// Header fields of an XDI file, attached to spectra taken from it.
+    pub metadata: Metadata,
+}
+
+impl Columns {
+    pub fn new(names: Vec<String>, data: Vec<Vec<f64>>) -> Result<Columns, Error> {
+        if !names.is_empty() && names.len() != data.len() {
+            bail!("{} names for {} columns", names.len(), data.len());
+        }
+        if data.iter().any(|c| c.len() != data[0].len()) {
+            bail!("Columns differ in length");
+        }
+        Ok(Columns {
+            names,
+            data,
+            metadata: Metadata::new(),
+        })
+    }
+
+    /// Reads rows of numbers. Blank lines are skipped; text lines before the
+    /// first row, with or without a leading `#`, form the header, and the
+    /// last of them names the columns if it has one field per column. XDI
+    /// files are named by their `Column.N` fields instead.
+    pub fn read<R: BufRead>(mut input: R) -> Result<Columns, Error> {
+        if xdi::peek(&mut input)? {
+            return Ok(Xdi::read(input)?.columns);
+        }
+        let mut header: Option<String> = None;
+        let mut data: Vec<Vec<f64>> = Vec::new();
+        for (n, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('#') {
+                if data.is_empty() {
+                    header = Some(line.trim_start_matches('#').trim().to_string());
+                }
+                continue;
+            }
+            let fields = split(line);
+            let row = fields
+                .iter()
+                .map(|f| f.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>();
+            let row = match row {
+                Ok(row) => row,
+                Err(_) if data.is_empty() => {
+                    header = Some(line.to_string());
+                    continue;
+                }
+                Err(e) => bail!("line {}: {}", n + 1, e),
+            };
+            if data.is_empty() {
+                data = vec![Vec::new(); row.len()];
+            }
+            if row.len() != data.len() {
+                bail!(
+                    "line {}: expected {} columns, found {}",
+                    n + 1,
+                    data.len(),
+                    row.len()
+                );
+            }
+            for (column, value) in data.iter_mut().zip(row) {
+                column.push(value);
+            }
+        }
+        if data.is_empty() {
+            bail!("No numeric rows");
+        }
+
+        let names = header
+            .map(|h| split(&h).iter().map(|s| s.to_string()).collect::<Vec<_>>())
+            .filter(|names| names.len() == data.len())
+            .unwrap_or_default();
+        Columns::new(names, data)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Columns, Error> {
+        let file = fs::File::open(path)?;
+        Columns::read(io::BufReader::new(file))
+    }
+
+    /// Number of rows.
+    pub fn len(&self) -> usize {
+        self.data.first().map_or(0, |c| c.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Name of column `i`, or its index if unnamed.
+    pub fn name(&self, i: usize) -> String {
+        self.names
+            .get(i)
+            .cloned()
+            .unwrap_or_else(|| format!("col{}", i))
+    }
+
+    /// Index of the column named `key`, or of the zero-based index it
+    /// spells.
+    pub fn index(&self, key: &str) -> Result<usize, Error> {
+        if let Some(i) = self.names.iter().position(|name| name == key) {
+            return Ok(i);
+        }
+        match key.parse::<usize>() {
+            Ok(i) if i < self.data.len() => Ok(i),
+            Ok(i) => bail!("No column {}; the file has {}", i, self.data.len()),
+            Err(_) if self.names.is_empty() => bail!("No column {:?}; the file has no header", key),
+            Err(_) => bail!("No column {:?}; found {}", key, self.names.join(", ")),
+        }
+    }
+
+    /// μ from the `signal` column, divided by or referred to the `i0`
+    /// column as `mode` requires, against the `energy` column.
+    pub fn spectrum(
+        &self,
+        energy: &str,
+        i0: Option<&str>,
+        signal: &str,
+        mode: Detection,
+    ) -> Result<Spectrum, Error> {
+        let energy = &self.data[self.index(energy)?];
+        let signal = &self.data[self.index(signal)?];
+        let mu = match (mode, i0) {
+            (Detection::Mu, _) => signal.clone(),
+            (_, Some(i0)) => {
+                let i0 = &self.data[self.index(i0)?];
+                i0.iter()
+                    .zip(signal)
+                    .map(|(&i0, &s)| mode.mu(i0, s))
+                    .collect()
+            }
+            (_, None) => bail!("{} detection needs an I0 column", mode),
+        };
+        let mut spectrum = Spectrum::from_unsorted(energy, &mu)?;
+        spectrum.metadata = self.metadata.clone();
+        Ok(spectrum.with_metadata("Detection.mode", &mode.to_string()))
+    }
+
+    /// The columns at `indices`, in that order.
+    pub fn select(&self, indices: &[usize]) -> Result<Columns, Error> {
+        if let Some(&i) = indices.iter().find(|&&i| i >= self.data.len()) {
+            bail!("No column {}; the file has {}", i, self.data.len());
+        }
+        let names = if self.names.is_empty() {
+            Vec::new()
+        } else {
+            indices.iter().map(|&i| self.names[i].clone()).collect()
+        };
+        let data = indices.iter().map(|&i| self.data[i].clone()).collect();
+        let mut columns = Columns::new(names, data)?;
+        columns.metadata = self.metadata.clone();
+        Ok(columns)
+    }
+
+    /// Writes space-separated rows preceded by `# key: value` lines of the
+    /// metadata and a `#` header line; spaces in names become underscores.
+    pub fn write<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        for (key, value) in &self.metadata {
+            writeln!(out, "# {}: {}", key, value)?;
+        }
+        let names = (0..self.data.len())
+            .map(|i| {
+                self.name(i)
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join("_")
+            })
+            .collect::<Vec<_>>();
+        writeln!(out, "# {}", names.join(" "))?;
+        for row in 0..self.len() {
+            let values = self
+                .data
+                .iter()
+                .map(|c| c[row].to_string())
+                .collect::<Vec<_>>();
+            writeln!(out, "{}", values.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Fields of a line separated by tabs, commas or whitespace, in that order
+/// of preference.
+fn split(line: &str) -> Vec<&str> {
+    if line.contains('\t') {
+        line.split('\t').map(str::trim).collect()
+    } else if line.contains(',') {
+        line.split(',').map(str::trim).collect()
+    } else {
+        line.split_whitespace().collect()
+    }
+}
@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+use crate::math::parse_number;
+use crate::Error;
+
+/// Minimal reader for the `element.ini` format: `[section]` headers,
+/// `key = value` pairs, a `[DEFAULT]` section inherited by every other
+/// section and `%(key)s` interpolation. Keys are case-insensitive.
+#[derive(Debug, Default)]
+pub struct Ini {
+    defaults: HashMap<String, String>,
+    sections: Vec<(String, HashMap<String, String>)>,
+}
+
+impl Ini {
+    pub fn new<R>(input: R) -> Result<Ini, Error>
+    where
+        R: BufRead,
+    {
+        let mut ini = Ini::default();
+        let mut current: Option<usize> = None;
+
+        for (n, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = line[1..line.len() - 1].trim();
+                current = if name == "DEFAULT" {
+                    None
+                } else {
+                    ini.sections.push((name.to_string(), HashMap::new()));
+                    Some(ini.sections.len() - 1)
+                };
+                continue;
+            }
+            let eq = line
+                .find(['=', ':'])
+                .ok_or_else(|| error!("line {}: expected `key = value`", n + 1))?;
+            let key = line[..eq].trim().to_lowercase();
+            let value = line[eq + 1..].trim().to_string();
+            match current {
+                Some(i) => ini.sections[i].1.insert(key, value),
+                None => ini.defaults.insert(key, value),
+            };
+        }
+
+        Ok(ini)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Ini, Error> {
+        let file = fs::File::open(path)?;
+        Ini::new(io::BufReader::new(file))
+    }
+
+    pub fn sections(&self) -> impl Iterator<Item = &str> {
+        self.sections.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn has_section(&self, section: &str) -> bool {
+        self.section(section).is_some()
+    }
+
+    fn section(&self, section: &str) -> Option<&HashMap<String, String>> {
+        self.sections
+            .iter()
+            .find(|(name, _)| name == section)
+            .map(|(_, values)| values)
+    }
+
+    fn raw(&self, section: &str, key: &str) -> Option<&str> {
+        self.section(section)
+            .and_then(|values| values.get(key))
+            .or_else(|| self.defaults.get(key))
+            .map(String::as_str)
+    }
+
+    /// Interpolated value of `key` in `section`, falling back to `[DEFAULT]`.
+    pub fn get(&self, section: &str, key: &str) -> Option<String> {
+        self.interpolate(section, key, 0)
+    }
+
+    fn interpolate(&self, section: &str, key: &str, depth: usize) -> Option<String> {
+        let raw = self.raw(section, &key.to_lowercase())?;
+        if depth > 10 {
+            return Some(raw.to_string());
+        }
+        let mut out = String::new();
+        let mut rest = raw;
+        while let Some(start) = rest.find("%(") {
+            out.push_str(&rest[..start]);
+            let end = match rest[start..].find(")s") {
+                Some(end) => start + end,
+                None => break,
+            };
+            let name = &rest[start + 2..end];
+            out.push_str(&self.interpolate(section, name, depth + 1)?);
+            rest = &rest[end + 2..];
+        }
+        out.push_str(rest);
+        Some(out)
+    }
+
+    pub fn get_f64(&self, section: &str, key: &str) -> Result<Option<f64>, Error> {
+        match self.get(section, key) {
+            Some(value) => parse_number(&value)
+                .map(Some)
+                .map_err(|e| error!("[{}] {}: {}", section, key, e)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn require_f64(&self, section: &str, key: &str) -> Result<f64, Error> {
+        self.get_f64(section, key)?
+            .ok_or_else(|| error!("[{}] missing key `{}`", section, key))
+    }
+}
+
+/// Per-element processing parameters, one `[Element]` section of
+/// `element.ini`. Offsets are in eV relative to the edge they belong to.
+///
+/// The edges are named by `edges`, the higher-j (lower-energy) edge first:
+/// `L3, L2` by default, `M5, M4` for rare earths or a single `K`. Their
+/// energies and windows come from `energy<edge>`, `<edge>st` and
+/// `<edge>en`; the `l3` fields hold the first edge and the `l2` fields the
+/// second. A single edge leaves the second window empty and the step
+/// ratio at zero unless given.
+#[derive(Debug, Clone)]
+pub struct ElementConfig {
+    pub element: String,
+    pub start_energy: f64,
+    pub end_energy: f64,
+    pub step_energy: f64,
+
+    pub preedge_start: f64,
+    pub preedge_width: f64,
+
+    /// Edge names, `edges` (default `L3, L2`).
+    pub edges: Vec<String>,
+    /// Orbital quantum number of the core level, `core.l` (from the edge
+    /// names by default).
+    pub core_l: u32,
+    /// Orbital quantum number of the probed valence shell, `valence.l` (p
+    /// for K, d for L2,3 and f for M4,5 edges by default).
+    pub valence_l: u32,
+
+    pub energy_l3: f64,
+    pub energy_l2: f64,
+    pub l3_start: f64,
+    pub l3_end: f64,
+    pub l2_start: f64,
+    pub l2_end: f64,
+
+    pub pre_end_offset: f64,
+    pub inter_start_offset: f64,
+    pub inter_end_offset: f64,
+    pub post_start_offset: f64,
+
+    /// L2/L3 edge step ratio of the two-step background.
+    pub ratio: f64,
+    /// Number of holes in the valence shell.
+    pub holes: f64,
+    pub offset_p: f64,
+    pub offset_r0: f64,
+
+    /// Factor from the effective to the true spin moment, e.g. for jj
+    /// mixing of the rare-earth 3d levels, `spin.correction` (default 1).
+    pub spin_correction: f64,
+    /// `⟨Tz⟩/⟨Sz⟩` of the magnetic dipole term, `tz.ratio` (default 0).
+    pub tz_ratio: f64,
+
+    /// Degree of circular polarization, `pc` (default 1).
+    pub pc: f64,
+    /// Angle between photon k-vector and magnetization in degrees, `angle`
+    /// (default 0).
+    pub angle: f64,
+}
+
+impl ElementConfig {
+    pub fn new(ini: &Ini, element: &str) -> Result<ElementConfig, Error> {
+        if !ini.has_section(element) {
+            bail!("No section [{}] in element config", element);
+        }
+        let get = |key: &str| ini.require_f64(element, key);
+
+        let edges = ini
+            .get(element, "edges")
+            .unwrap_or_else(|| "L3, L2".to_string())
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+        if edges.is_empty() || edges.len() > 2 {
+            bail!("[{}] edges: expected one or two edge names", element);
+        }
+        let core_l = match orbital(ini, element, "core.l")? {
+            Some(l) => l,
+            None => core_l(&edges[0]).map_err(|e| error!("[{}] {}", element, e))?,
+        };
+        let valence_l = match orbital(ini, element, "valence.l")? {
+            Some(l) => l,
+            None => (core_l + 1).min(3),
+        };
+        if core_l > 0 && edges.len() != 2 {
+            bail!(
+                "[{}] a spin-orbit split core level needs two edges",
+                element
+            );
+        }
+        let (l, c) = (
+            (valence_l * (valence_l + 1)) as f64,
+            (core_l * (core_l + 1)) as f64,
+        );
+        if l + 2.0 - c == 0.0 || (core_l > 0 && l - 2.0 - c == 0.0) {
+            bail!(
+                "[{}] no sum rule for core.l = {}, valence.l = {}",
+                element,
+                core_l,
+                valence_l
+            );
+        }
+
+        let first = &edges[0];
+        let energy_l3 = get(&format!("energy{}", first))?;
+        let l3_start = get(&format!("{}st", first))?;
+        let l3_end = get(&format!("{}en", first))?;
+        let (energy_l2, l2_start, l2_end) = match edges.get(1) {
+            Some(second) => (
+                get(&format!("energy{}", second))?,
+                get(&format!("{}st", second))?,
+                get(&format!("{}en", second))?,
+            ),
+            None => (energy_l3, l3_end, l3_end),
+        };
+
+        Ok(ElementConfig {
+            element: element.to_string(),
+            start_energy: get("startenergy")?,
+            end_energy: get("endenergy")?,
+            step_energy: get("stepenergy")?,
+
+            preedge_start: get("preedgestart")?,
+            preedge_width: get("preedgewidth")?,
+
+            edges,
+            core_l,
+            valence_l,
+
+            energy_l3,
+            energy_l2,
+            l3_start,
+            l3_end,
+            l2_start,
+            l2_end,
+
+            pre_end_offset: get("pre.en.offset")?,
+            inter_start_offset: get("inter.st.offset")?,
+            inter_end_offset: get("inter.en.offset")?,
+            post_start_offset: get("post.st.offset")?,
+
+            ratio: match ini.get_f64(element, "ratio")? {
+                Some(ratio) => ratio,
+                None if core_l == 0 => 0.0,
+                None => get("ratio")?,
+            },
+            holes: get("holes")?,
+            offset_p: get("offset.p")?,
+            offset_r0: get("offset.r0")?,
+
+            spin_correction: ini.get_f64(element, "spin.correction")?.unwrap_or(1.0),
+            tz_ratio: ini.get_f64(element, "tz.ratio")?.unwrap_or(0.0),
+
+            pc: ini.get_f64(element, "pc")?.unwrap_or(1.0),
+            angle: ini.get_f64(element, "angle")?.unwrap_or(0.0),
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, element: &str) -> Result<ElementConfig, Error> {
+        ElementConfig::new(&Ini::load(path)?, element)
+    }
+
+    /// Absolute integration window around the L3 (first) edge.
+    pub fn l3_window(&self) -> (f64, f64) {
+        (self.energy_l3 + self.l3_start, self.energy_l3 + self.l3_end)
+    }
+
+    /// Absolute integration window around the L2 (second) edge; empty for
+    /// a single edge.
+    pub fn l2_window(&self) -> (f64, f64) {
+        (self.energy_l2 + self.l2_start, self.energy_l2 + self.l2_end)
+    }
+
+    /// Factor from measured to fully polarized, collinear XMCD,
+    /// 1 / (Pc·cos θ).
+    pub fn xmcd_correction(&self) -> Result<f64, Error> {
+        let projection = self.pc * self.angle.to_radians().cos();
+        if projection.abs() < 1e-6 {
+            bail!(
+                "No circular dichroism for Pc = {} at {}°",
+                self.pc,
+                self.angle
+            );
+        }
+        Ok(1.0 / projection)
+    }
+
+    /// Energy where the pre-edge region ends.
+    pub fn pre_end(&self) -> f64 {
+        self.energy_l3 + self.pre_end_offset
+    }
+
+    /// Energy where the post-edge region starts.
+    pub fn post_start(&self) -> f64 {
+        self.energy_l2 + self.post_start_offset
+    }
+}
+
+/// The orbital quantum number `key` of `element`, a non-negative integer.
+fn orbital(ini: &Ini, element: &str, key: &str) -> Result<Option<u32>, Error> {
+    match ini.get_f64(element, key)? {
+        None => Ok(None),
+        Some(l) if l >= 0.0 && l.fract() == 0.0 && l <= f64::from(u32::MAX) => Ok(Some(l as u32)),
+        Some(l) => bail!(
+            "[{}] {}: expected a non-negative integer, found {}",
+            element,
+            key,
+            l
+        ),
+    }
+}
+
+/// Orbital quantum number of the core level behind an edge name, e.g. 1 for
+/// `L3` (2p) and 2 for `M5` (3d).
+fn core_l(edge: &str) -> Result<u32, Error> {
+    let l = match edge.to_uppercase().as_str() {
+        "K" | "L1" | "M1" | "N1" | "O1" => 0,
+        "L2" | "L3" | "M2" | "M3" | "N2" | "N3" | "O2" | "O3" => 1,
+        "M4" | "M5" | "N4" | "N5" | "O4" | "O5" => 2,
+        "N6" | "N7" => 3,
+        _ => bail!("Unknown edge {:?}; set core.l", edge),
+    };
+    Ok(l)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_edge_is_an_error() {
+        assert_eq!(core_l("m5").unwrap(), 2);
+        assert!(core_l("").is_err());
+        assert!(core_l("X9").is_err());
+        let ini = Ini::new("[X]\nedges = , Q1\n".as_bytes()).unwrap();
+        assert!(ElementConfig::new(&ini, "X").is_err());
+    }
+
+    #[test]
+    fn orbital_numbers_are_integers() {
+        for (key, value) in &[("core.l", "-1"), ("core.l", "1.5"), ("valence.l", "-2")] {
+            let text = format!("[X]\nedges = K\n{} = {}\n", key, value);
+            let ini = Ini::new(text.as_bytes()).unwrap();
+            let error = ElementConfig::new(&ini, "X").unwrap_err().to_string();
+            assert!(
+                error.starts_with(&format!("[X] {}: expected a non-negative integer", key)),
+                "{}",
+                error
+            );
+        }
+        // Past the quantum numbers, on to the energies.
+        let ini = Ini::new("[X]\nedges = K\ncore.l = 0\nvalence.l = 2.0\n".as_bytes()).unwrap();
+        let error = ElementConfig::new(&ini, "X").unwrap_err().to_string();
+        assert!(error.contains("energyK"), "{}", error);
+    }
+
+    #[test]
+    fn rare_earths_carry_spin_corrections() {
+        let ini = Ini::new(include_str!("../data/element.ini").as_bytes()).unwrap();
+        let tb = ElementConfig::new(&ini, "Tb").unwrap();
+        assert!((tb.tz_ratio + 1.0 / 9.0).abs() < 1e-12);
+        assert!(tb.spin_correction > 1.0);
+        let dy = ElementConfig::new(&ini, "Dy").unwrap();
+        assert!((dy.tz_ratio + 2.0 / 15.0).abs() < 1e-12);
+    }
+}
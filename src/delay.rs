@@ -0,0 +1,377 @@
+use std::io::BufRead;
+
+use crate::config::ElementConfig;
+use crate::fit::{self, Fit};
+use crate::math;
+use crate::plot::{Color, Figure, Panel, Style};
+use crate::spectrum::Spectrum;
+use crate::xmcd::Xmcd;
+use crate::Error;
+
+/// Points of the fitted curve in [`DemagFit::figure`].
+const FIGURE_POINTS: usize = 500;
+
+/// Pump-probe data on an energy × delay grid. Delay scans at a fixed photon
+/// energy are maps with a single energy column.
+#[derive(Debug, Clone)]
+pub struct DelayMap {
+    delay: Vec<f64>,
+    energy: Vec<f64>,
+    plus: Vec<Spectrum>,
+    minus: Vec<Spectrum>,
+}
+
+impl DelayMap {
+    /// Reads four columns per line: delay, energy, μ+ and μ−. Every delay
+    /// must be measured at the same set of energies, and every value must
+    /// be finite.
+    pub fn new<R>(mut input: R) -> Result<DelayMap, Error>
+    where
+        R: BufRead,
+    {
+        let mut points = Vec::new();
+        let mut buffer = String::new();
+        let mut n = 0;
+        while input.read_line(&mut buffer)? > 0 {
+            n += 1;
+            let line = buffer.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                let values = line
+                    .split_whitespace()
+                    .map(|s| s.parse::<f64>())
+                    .collect::<Result<Vec<_>, _>>()?;
+                if values.len() < 4 {
+                    bail!("Expected delay, energy, plus and minus columns: {:?}", line);
+                }
+                if let Some(v) = values[..4].iter().find(|v| !v.is_finite()) {
+                    bail!("line {}: non-finite value {}", n, v);
+                }
+                points.push([values[0], values[1], values[2], values[3]]);
+            }
+            buffer.clear();
+        }
+        if points.is_empty() {
+            bail!("No points in the delay map");
+        }
+
+        let axis = |k: usize| {
+            let mut axis = points.iter().map(|p| p[k]).collect::<Vec<_>>();
+            axis.sort_by(|a, b| a.total_cmp(b));
+            axis.dedup();
+            axis
+        };
+        let delay = axis(0);
+        let energy = axis(1);
+        if delay.len() * energy.len() != points.len() {
+            bail!(
+                "Incomplete map: {} points for {} delays × {} energies",
+                points.len(),
+                delay.len(),
+                energy.len()
+            );
+        }
+
+        let mut plus = vec![vec![f64::NAN; energy.len()]; delay.len()];
+        let mut minus = plus.clone();
+        for p in &points {
+            let i = delay.binary_search_by(|d| d.total_cmp(&p[0])).unwrap();
+            let j = energy.binary_search_by(|e| e.total_cmp(&p[1])).unwrap();
+            if !plus[i][j].is_nan() {
+                bail!("Duplicate point at delay {} and energy {}", p[0], p[1]);
+            }
+            plus[i][j] = p[2];
+            minus[i][j] = p[3];
+        }
+
+        let spectra = |rows: Vec<Vec<f64>>| {
+            rows.into_iter()
+                .map(|row| Spectrum::new(energy.clone(), row))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(DelayMap {
+            plus: spectra(plus)?,
+            minus: spectra(minus)?,
+            delay,
+            energy,
+        })
+    }
+
+    pub fn delay(&self) -> &[f64] {
+        &self.delay
+    }
+
+    pub fn energy(&self) -> &[f64] {
+        &self.energy
+    }
+
+    /// μ+ at each delay.
+    pub fn plus(&self) -> &[Spectrum] {
+        &self.plus
+    }
+
+    /// μ− at each delay.
+    pub fn minus(&self) -> &[Spectrum] {
+        &self.minus
+    }
+
+    /// Normalizes every delay slice and computes its XMCD.
+    pub fn xmcd(&self, config: &ElementConfig) -> Result<DelayXmcd, Error> {
+        let slices = self
+            .plus
+            .iter()
+            .zip(&self.minus)
+            .zip(&self.delay)
+            .map(|((plus, minus), delay)| {
+                Xmcd::new(plus, minus, config).map_err(|e| error!("delay {}: {}", delay, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(DelayXmcd {
+            delay: self.delay.clone(),
+            slices,
+        })
+    }
+
+    /// Helicity asymmetry (μ+ − μ−) / (μ+ + μ−) at each delay, which needs
+    /// no edge normalization and so also works for fixed-energy delay scans.
+    pub fn asymmetry(&self) -> Result<Vec<Spectrum>, Error> {
+        self.plus
+            .iter()
+            .zip(&self.minus)
+            .zip(&self.delay)
+            .map(|((plus, minus), delay)| {
+                let values = plus
+                    .values()
+                    .iter()
+                    .zip(minus.values())
+                    .map(|(p, m)| (p - m) / (p + m))
+                    .collect();
+                plus.with_values(values)
+                    .map_err(|e| error!("delay {}: {}", delay, e))
+            })
+            .collect()
+    }
+
+    /// Asymmetry at `energy` against delay. The energy must lie inside the
+    /// measured range.
+    pub fn asymmetry_trace(&self, energy: f64) -> Result<Spectrum, Error> {
+        check_energy(&self.energy, energy)?;
+        let values = self.asymmetry()?.iter().map(|s| s.at(energy)).collect();
+        Spectrum::new(self.delay.clone(), values)
+    }
+}
+
+/// Normalized XMCD for every delay of a [`DelayMap`].
+#[derive(Debug, Clone)]
+pub struct DelayXmcd {
+    delay: Vec<f64>,
+    slices: Vec<Xmcd>,
+}
+
+impl DelayXmcd {
+    pub fn delay(&self) -> &[f64] {
+        &self.delay
+    }
+
+    /// The XMCD at each delay.
+    pub fn slices(&self) -> &[Xmcd] {
+        &self.slices
+    }
+
+    /// XMCD at `energy` against delay. The energy must lie inside the range
+    /// of every slice.
+    pub fn trace(&self, energy: f64) -> Result<Spectrum, Error> {
+        for slice in &self.slices {
+            check_energy(slice.xmcd().energy(), energy)?;
+        }
+        let values = self.slices.iter().map(|s| s.xmcd().at(energy)).collect();
+        Spectrum::new(self.delay.clone(), values)
+    }
+}
+
+/// Traces are read off by interpolation, which would silently clamp an
+/// energy outside the measured grid to its end points.
+fn check_energy(grid: &[f64], energy: f64) -> Result<(), Error> {
+    let (lo, hi) = (grid[0], grid[grid.len() - 1]);
+    if !(lo..=hi).contains(&energy) {
+        bail!(
+            "Energy {} eV lies outside the measured {}–{} eV",
+            energy,
+            lo,
+            hi
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dynamics {
+    /// Single demagnetization time.
+    Exponential,
+    /// Demagnetization followed by a partial recovery.
+    DoubleExponential,
+}
+
+/// Fit of a time trace to an exponential demagnetization model convolved
+/// with a Gaussian instrument response of fixed width:
+///
+/// `y(t) = y0 − A·(1 − e^(−t/τm))·Θ(t) + B·(1 − e^(−t/τr))·Θ(t)`, with
+/// `t` measured from `t0` and `B = 0` for [`Dynamics::Exponential`].
+#[derive(Debug, Clone)]
+pub struct DemagFit {
+    pub dynamics: Dynamics,
+    pub irf_fwhm: f64,
+    pub y0: f64,
+    pub t0: f64,
+    pub amplitude: f64,
+    pub tau_m: f64,
+    pub recovery: f64,
+    pub tau_r: f64,
+    pub fit: Fit,
+}
+
+impl DemagFit {
+    /// Fits `trace`, a signal against delay.
+    pub fn new(trace: &Spectrum, dynamics: Dynamics, irf_fwhm: f64) -> Result<DemagFit, Error> {
+        let (delay, trace) = (trace.energy(), trace.values());
+        let sigma = irf_fwhm / (2.0 * (2.0 * 2f64.ln()).sqrt());
+
+        let span = delay[delay.len() - 1] - delay[0];
+        let head = (trace.len() / 4).max(1);
+        let y0 = trace[..head].iter().sum::<f64>() / head as f64;
+        let extreme = trace
+            .iter()
+            .enumerate()
+            .max_by(|a, b| (a.1 - y0).abs().total_cmp(&(b.1 - y0).abs()))
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+        let amplitude = y0 - trace[extreme];
+        let t0 = trace
+            .iter()
+            .position(|&y| (y0 - y) / amplitude >= 0.5)
+            .map_or(delay[0], |i| delay[i]);
+        let tau_m = (span / 20.0).max(irf_fwhm / 2.0);
+
+        let mut p0 = vec![y0, t0, amplitude, tau_m];
+        if dynamics == Dynamics::DoubleExponential {
+            p0.extend_from_slice(&[0.5 * amplitude, span / 3.0]);
+        }
+
+        let fit = fit::levenberg_marquardt(|p, t| demag(p, sigma, t), delay, trace, &p0)?;
+        let p = fit.params.clone();
+        let (recovery, tau_r) = match dynamics {
+            Dynamics::Exponential => (0.0, f64::INFINITY),
+            Dynamics::DoubleExponential => (p[4], p[5].abs()),
+        };
+
+        Ok(DemagFit {
+            dynamics,
+            irf_fwhm,
+            y0: p[0],
+            t0: p[1],
+            amplitude: p[2],
+            tau_m: p[3].abs(),
+            recovery,
+            tau_r,
+            fit,
+        })
+    }
+
+    /// The measured `trace` as points with the fitted curve overlaid.
+    pub fn figure(&self, trace: &Spectrum) -> Figure {
+        let (lo, hi) = trace.range();
+        let t = (0..=FIGURE_POINTS)
+            .map(|i| lo + (hi - lo) * i as f64 / FIGURE_POINTS as f64)
+            .collect::<Vec<_>>();
+        let fit = t.iter().map(|&t| self.eval(t)).collect::<Vec<_>>();
+        let panel = Panel::new("delay", "signal")
+            .trace(
+                "data",
+                trace.energy(),
+                trace.values(),
+                Style::Points,
+                Color::BLUE,
+            )
+            .trace("fit", &t, &fit, Style::Dashed, Color::RED);
+        Figure::new(vec![panel])
+    }
+
+    pub fn eval(&self, t: f64) -> f64 {
+        let sigma = self.irf_fwhm / (2.0 * (2.0 * 2f64.ln()).sqrt());
+        demag(&self.fit.params, sigma, t)
+    }
+}
+
+fn demag(p: &[f64], sigma: f64, t: f64) -> f64 {
+    let t = t - p[1];
+    let rise = |tau: f64| step(sigma, t) - decay(sigma, tau.abs(), t);
+    let mut y = p[0] - p[2] * rise(p[3]);
+    if p.len() > 4 {
+        y += p[4] * rise(p[5]);
+    }
+    y
+}
+
+/// Heaviside step convolved with a unit-area Gaussian of width `sigma`.
+fn step(sigma: f64, t: f64) -> f64 {
+    if sigma > 0.0 {
+        0.5 * math::erfc(-t / (sigma * 2f64.sqrt()))
+    } else if t >= 0.0 {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// `Θ(t)·e^(−t/τ)` convolved with a unit-area Gaussian of width `sigma`.
+fn decay(sigma: f64, tau: f64, t: f64) -> f64 {
+    if sigma > 0.0 {
+        let a = sigma * sigma / (2.0 * tau * tau) - t / tau;
+        let x = (sigma * sigma / tau - t) / (sigma * 2f64.sqrt());
+        0.5 * math::exp_erfc(a, x)
+    } else if t >= 0.0 {
+        (-t / tau).exp()
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_non_finite_values() {
+        let input = "0 770 1 1\nnan 780 1 1\n0 780 1 1\nnan 770 1 1\n";
+        assert!(DelayMap::new(input.as_bytes()).is_err());
+        let input = "0 770 1 1\n0 780 inf 1\n";
+        assert!(DelayMap::new(input.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn reads_a_complete_map() {
+        let input = "# delay energy plus minus\n1 780 3 4\n0 770 1 2\n0 780 1 2\n1 770 3 4\n";
+        let map = DelayMap::new(input.as_bytes()).unwrap();
+        assert_eq!(map.delay(), &[0.0, 1.0]);
+        assert_eq!(map.energy(), &[770.0, 780.0]);
+        assert_eq!(map.plus()[1].values(), &[3.0, 3.0]);
+    }
+
+    #[test]
+    fn rejects_an_empty_map() {
+        assert!(DelayMap::new("".as_bytes()).is_err());
+        assert!(DelayMap::new("# delay energy plus minus\n\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn traces_stay_inside_the_measured_energies() {
+        let input = "0 770 1 3\n0 780 1 1\n1 770 2 2\n1 780 3 1\n";
+        let map = DelayMap::new(input.as_bytes()).unwrap();
+        let trace = map.asymmetry_trace(775.0).unwrap();
+        assert_eq!(trace.energy(), &[0.0, 1.0]);
+        assert!((trace.values()[0] + 0.25).abs() < 1e-12);
+        assert!((trace.values()[1] - 0.25).abs() < 1e-12);
+        assert!(map.asymmetry_trace(770.0).is_ok());
+        assert!(map.asymmetry_trace(769.9).is_err());
+        assert!(map.asymmetry_trace(780.1).is_err());
+    }
+}
@@ -0,0 +1,364 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+use crate::Error;
+
+/// Emission line of an edge, from a `Lines` record.
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub iupac: String,
+    pub siegbahn: String,
+    pub energy: f64,
+    pub intensity: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Edge {
+    pub name: String,
+    pub energy: f64,
+    pub fluorescence_yield: f64,
+    pub jump_ratio: f64,
+    pub lines: Vec<Line>,
+}
+
+impl Edge {
+    /// Intensity-weighted mean energy of the emission lines.
+    pub fn fluorescence_energy(&self) -> Option<f64> {
+        let total = self.lines.iter().map(|l| l.intensity).sum::<f64>();
+        if total > 0.0 {
+            Some(
+                self.lines
+                    .iter()
+                    .map(|l| l.energy * l.intensity)
+                    .sum::<f64>()
+                    / total,
+            )
+        } else {
+            None
+        }
+    }
+}
+
+/// Log-log table with natural cubic spline second derivatives, as stored in
+/// the `Photo` and `Scatter` records.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    pub log_energy: Vec<f64>,
+    pub log_value: Vec<f64>,
+    pub spline: Vec<f64>,
+}
+
+impl Table {
+    /// Spline-interpolated value at `energy` (eV). Absorption edges appear
+    /// as repeated energies; exactly at an edge the value above it is used.
+    pub fn eval(&self, energy: f64) -> f64 {
+        let x = &self.log_energy;
+        let n = x.len();
+        if n == 0 {
+            return 0.0;
+        }
+        if n == 1 {
+            return self.log_value[0].exp();
+        }
+        let at = energy.ln();
+        let hi = x.iter().position(|&v| v > at).unwrap_or(n - 1).max(1);
+        let lo = hi - 1;
+        let h = x[hi] - x[lo];
+        if h <= 0.0 {
+            return self.log_value[hi].exp();
+        }
+        let a = (x[hi] - at) / h;
+        let b = (at - x[lo]) / h;
+        let y = &self.log_value;
+        let y2 = &self.spline;
+        let value = a * y[lo]
+            + b * y[hi]
+            + ((a * a * a - a) * y2[lo] + (b * b * b - b) * y2[hi]) * h * h / 6.0;
+        value.exp()
+    }
+}
+
+/// One `Element` record of the Elam/Ravel/Sieber `elem.dat` database.
+/// Cross sections are in cm²/g.
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub symbol: String,
+    pub z: u32,
+    pub atomic_weight: f64,
+    /// Density in g/cm³.
+    pub density: f64,
+    pub edges: Vec<Edge>,
+    pub photo: Table,
+    pub coherent: Table,
+    pub incoherent: Table,
+}
+
+impl Element {
+    pub fn edge(&self, name: &str) -> Option<&Edge> {
+        self.edges
+            .iter()
+            .find(|e| e.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Photoabsorption cross section at `energy` (eV), in cm²/g.
+    pub fn photo(&self, energy: f64) -> f64 {
+        self.photo.eval(energy)
+    }
+
+    /// Total attenuation cross section at `energy` (eV), in cm²/g.
+    pub fn total(&self, energy: f64) -> f64 {
+        self.photo.eval(energy) + self.coherent.eval(energy) + self.incoherent.eval(energy)
+    }
+
+    /// Photoabsorption of this element without the jump at `edge`,
+    /// extrapolating the cross section below the edge with its log-log
+    /// slope.
+    pub fn photo_below(&self, edge: &Edge, energy: f64) -> f64 {
+        if energy < edge.energy {
+            return self.photo(energy);
+        }
+        let x = &self.photo.log_energy;
+        let y = &self.photo.log_value;
+        let at = edge.energy.ln();
+        // The edge is a repeated energy in the table; `k` is the point below.
+        let k = (1..x.len().saturating_sub(1))
+            .filter(|&k| x[k] == x[k + 1])
+            .min_by(|&a, &b| (x[a] - at).abs().partial_cmp(&(x[b] - at).abs()).unwrap());
+        let k = match k {
+            Some(k) if (x[k] - at).abs() < 0.01 => k,
+            _ => return self.photo(energy),
+        };
+        // Fit the slope over the preceding ~10% in energy, past any pre-edge
+        // structure in the table.
+        let j = x[..k]
+            .iter()
+            .rposition(|&v| v < x[k] - 0.1)
+            .unwrap_or(k - 1);
+        let slope = (y[k] - y[j]) / (x[k] - x[j]);
+        (y[k] + slope * (energy.ln() - x[k])).exp()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    pub elements: Vec<Element>,
+}
+
+impl Database {
+    pub fn new<R>(input: R) -> Result<Database, Error>
+    where
+        R: BufRead,
+    {
+        #[derive(PartialEq)]
+        enum Block {
+            None,
+            Photo,
+            Scatter,
+        }
+
+        let mut elements = Vec::new();
+        let mut current: Option<Element> = None;
+        let mut block = Block::None;
+
+        for (n, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let context = |e: Error| error!("elem.dat line {}: {}", n + 1, e);
+            let num = |i: usize| -> Result<f64, Error> {
+                fields
+                    .get(i)
+                    .ok_or_else(|| error!("missing field {}", i + 1))?
+                    .parse::<f64>()
+                    .map_err(Error::from)
+            };
+
+            match fields[0] {
+                "Version" | "Lines" | "CK" | "CKtotal" => {
+                    block = Block::None;
+                }
+                "End" => break,
+                "Element" => {
+                    if let Some(element) = current.take() {
+                        elements.push(element);
+                    }
+                    current = Some(Element {
+                        symbol: fields.get(1).unwrap_or(&"").to_string(),
+                        z: num(2).map_err(context)? as u32,
+                        atomic_weight: num(3).map_err(context)?,
+                        density: num(4).map_err(context)?,
+                        edges: Vec::new(),
+                        photo: Table::default(),
+                        coherent: Table::default(),
+                        incoherent: Table::default(),
+                    });
+                    block = Block::None;
+                }
+                "EndElement" => {
+                    if let Some(element) = current.take() {
+                        elements.push(element);
+                    }
+                    block = Block::None;
+                }
+                "Edge" => {
+                    let element = current
+                        .as_mut()
+                        .ok_or_else(|| error!("elem.dat line {}: Edge outside Element", n + 1))?;
+                    element.edges.push(Edge {
+                        name: fields.get(1).unwrap_or(&"").to_string(),
+                        energy: num(2).map_err(context)?,
+                        fluorescence_yield: num(3).map_err(context)?,
+                        jump_ratio: num(4).map_err(context)?,
+                        lines: Vec::new(),
+                    });
+                    block = Block::None;
+                }
+                "Photo" => block = Block::Photo,
+                "Scatter" => block = Block::Scatter,
+                _ => {
+                    let element = match current.as_mut() {
+                        Some(element) => element,
+                        None => continue,
+                    };
+                    let first = fields[0].chars().next().unwrap_or(' ');
+                    if first.is_ascii_alphabetic() {
+                        // Emission line: IUPAC and Siegbahn symbols, energy, intensity.
+                        if let Some(edge) = element.edges.last_mut() {
+                            edge.lines.push(Line {
+                                iupac: fields[0].to_string(),
+                                siegbahn: fields.get(1).unwrap_or(&"").to_string(),
+                                energy: num(2).map_err(context)?,
+                                intensity: num(3).map_err(context)?,
+                            });
+                        }
+                        continue;
+                    }
+                    match block {
+                        Block::Photo => {
+                            element.photo.log_energy.push(num(0).map_err(context)?);
+                            element.photo.log_value.push(num(1).map_err(context)?);
+                            element.photo.spline.push(num(2).map_err(context)?);
+                        }
+                        Block::Scatter => {
+                            let x = num(0).map_err(context)?;
+                            element.coherent.log_energy.push(x);
+                            element.coherent.log_value.push(num(1).map_err(context)?);
+                            element.coherent.spline.push(num(2).map_err(context)?);
+                            element.incoherent.log_energy.push(x);
+                            element.incoherent.log_value.push(num(3).map_err(context)?);
+                            element.incoherent.spline.push(num(4).map_err(context)?);
+                        }
+                        Block::None => bail!("elem.dat line {}: unexpected {:?}", n + 1, line),
+                    }
+                }
+            }
+        }
+        if let Some(element) = current.take() {
+            elements.push(element);
+        }
+
+        Ok(Database { elements })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Database, Error> {
+        let file = fs::File::open(path)?;
+        Database::new(io::BufReader::new(file))
+    }
+
+    pub fn element(&self, symbol: &str) -> Option<&Element> {
+        self.elements
+            .iter()
+            .find(|e| e.symbol.eq_ignore_ascii_case(symbol))
+    }
+
+    pub fn require(&self, symbol: &str) -> Result<&Element, Error> {
+        self.element(symbol)
+            .ok_or_else(|| error!("Unknown element {:?}", symbol))
+    }
+}
+
+/// Sample stoichiometry, e.g. `Fe2O3` or `Co0.8Pt0.2`.
+#[derive(Debug, Clone)]
+pub struct Composition {
+    /// Element symbols with their atomic amounts.
+    pub atoms: Vec<(String, f64)>,
+    /// Density in g/cm³.
+    pub density: f64,
+}
+
+impl Composition {
+    pub fn new(formula: &str, density: f64) -> Result<Composition, Error> {
+        let mut atoms: Vec<(String, f64)> = Vec::new();
+        let chars = formula
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<Vec<_>>();
+        let mut i = 0;
+        while i < chars.len() {
+            if !chars[i].is_ascii_uppercase() {
+                bail!("Bad formula {:?} at {:?}", formula, chars[i]);
+            }
+            let mut symbol = chars[i].to_string();
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_lowercase() {
+                symbol.push(chars[i]);
+                i += 1;
+            }
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let amount = if start == i {
+                1.0
+            } else {
+                chars[start..i].iter().collect::<String>().parse::<f64>()?
+            };
+            match atoms.iter_mut().find(|(s, _)| *s == symbol) {
+                Some(atom) => atom.1 += amount,
+                None => atoms.push((symbol, amount)),
+            }
+        }
+        if atoms.is_empty() {
+            bail!("Empty formula");
+        }
+        Ok(Composition { atoms, density })
+    }
+
+    /// Mass fraction of each element.
+    pub fn mass_fractions<'a>(&self, db: &'a Database) -> Result<Vec<(&'a Element, f64)>, Error> {
+        let masses = self
+            .atoms
+            .iter()
+            .map(|(symbol, n)| Ok((db.require(symbol)?, n * db.require(symbol)?.atomic_weight)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let total = masses.iter().map(|(_, m)| m).sum::<f64>();
+        Ok(masses.into_iter().map(|(e, m)| (e, m / total)).collect())
+    }
+
+    /// Linear attenuation coefficient at `energy` (eV), in 1/cm.
+    pub fn attenuation(&self, db: &Database, energy: f64) -> Result<f64, Error> {
+        Ok(self
+            .mass_fractions(db)?
+            .iter()
+            .map(|(e, w)| w * e.total(energy))
+            .sum::<f64>()
+            * self.density)
+    }
+}
+
+impl fmt::Display for Composition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (symbol, amount) in &self.atoms {
+            write!(f, "{}", symbol)?;
+            if *amount != 1.0 {
+                write!(f, "{}", amount)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,389 @@
+use std::f64::consts::PI;
+use std::io::BufRead;
+use std::str::FromStr;
+
+use nalgebra::{DMatrix, DVector};
+
+use crate::math;
+use crate::spectrum::Spectrum;
+use crate::xas::Xas;
+use crate::xdi::{self, Xdi};
+use crate::Error;
+
+/// `2m/ħ²` in 1/(eV·Å²): `k = sqrt(ETOK·(E − E0))`.
+const ETOK: f64 = 0.262_468_284_3;
+/// Step of the uniform k grid, in 1/Å.
+pub const KSTEP: f64 = 0.05;
+/// FFT length; the R grid step is `π / (KSTEP·NFFT)`.
+const NFFT: usize = 2048;
+/// Points at the high-k end where χ is pulled towards zero.
+const NCLAMP: usize = 5;
+
+/// Fourier transform window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    Hanning,
+    Kaiser,
+}
+
+impl Window {
+    /// Window weights on `x` for the range `[min, max]`. For `Hanning`, `dx`
+    /// is the width of the sin² tapers centred on both ends; for `Kaiser`
+    /// it is the shape parameter of the Kaiser–Bessel window spanning the
+    /// range.
+    pub fn weights(self, x: &[f64], min: f64, max: f64, dx: f64) -> Vec<f64> {
+        match self {
+            Window::Hanning => {
+                let (x1, x2) = (min - dx / 2.0, min + dx / 2.0);
+                let (x3, x4) = (max - dx / 2.0, max + dx / 2.0);
+                x.iter()
+                    .map(|&v| {
+                        if v < x1 || v > x4 {
+                            0.0
+                        } else if v < x2 {
+                            (PI / 2.0 * (v - x1) / (x2 - x1)).sin().powi(2)
+                        } else if v > x3 {
+                            (PI / 2.0 * (v - x3) / (x4 - x3)).cos().powi(2)
+                        } else {
+                            1.0
+                        }
+                    })
+                    .collect()
+            }
+            Window::Kaiser => {
+                let center = 0.5 * (min + max);
+                let half = 0.5 * (max - min);
+                let norm = math::bessel_i0(dx);
+                x.iter()
+                    .map(|&v| {
+                        let arg = 1.0 - ((v - center) / half).powi(2);
+                        if arg > 0.0 {
+                            math::bessel_i0(dx * arg.sqrt()) / norm
+                        } else {
+                            0.0
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hanning" | "Hanning" | "HANNING" => Ok(Window::Hanning),
+            "kaiser" | "Kaiser" | "KAISER" => Ok(Window::Kaiser),
+            _ => Err(error!("Unknown window {:?}", s)),
+        }
+    }
+}
+
+/// Background removal parameters.
+#[derive(Debug, Clone)]
+pub struct Autobk {
+    /// Edge energy; the maximum of the derivative if `None`.
+    pub e0: Option<f64>,
+    /// Distance in Å below which the background spline removes all Fourier
+    /// components.
+    pub rbkg: f64,
+    /// k-weight used while fitting the background.
+    pub kweight: f64,
+}
+
+impl Default for Autobk {
+    fn default() -> Autobk {
+        Autobk {
+            e0: None,
+            rbkg: 1.0,
+            kweight: 1.0,
+        }
+    }
+}
+
+/// Forward and back transform parameters.
+#[derive(Debug, Clone)]
+pub struct Transform {
+    pub kmin: f64,
+    pub kmax: f64,
+    pub kweight: f64,
+    pub dk: f64,
+    pub window: Window,
+    /// R range of the back transform.
+    pub rmin: f64,
+    pub rmax: f64,
+    pub dr: f64,
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform {
+            kmin: 2.0,
+            kmax: 12.0,
+            kweight: 2.0,
+            dk: 1.0,
+            window: Window::Hanning,
+            rmin: 1.0,
+            rmax: 3.0,
+            dr: 0.0,
+        }
+    }
+}
+
+/// EXAFS oscillations `χ(k)` of one absorption spectrum.
+///
+/// The background `μ0` is a cubic spline in k whose knot count follows
+/// the information content below `rbkg`, `2·rbkg·Δk/π + 1` (AUTOBK,
+/// Newville et al., PRB 47, 14126 (1993)). The knot values minimize the
+/// transform of `χ(k)` below `rbkg`; as `χ` is linear in them this is a
+/// linear least-squares problem.
+#[derive(Debug, Clone)]
+pub struct Exafs {
+    pub energy: Vec<f64>,
+    pub mu: Vec<f64>,
+    pub e0: f64,
+    pub edge_step: f64,
+    pub rbkg: f64,
+    /// `μ0(E)` on `energy`; the pre-edge line below `e0`.
+    pub background: Vec<f64>,
+    /// Uniform grid from 0 with step [`KSTEP`], in 1/Å.
+    pub k: Vec<f64>,
+    pub chi: Vec<f64>,
+}
+
+/// Complex transform of `χ(k)` on a uniform grid.
+#[derive(Debug, Clone)]
+pub struct ComplexSpectrum {
+    pub x: Vec<f64>,
+    pub re: Vec<f64>,
+    pub im: Vec<f64>,
+}
+
+impl ComplexSpectrum {
+    pub fn magnitude(&self) -> Vec<f64> {
+        self.re
+            .iter()
+            .zip(&self.im)
+            .map(|(re, im)| re.hypot(*im))
+            .collect()
+    }
+
+    pub fn phase(&self) -> Vec<f64> {
+        self.re
+            .iter()
+            .zip(&self.im)
+            .map(|(re, im)| im.atan2(*re))
+            .collect()
+    }
+}
+
+impl Exafs {
+    pub fn new(spectrum: &Spectrum, params: &Autobk) -> Result<Exafs, Error> {
+        let (energy, mu) = (spectrum.energy(), spectrum.values());
+        if energy.len() < 4 {
+            bail!("Need at least four points of μ");
+        }
+        if params.rbkg <= 0.0 {
+            bail!("Rbkg must be positive");
+        }
+        let e0 = match params.e0 {
+            Some(e0) => e0,
+            None => {
+                let derivative = energy
+                    .windows(3)
+                    .zip(mu.windows(3))
+                    .map(|(e, m)| (m[2] - m[0]) / (e[2] - e[0]))
+                    .collect::<Vec<_>>();
+                let i = math::argmax(&derivative).ok_or_else(|| error!("Cannot find E0"))?;
+                energy[i + 1]
+            }
+        };
+        let first = energy[0];
+        let last = energy[energy.len() - 1];
+        if e0 <= first || e0 >= last {
+            bail!("E0 = {} eV lies outside the data", e0);
+        }
+
+        // Pre-edge and post-edge lines fix the edge step.
+        let pre = math::line_fit(energy, mu, e0 - 150.0, e0 - 30.0)
+            .or_else(|| math::line_fit(energy, mu, first, e0 - 0.5 * (e0 - first)))
+            .ok_or_else(|| error!("Not enough pre-edge points"))?;
+        let post_start = if last - e0 > 150.0 {
+            e0 + 100.0
+        } else {
+            e0 + (last - e0) / 3.0
+        };
+        let post = math::line_fit(energy, mu, post_start, last)
+            .ok_or_else(|| error!("Not enough post-edge points"))?;
+        let edge_step = (post.0 - pre.0) * e0 + post.1 - pre.1;
+        if edge_step <= 0.0 {
+            bail!("Edge step {} must be positive", edge_step);
+        }
+
+        let kmax = (ETOK * (last - e0)).sqrt();
+        let k = math::arange(0.0, kmax, KSTEP);
+        let mu_k = k
+            .iter()
+            .map(|&k| math::interp(energy, mu, e0 + k * k / ETOK))
+            .collect::<Vec<_>>();
+
+        let nknots = ((2.0 * params.rbkg * kmax / PI) as usize + 1).max(2);
+        let knots = (0..nknots)
+            .map(|i| kmax * i as f64 / (nknots - 1) as f64)
+            .collect::<Vec<_>>();
+        let basis = (0..nknots)
+            .map(|j| {
+                let unit = (0..nknots)
+                    .map(|i| if i == j { 1.0 } else { 0.0 })
+                    .collect::<Vec<_>>();
+                math::spline(&knots, &unit, &k)
+            })
+            .collect::<Vec<_>>();
+
+        // Rows: real and imaginary χ(R) below rbkg, then the high-k clamp.
+        let window = Window::Hanning.weights(&k, 0.0, kmax, 0.1);
+        let weight = k
+            .iter()
+            .zip(&window)
+            .map(|(k, w)| k.powf(params.kweight) * w / edge_step)
+            .collect::<Vec<_>>();
+        let nr = ((params.rbkg / rstep()).ceil() as usize).max(1);
+        let nclamp = NCLAMP.min(k.len());
+        let rows = |y: &[f64]| {
+            let weighted = y
+                .iter()
+                .zip(&weight)
+                .map(|(y, w)| y * w)
+                .collect::<Vec<_>>();
+            let r = forward(&weighted);
+            let mut out = r.re[..nr].to_vec();
+            out.extend_from_slice(&r.im[..nr]);
+            out.extend(y[y.len() - nclamp..].iter().map(|y| y / edge_step));
+            out
+        };
+
+        let target = rows(&mu_k);
+        let columns = basis.iter().map(|b| rows(b)).collect::<Vec<_>>();
+        let a = DMatrix::from_fn(target.len(), nknots, |i, j| columns[j][i]);
+        let b = DVector::from_vec(target);
+        let values = a
+            .svd(true, true)
+            .solve(&b, 1e-12)
+            .map_err(|e| error!("Background fit failed: {}", e))?;
+
+        let spline_k = (0..k.len())
+            .map(|i| (0..nknots).map(|j| values[j] * basis[j][i]).sum::<f64>())
+            .collect::<Vec<_>>();
+        let chi = mu_k
+            .iter()
+            .zip(&spline_k)
+            .map(|(mu, bg)| (mu - bg) / edge_step)
+            .collect();
+        let knot_values = values.iter().cloned().collect::<Vec<_>>();
+        let background = energy
+            .iter()
+            .map(|&e| {
+                if e < e0 {
+                    pre.0 * e + pre.1
+                } else {
+                    let k = (ETOK * (e - e0)).sqrt().min(kmax);
+                    math::spline(&knots, &knot_values, &[k])[0]
+                }
+            })
+            .collect();
+
+        Ok(Exafs {
+            energy: energy.to_vec(),
+            mu: mu.to_vec(),
+            e0,
+            edge_step,
+            rbkg: params.rbkg,
+            background,
+            k,
+            chi,
+        })
+    }
+
+    /// Transmission data in three columns: energy, I0 and I1, or an XDI
+    /// file with μ or intensities in labeled columns.
+    pub fn from_columns<R>(mut input: R, params: &Autobk) -> Result<Exafs, Error>
+    where
+        R: BufRead,
+    {
+        if xdi::peek(&mut input)? {
+            return Exafs::new(&Xdi::read(input)?.spectrum()?, params);
+        }
+        let (energy, i0, i1) = Xas::load_from_file(input)?;
+        let mu = i0
+            .iter()
+            .zip(&i1)
+            .map(|(i0, i1)| (i0 / i1).ln())
+            .collect::<Vec<_>>();
+        Exafs::new(&Spectrum::from_unsorted(&energy, &mu)?, params)
+    }
+
+    /// `χ(k)·k^w`.
+    pub fn k_weighted(&self, kweight: f64) -> Vec<f64> {
+        self.chi
+            .iter()
+            .zip(&self.k)
+            .map(|(chi, k)| chi * k.powf(kweight))
+            .collect()
+    }
+
+    /// Windowed transform of `χ(k)·k^w` to R, in 1/Å^(w+1).
+    pub fn r_space(&self, params: &Transform) -> ComplexSpectrum {
+        let window = params
+            .window
+            .weights(&self.k, params.kmin, params.kmax, params.dk);
+        let weighted = self
+            .k_weighted(params.kweight)
+            .iter()
+            .zip(&window)
+            .map(|(chi, w)| chi * w)
+            .collect::<Vec<_>>();
+        forward(&weighted)
+    }
+
+    /// Back transform of the windowed R range to q, on the k grid.
+    pub fn q_space(&self, params: &Transform) -> ComplexSpectrum {
+        let r = self.r_space(params);
+        let window = params
+            .window
+            .weights(&r.x, params.rmin, params.rmax, params.dr);
+        let mut re = vec![0.0; NFFT];
+        let mut im = vec![0.0; NFFT];
+        for i in 0..r.x.len() {
+            re[i] = r.re[i] * window[i];
+            im[i] = r.im[i] * window[i];
+        }
+        math::fft(&mut re, &mut im, true);
+        let scale = 2.0 * PI.sqrt() / (KSTEP * NFFT as f64);
+        let n = self.k.len();
+        ComplexSpectrum {
+            x: self.k.clone(),
+            re: re[..n].iter().map(|v| v * scale).collect(),
+            im: im[..n].iter().map(|v| v * scale).collect(),
+        }
+    }
+}
+
+fn rstep() -> f64 {
+    PI / (KSTEP * NFFT as f64)
+}
+
+/// Zero-padded FFT of a k-space signal, keeping positive R.
+fn forward(y: &[f64]) -> ComplexSpectrum {
+    let mut re = vec![0.0; NFFT];
+    let mut im = vec![0.0; NFFT];
+    let n = y.len().min(NFFT);
+    re[..n].copy_from_slice(&y[..n]);
+    math::fft(&mut re, &mut im, false);
+    let scale = KSTEP / PI.sqrt();
+    ComplexSpectrum {
+        x: (0..NFFT / 2).map(|i| i as f64 * rstep()).collect(),
+        re: re[..NFFT / 2].iter().map(|v| v * scale).collect(),
+        im: im[..NFFT / 2].iter().map(|v| v * scale).collect(),
+    }
+}
@@ -0,0 +1,532 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::config::ElementConfig;
+use crate::pipeline;
+use crate::series::SeriesTable;
+use crate::spectrum::{Metadata, Spectrum};
+use crate::sumrules::SumRules;
+use crate::xas::Xas;
+use crate::xmcd::Xmcd;
+use crate::xmld::Xmld;
+use crate::Error;
+
+/// Version line of the XDI files written here.
+const XDI_VERSION: &str = concat!("XDI/1.0 xmcd_rs/", env!("CARGO_PKG_VERSION"));
+
+/// One column of an exported table; an empty unit means dimensionless or
+/// normalized.
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    pub unit: String,
+    pub values: Vec<f64>,
+}
+
+/// Scalar result such as the edge jump or a moment.
+#[derive(Debug, Clone)]
+pub struct Quantity {
+    pub name: String,
+    pub value: f64,
+    pub unit: String,
+}
+
+/// Spectra and scalar results with the element, edge, E0 and metadata,
+/// including the processing provenance, that every format carries.
+#[derive(Debug, Clone, Default)]
+pub struct Export {
+    pub element: Option<String>,
+    pub edge: Option<String>,
+    pub e0: Option<f64>,
+    /// `Namespace.tag` entries, e.g. `Process.01` or `Scan.file`.
+    pub metadata: Metadata,
+    pub columns: Vec<Column>,
+    pub results: Vec<Quantity>,
+}
+
+impl Export {
+    pub fn new() -> Export {
+        Export::default()
+    }
+
+    pub fn element(mut self, symbol: &str, edge: &str) -> Export {
+        self.element = Some(symbol.to_string());
+        self.edge = Some(edge.to_string());
+        self
+    }
+
+    pub fn e0(mut self, e0: f64) -> Export {
+        self.e0 = Some(e0);
+        self
+    }
+
+    /// Adds the entries of `metadata`, keeping any already present.
+    pub fn metadata(mut self, metadata: &Metadata) -> Export {
+        for (key, value) in metadata {
+            self.metadata
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        self
+    }
+
+    pub fn column(mut self, name: &str, unit: &str, values: &[f64]) -> Export {
+        self.columns.push(Column {
+            name: name.to_string(),
+            unit: unit.to_string(),
+            values: values.to_vec(),
+        });
+        self
+    }
+
+    pub fn result(mut self, name: &str, value: f64, unit: &str) -> Export {
+        self.results.push(Quantity {
+            name: name.to_string(),
+            value,
+            unit: unit.to_string(),
+        });
+        self
+    }
+
+    /// Energy, values named `name` and, if present, their uncertainties.
+    pub fn from_spectrum(spectrum: &Spectrum, name: &str, unit: &str) -> Export {
+        let mut export = Export::new()
+            .metadata(&spectrum.metadata)
+            .column("energy", "eV", spectrum.energy())
+            .column(name, unit, spectrum.values());
+        if let Some(sigma) = spectrum.uncertainty() {
+            export = export.column(&format!("{}_std", name), unit, sigma);
+        }
+        export
+    }
+
+    /// Interpolated and normalized μ of a scan with its E0, the element
+    /// and edge of `config` and the steps behind both.
+    pub fn from_xas(xas: &Xas, config: &ElementConfig) -> Result<Export, Error> {
+        let norm = xas.normalized(config)?;
+        Ok(Export::from_spectrum(&xas.spectrum, "mu", "")
+            .metadata(&norm.metadata)
+            .element(
+                &config.element,
+                config.edges.first().map_or("", |e| e.as_str()),
+            )
+            .e0(xas.e0)
+            .column("norm", "", norm.values()))
+    }
+
+    /// Energy, averaged absorption, raw and corrected dichroism and the
+    /// edge jump, with the metadata and processing steps of both
+    /// helicities; E0 is the energy of the first edge.
+    pub fn from_xmcd(xmcd: &Xmcd) -> Export {
+        let config = xmcd.config();
+        Export::new()
+            .metadata(xmcd.metadata())
+            .element(
+                &config.element,
+                config.edges.first().map_or("", |e| e.as_str()),
+            )
+            .e0(config.energy_l3)
+            .column("energy", "eV", xmcd.energy())
+            .column("xas", "", xmcd.xas().values())
+            .column("xmcd_raw", "", xmcd.xmcd_raw().values())
+            .column("xmcd", "", xmcd.xmcd().values())
+            .result("edge_jump", xmcd.edge_jump(), "")
+    }
+
+    /// Energy, averaged absorption, raw and corrected linear dichroism, the
+    /// edge jump and the incidence angle from the surface normal, with the
+    /// metadata and processing steps of both spectra; E0 is the energy of
+    /// the first edge.
+    pub fn from_xmld(xmld: &Xmld) -> Export {
+        let config = xmld.config();
+        Export::new()
+            .metadata(xmld.metadata())
+            .element(
+                &config.element,
+                config.edges.first().map_or("", |e| e.as_str()),
+            )
+            .e0(config.energy_l3)
+            .column("energy", "eV", xmld.energy())
+            .column("xas", "", xmld.xas().values())
+            .column("raw", "", xmld.raw().values())
+            .column("dichroism", "", xmld.dichroism().values())
+            .result("edge_jump", xmld.edge_jump(), "")
+            .result("angle_from_normal", xmld.angle_from_normal(), "deg")
+    }
+
+    /// One row per spectrum of a series: the parameter, peak position,
+    /// asymmetry and the XMCD quantities, with the metadata the spectra
+    /// share; E0 is the energy of the first edge.
+    pub fn from_series(table: &SeriesTable) -> Export {
+        let config = table.config();
+        Export::new()
+            .metadata(table.metadata())
+            .element(
+                &config.element,
+                config.edges.first().map_or("", |e| e.as_str()),
+            )
+            .e0(config.energy_l3)
+            .column(table.parameter(), "", &table.column(|r| r.value))
+            .column("peak_position", "eV", &table.column(|r| r.peak_position))
+            .column("asymmetry", "", &table.column(|r| r.asymmetry))
+            .column("amplitude", "", &table.column(|r| r.amplitude))
+            .column("edge_jump", "", &table.column(|r| r.edge_jump))
+            .column("m_orb", "μB", &table.column(|r| r.m_orb))
+            .column("m_spin", "μB", &table.column(|r| r.m_spin))
+    }
+
+    /// Adds the sum-rule integrals and moments.
+    pub fn sumrules(self, rules: &SumRules) -> Export {
+        self.result("p", rules.p, "eV")
+            .result("q", rules.q, "eV")
+            .result("r", rules.r, "eV")
+            .result("m_orb", rules.m_orb, "μB")
+            .result("m_spin", rules.m_spin, "μB")
+            .result("m_orb_raw", rules.m_orb_raw, "μB")
+            .result("m_spin_raw", rules.m_spin_raw, "μB")
+            .result("ratio", rules.ratio(), "")
+    }
+
+    fn rows(&self) -> Result<usize, Error> {
+        let rows = self.columns.first().map_or(0, |c| c.values.len());
+        if self.columns.iter().any(|c| c.values.len() != rows) {
+            bail!("Exported columns differ in length");
+        }
+        Ok(rows)
+    }
+
+    /// `Namespace.tag: value` lines shared by the CSV and XDI headers; a
+    /// line break in a key or value is an error, as it would end the line.
+    fn fields(&self) -> Result<Vec<(String, String)>, Error> {
+        let mut fields = Vec::new();
+        if let Some(element) = &self.element {
+            fields.push(("Element.symbol".to_string(), element.clone()));
+        }
+        if let Some(edge) = &self.edge {
+            fields.push(("Element.edge".to_string(), edge.clone()));
+        }
+        if let Some(e0) = self.e0 {
+            fields.push(("Scan.edge_energy".to_string(), format!("{} eV", e0)));
+        }
+        // Metadata read from an XDI file repeats the element and edge.
+        for (key, value) in &self.metadata {
+            if fields.iter().all(|(k, _)| k != key) {
+                fields.push((key.clone(), value.clone()));
+            }
+        }
+        for q in &self.results {
+            fields.push((format!("Result.{}", q.name), with_unit(q.value, &q.unit)));
+        }
+        if let Some((key, _)) = fields
+            .iter()
+            .find(|(k, v)| k.contains(&['\n', '\r'][..]) || v.contains(&['\n', '\r'][..]))
+        {
+            bail!("Header field {:?} spans several lines", key);
+        }
+        Ok(fields)
+    }
+
+    pub fn write<W: Write>(&self, format: Format, out: W) -> Result<(), Error> {
+        match format {
+            Format::Csv => self.write_csv(out),
+            Format::Json => self.write_json(out),
+            Format::Xdi => self.write_xdi(out),
+        }
+    }
+
+    /// `# Namespace.tag: value` comment lines, then a header row of names
+    /// with units in parentheses and comma-separated rows.
+    pub fn write_csv<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        let rows = self.rows()?;
+        let fields = self.fields()?;
+        for (key, value) in fields {
+            writeln!(out, "# {}: {}", key, value)?;
+        }
+        let header = self
+            .columns
+            .iter()
+            .map(|c| csv_field(&label(c)))
+            .collect::<Vec<_>>();
+        writeln!(out, "{}", header.join(","))?;
+        for row in 0..rows {
+            let values = self
+                .columns
+                .iter()
+                .map(|c| c.values[row].to_string())
+                .collect::<Vec<_>>();
+            writeln!(out, "{}", values.join(","))?;
+        }
+        Ok(())
+    }
+
+    /// One object with `element`, `edge`, `e0`, `provenance`, `metadata`,
+    /// `results` and `columns`; non-finite numbers become `null`.
+    pub fn write_json<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        self.rows()?;
+        let optional =
+            |s: &Option<String>| s.as_ref().map_or("null".to_string(), |s| json_string(s));
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"element\": {},", optional(&self.element))?;
+        writeln!(out, "  \"edge\": {},", optional(&self.edge))?;
+        writeln!(
+            out,
+            "  \"e0\": {},",
+            self.e0.map_or("null".to_string(), json_number)
+        )?;
+        let provenance = pipeline::provenance(&self.metadata)
+            .iter()
+            .map(|s| json_string(s))
+            .collect::<Vec<_>>();
+        writeln!(out, "  \"provenance\": [{}],", provenance.join(", "))?;
+        let metadata = self
+            .metadata
+            .iter()
+            .map(|(k, v)| format!("    {}: {}", json_string(k), json_string(v)))
+            .collect::<Vec<_>>();
+        writeln!(out, "  \"metadata\": {{{}}},", block(&metadata, "  "))?;
+        let results = self
+            .results
+            .iter()
+            .map(|q| {
+                format!(
+                    "    {}: {{\"value\": {}, \"unit\": {}}}",
+                    json_string(&q.name),
+                    json_number(q.value),
+                    json_string(&q.unit)
+                )
+            })
+            .collect::<Vec<_>>();
+        writeln!(out, "  \"results\": {{{}}},", block(&results, "  "))?;
+        let columns = self
+            .columns
+            .iter()
+            .map(|c| {
+                let values = c.values.iter().map(|&v| json_number(v)).collect::<Vec<_>>();
+                format!(
+                    "    {{\"name\": {}, \"unit\": {}, \"values\": [{}]}}",
+                    json_string(&c.name),
+                    json_string(&c.unit),
+                    values.join(", ")
+                )
+            })
+            .collect::<Vec<_>>();
+        writeln!(out, "  \"columns\": [{}]", block(&columns, "  "))?;
+        writeln!(out, "}}")?;
+        Ok(())
+    }
+
+    /// XAS Data Interchange 1.0: the version line, `Column.N: name unit`
+    /// and `Namespace.tag` fields, then the column labels and
+    /// space-separated rows.
+    pub fn write_xdi<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        let rows = self.rows()?;
+        let fields = self.fields()?;
+        writeln!(out, "# {}", XDI_VERSION)?;
+        for (i, c) in self.columns.iter().enumerate() {
+            writeln!(out, "# Column.{}: {}", i + 1, xdi_column(c))?;
+        }
+        for (key, value) in fields {
+            writeln!(out, "# {}: {}", key, value)?;
+        }
+        writeln!(out, "# ///")?;
+        writeln!(out, "# written by xmcd_rs")?;
+        writeln!(out, "#----")?;
+        let names = self
+            .columns
+            .iter()
+            .map(|c| word(&c.name))
+            .collect::<Vec<_>>();
+        writeln!(out, "# {}", names.join(" "))?;
+        for row in 0..rows {
+            let values = self
+                .columns
+                .iter()
+                .map(|c| c.values[row].to_string())
+                .collect::<Vec<_>>();
+            writeln!(out, "{}", values.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// File format of an [`Export`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+    Xdi,
+}
+
+impl Format {
+    /// Format named by the extension of `path`, if it names one.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(|e| e.parse().ok())
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Csv => write!(f, "csv"),
+            Self::Json => write!(f, "json"),
+            Self::Xdi => write!(f, "xdi"),
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = match s {
+            "csv" | "CSV" => Format::Csv,
+            "json" | "JSON" => Format::Json,
+            "xdi" | "XDI" => Format::Xdi,
+            _ => bail!("Unknown export format {:?}; use csv, json or xdi", s),
+        };
+        Ok(s)
+    }
+}
+
+fn with_unit(value: f64, unit: &str) -> String {
+    if unit.is_empty() {
+        value.to_string()
+    } else {
+        format!("{} {}", value, unit)
+    }
+}
+
+/// `name (unit)`, or the bare name without a unit.
+fn label(column: &Column) -> String {
+    if column.unit.is_empty() {
+        column.name.clone()
+    } else {
+        format!("{} ({})", column.name, column.unit)
+    }
+}
+
+/// XDI column labels are single words; spaces become underscores.
+fn word(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join("_")
+}
+
+fn xdi_column(column: &Column) -> String {
+    if column.unit.is_empty() {
+        word(&column.name)
+    } else {
+        format!("{} {}", word(&column.name), column.unit)
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_number(x: f64) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// Comma-separated `lines`, one per line and closed at `indent`; nothing
+/// between the brackets if empty.
+fn block(lines: &[String], indent: &str) -> String {
+    if lines.is_empty() {
+        String::new()
+    } else {
+        format!("\n{}\n{}", lines.join(",\n"), indent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xdi::Xdi;
+
+    fn export() -> Export {
+        let mut metadata = Metadata::new();
+        metadata.insert("Scan.file".to_string(), "a, \"b\".txt".to_string());
+        metadata.insert("Process.01".to_string(), "smooth half_width=2".to_string());
+        Export::new()
+            .element("Co", "L3")
+            .e0(778.1)
+            .metadata(&metadata)
+            .column("energy", "eV", &[770.0, 771.0])
+            .column("xmcd", "", &[0.5, -0.25])
+            .result("m_orb", 0.25, "μB")
+    }
+
+    #[test]
+    fn headers_read_back() {
+        let export = export();
+        let fields = export.fields().unwrap();
+
+        let mut xdi = Vec::new();
+        export.write_xdi(&mut xdi).unwrap();
+        let xdi = Xdi::read(xdi.as_slice()).unwrap();
+        for (key, value) in &fields {
+            assert_eq!(xdi.field(key), Some(value.as_str()), "{}", key);
+        }
+        assert_eq!(xdi.columns.names, ["energy", "xmcd"]);
+        assert_eq!(xdi.units, ["eV", ""]);
+
+        let mut csv = Vec::new();
+        export.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let header = csv
+            .lines()
+            .filter_map(|line| line.strip_prefix("# "))
+            .map(|line| {
+                let (key, value) = line.split_at(line.find(": ").unwrap());
+                (key.to_string(), value[2..].to_string())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(header, fields);
+        assert_eq!(
+            csv.lines().skip(fields.len()).collect::<Vec<_>>(),
+            ["energy (eV),xmcd", "770,0.5", "771,-0.25"]
+        );
+    }
+
+    #[test]
+    fn line_breaks_stay_out_of_headers() {
+        let export = export()
+            .metadata(&std::iter::once(("Scan.note".to_string(), "a\nb".to_string())).collect());
+        let mut out = Vec::new();
+        let error = export.write_csv(&mut out).unwrap_err().to_string();
+        assert!(error.contains("Scan.note"), "{}", error);
+        assert!(export.write_xdi(&mut out).is_err());
+        assert!(out.is_empty());
+
+        export.write_json(&mut out).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains(r#""Scan.note": "a\nb""#), "{}", json);
+        assert!(json.contains(r#""Scan.file": "a, \"b\".txt""#), "{}", json);
+        assert!(json.contains(r#""provenance": ["smooth half_width=2"]"#));
+        assert_eq!(json_string("tab\t \\ \u{1} μ"), r#""tab\t \\ \u0001 μ""#);
+    }
+}
@@ -0,0 +1,287 @@
+use std::io::Write;
+
+use nalgebra::DMatrix;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::math;
+use crate::series::Series;
+use crate::spectrum::Spectrum;
+use crate::xmcd::Xmcd;
+use crate::Error;
+
+/// Relative change of the NMF residual below which the updates stop.
+const NMF_TOLERANCE: f64 = 1e-9;
+
+/// Spectra of a series on one energy grid, one column per spectrum.
+#[derive(Debug, Clone)]
+pub struct SpectraMatrix {
+    pub energy: Vec<f64>,
+    /// Series parameter of each spectrum.
+    pub values: Vec<f64>,
+    pub spectra: Vec<Vec<f64>>,
+}
+
+/// Principal component analysis by SVD of the raw (not mean-centred) data
+/// matrix, with Malinowski's indicator function and F-test for the number
+/// of significant components.
+#[derive(Debug, Clone)]
+pub struct Pca {
+    pub energy: Vec<f64>,
+    pub values: Vec<f64>,
+    /// Squared singular values, largest first.
+    pub eigenvalues: Vec<f64>,
+    /// Fraction of the total variance of each component.
+    pub variance: Vec<f64>,
+    /// Real error when keeping `n` components, for `n = 1..`.
+    pub real_error: Vec<f64>,
+    /// Malinowski's IND; its minimum marks the number of components.
+    pub ind: Vec<f64>,
+    /// F(1, s − n) of each eigenvalue against the remaining ones.
+    pub f: Vec<f64>,
+    /// Probability that eigenvalue `n` belongs to the noise.
+    pub significance: Vec<f64>,
+    /// Abstract component spectra, scaled by their singular values.
+    pub components: Vec<Vec<f64>>,
+    /// Score of each component across the series.
+    pub scores: Vec<Vec<f64>>,
+}
+
+/// Non-negative matrix factorization `D ≈ W·H` by Lee–Seung multiplicative
+/// updates. Component spectra are scaled to a maximum of one.
+#[derive(Debug, Clone)]
+pub struct Nmf {
+    pub energy: Vec<f64>,
+    pub values: Vec<f64>,
+    pub components: Vec<Vec<f64>>,
+    /// Weight of each component across the series.
+    pub weights: Vec<Vec<f64>>,
+    /// Frobenius norm of `D − W·H`.
+    pub residual: f64,
+    pub iterations: usize,
+}
+
+impl SpectraMatrix {
+    pub fn new(
+        energy: Vec<f64>,
+        values: Vec<f64>,
+        spectra: Vec<Vec<f64>>,
+    ) -> Result<SpectraMatrix, Error> {
+        if spectra.len() < 2 || values.len() != spectra.len() {
+            bail!("Need at least two spectra, each with a parameter value");
+        }
+        if spectra.iter().any(|s| s.len() != energy.len()) {
+            bail!("Spectra must share the energy grid");
+        }
+        Ok(SpectraMatrix {
+            energy,
+            values,
+            spectra,
+        })
+    }
+
+    /// Spectra with one parameter value each, interpolated onto the
+    /// energies of the first one within the range they all cover.
+    pub fn from_spectra(values: Vec<f64>, spectra: &[Spectrum]) -> Result<SpectraMatrix, Error> {
+        let first = match spectra.first() {
+            Some(first) => first,
+            None => bail!("No spectra"),
+        };
+        let lo = spectra.iter().map(|s| s.range().0).fold(f64::MIN, f64::max);
+        let hi = spectra.iter().map(|s| s.range().1).fold(f64::MAX, f64::min);
+        let grid = first.crop(lo, hi)?;
+        let spectra = spectra
+            .iter()
+            .map(|s| Ok(s.resample(grid.energy())?.into_parts().1))
+            .collect::<Result<Vec<_>, Error>>()?;
+        SpectraMatrix::new(grid.energy().to_vec(), values, spectra)
+    }
+
+    /// Takes `signal` of the XMCD of each pair of a series, interpolated
+    /// onto the energies of the first one within the range they all cover.
+    pub fn from_series<F>(series: &Series, signal: F) -> Result<SpectraMatrix, Error>
+    where
+        F: Fn(&Xmcd) -> &Spectrum,
+    {
+        if series.points().is_empty() {
+            bail!("Empty series");
+        }
+        let values = series.points().iter().map(|p| p.value()).collect();
+        let spectra = series
+            .points()
+            .iter()
+            .map(|p| match p.xmcd() {
+                Some(xmcd) => Ok(signal(xmcd).clone()),
+                None => bail!(
+                    "{} = {}: the spectra do not reach the normalization windows",
+                    series.parameter(),
+                    p.value()
+                ),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        SpectraMatrix::from_spectra(values, &spectra)
+    }
+
+    fn matrix(&self) -> DMatrix<f64> {
+        DMatrix::from_fn(self.energy.len(), self.spectra.len(), |i, j| {
+            self.spectra[j][i]
+        })
+    }
+
+    pub fn pca(&self) -> Result<Pca, Error> {
+        let (r, c) = (self.energy.len(), self.spectra.len());
+        if r < c {
+            bail!("Need more energies ({}) than spectra ({})", r, c);
+        }
+        let svd = self.matrix().svd(true, true);
+        let singular = svd.singular_values;
+        let u = svd.u.ok_or_else(|| error!("SVD failed"))?;
+        let v_t = svd.v_t.ok_or_else(|| error!("SVD failed"))?;
+        // nalgebra does not sort the singular values.
+        let mut order = (0..singular.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| singular[b].partial_cmp(&singular[a]).unwrap());
+
+        let s = order.len();
+        let eigenvalues = order
+            .iter()
+            .map(|&k| singular[k].powi(2))
+            .collect::<Vec<_>>();
+        let total = eigenvalues.iter().sum::<f64>();
+        let variance = eigenvalues.iter().map(|l| l / total).collect();
+        let tail = |n: usize| eigenvalues[n..].iter().sum::<f64>();
+
+        let mut real_error = Vec::new();
+        let mut ind = Vec::new();
+        for n in 1..s {
+            let re = (tail(n) / (r * (c - n)) as f64).sqrt();
+            real_error.push(re);
+            ind.push(re / ((c - n) * (c - n)) as f64);
+        }
+
+        // Malinowski's F-test on reduced eigenvalues, j counted from one.
+        let weight = |j: usize| ((r - j + 1) * (c - j + 1)) as f64;
+        let mut f = Vec::new();
+        let mut significance = Vec::new();
+        for n in 1..s {
+            let pool = (n + 1..=s).map(weight).sum::<f64>();
+            let rest = tail(n);
+            let value = if rest > 0.0 {
+                pool / weight(n) * eigenvalues[n - 1] / rest
+            } else {
+                f64::INFINITY
+            };
+            let dof = (s - n) as f64;
+            f.push(value);
+            significance.push(math::beta_inc(0.5 * dof, 0.5, dof / (dof + value)));
+        }
+
+        let components = order
+            .iter()
+            .map(|&k| {
+                let sv = singular[k];
+                u.column(k).iter().map(|v| v * sv).collect()
+            })
+            .collect();
+        let scores = order
+            .iter()
+            .map(|&k| v_t.row(k).iter().cloned().collect())
+            .collect();
+
+        Ok(Pca {
+            energy: self.energy.clone(),
+            values: self.values.clone(),
+            eigenvalues,
+            variance,
+            real_error,
+            ind,
+            f,
+            significance,
+            components,
+            scores,
+        })
+    }
+
+    /// Factorizes into `rank` components. Negative data points, typically
+    /// noise, are set to zero.
+    pub fn nmf(&self, rank: usize, iterations: usize, seed: u64) -> Result<Nmf, Error> {
+        let (r, c) = (self.energy.len(), self.spectra.len());
+        if rank == 0 || rank > c {
+            bail!("Rank must be between 1 and the number of spectra ({})", c);
+        }
+        let d = self.matrix().map(|v| v.max(0.0));
+        let scale = (d.mean()).max(1e-12);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut w = DMatrix::from_fn(r, rank, |_, _| scale * rng.gen::<f64>() + 1e-12);
+        let mut h = DMatrix::from_fn(rank, c, |_, _| rng.gen::<f64>() + 1e-12);
+
+        let eps = 1e-300;
+        let mut residual = (&d - &w * &h).norm();
+        let mut done = iterations;
+        for i in 0..iterations {
+            let numerator = w.transpose() * &d;
+            let denominator = w.transpose() * &w * &h;
+            h = h.component_mul(&numerator.zip_map(&denominator, |n, m| n / (m + eps)));
+            let numerator = &d * h.transpose();
+            let denominator = &w * &h * h.transpose();
+            w = w.component_mul(&numerator.zip_map(&denominator, |n, m| n / (m + eps)));
+
+            let next = (&d - &w * &h).norm();
+            let converged = (residual - next).abs() <= NMF_TOLERANCE * residual.max(eps);
+            residual = next;
+            if converged {
+                done = i + 1;
+                break;
+            }
+        }
+
+        let mut components = Vec::with_capacity(rank);
+        let mut weights = Vec::with_capacity(rank);
+        for k in 0..rank {
+            let max = w.column(k).iter().cloned().fold(0.0, f64::max);
+            let norm = if max > 0.0 { max } else { 1.0 };
+            components.push(w.column(k).iter().map(|v| v / norm).collect());
+            weights.push(h.row(k).iter().map(|v| v * norm).collect());
+        }
+
+        Ok(Nmf {
+            energy: self.energy.clone(),
+            values: self.values.clone(),
+            components,
+            weights,
+            residual,
+            iterations: done,
+        })
+    }
+}
+
+impl Pca {
+    /// Number of components at the minimum of IND.
+    pub fn components_by_ind(&self) -> usize {
+        self.ind
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+            .map_or(1, |(i, _)| i + 1)
+    }
+
+    /// Writes the scree table: component, eigenvalue, variance, RE, IND, F
+    /// and significance level.
+    pub fn write<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        writeln!(out, "# n eigenvalue variance re ind f significance")?;
+        for n in 0..self.eigenvalues.len() {
+            let stat = |v: &[f64]| v.get(n).cloned().unwrap_or(f64::NAN);
+            writeln!(
+                out,
+                "{} {} {} {} {} {} {}",
+                n + 1,
+                self.eigenvalues[n],
+                self.variance[n],
+                stat(&self.real_error),
+                stat(&self.ind),
+                stat(&self.f),
+                stat(&self.significance)
+            )?;
+        }
+        Ok(())
+    }
+}
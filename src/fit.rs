@@ -0,0 +1,190 @@
+use nalgebra::{DMatrix, DVector};
+
+use crate::Error;
+
+const MAX_ITERATIONS: usize = 200;
+
+/// Result of a least-squares fit.
+#[derive(Debug, Clone)]
+pub struct Fit {
+    pub params: Vec<f64>,
+    /// One-sigma uncertainties from the covariance matrix, scaled by the
+    /// reduced χ².
+    pub errors: Vec<f64>,
+    pub chi2: f64,
+    pub reduced_chi2: f64,
+}
+
+/// Levenberg–Marquardt minimization of `Σ (y − model(p, x))²` with a
+/// forward-difference Jacobian.
+pub fn levenberg_marquardt<F>(model: F, x: &[f64], y: &[f64], p0: &[f64]) -> Result<Fit, Error>
+where
+    F: Fn(&[f64], f64) -> f64,
+{
+    if x.len() != y.len() {
+        bail!("x and y differ in length");
+    }
+    let n = x.len();
+    let m = p0.len();
+    if n <= m {
+        bail!("Need more points ({}) than parameters ({})", n, m);
+    }
+
+    let residuals = |p: &[f64]| DVector::from_iterator(n, (0..n).map(|i| y[i] - model(p, x[i])));
+    let jacobian = |p: &[f64]| {
+        let base = (0..n).map(|i| model(p, x[i])).collect::<Vec<_>>();
+        let mut jac = DMatrix::zeros(n, m);
+        let mut q = p.to_vec();
+        for j in 0..m {
+            let h = 1e-6 * p[j].abs().max(1e-3);
+            q[j] = p[j] + h;
+            for i in 0..n {
+                jac[(i, j)] = (model(&q, x[i]) - base[i]) / h;
+            }
+            q[j] = p[j];
+        }
+        jac
+    };
+
+    let mut p = p0.to_vec();
+    let mut r = residuals(&p);
+    let mut chi2 = r.norm_squared();
+    let mut lambda = 1e-3;
+
+    for _ in 0..MAX_ITERATIONS {
+        let jac = jacobian(&p);
+        let jtj = jac.transpose() * &jac;
+        let jtr = jac.transpose() * &r;
+
+        let mut damped = jtj.clone();
+        for k in 0..m {
+            damped[(k, k)] += lambda * jtj[(k, k)].max(1e-12);
+        }
+        let step = match damped.lu().solve(&jtr) {
+            Some(step) => step,
+            None => bail!("Singular normal equations"),
+        };
+
+        let trial = p
+            .iter()
+            .zip(step.iter())
+            .map(|(p, s)| p + s)
+            .collect::<Vec<_>>();
+        let trial_r = residuals(&trial);
+        let trial_chi2 = trial_r.norm_squared();
+
+        if trial_chi2.is_finite() && trial_chi2 < chi2 {
+            let converged = (chi2 - trial_chi2) <= 1e-12 * chi2.max(1e-300);
+            p = trial;
+            r = trial_r;
+            chi2 = trial_chi2;
+            lambda = (lambda / 10.0).max(1e-12);
+            if converged {
+                break;
+            }
+        } else {
+            lambda *= 10.0;
+            if lambda > 1e12 {
+                break;
+            }
+        }
+    }
+
+    let reduced_chi2 = chi2 / (n - m) as f64;
+    let jac = jacobian(&p);
+    let errors = match (jac.transpose() * &jac).try_inverse() {
+        Some(cov) => (0..m)
+            .map(|k| (cov[(k, k)] * reduced_chi2).abs().sqrt())
+            .collect(),
+        None => vec![f64::NAN; m],
+    };
+
+    Ok(Fit {
+        params: p,
+        errors,
+        chi2,
+        reduced_chi2,
+    })
+}
+
+/// Non-negative least squares, `min ‖A·x − b‖` subject to `x ≥ 0`
+/// (Lawson and Hanson's active-set method).
+pub fn nnls(a: &DMatrix<f64>, b: &DVector<f64>) -> Result<Vec<f64>, Error> {
+    let (n, m) = a.shape();
+    if b.len() != n {
+        bail!("A has {} rows but b has {}", n, b.len());
+    }
+    let tol = 1e-12 * a.norm().max(1e-300) * b.norm().max(1e-300);
+    let mut x = vec![0.0; m];
+    let mut active = vec![false; m];
+
+    let solve = |active: &[bool]| -> Result<Vec<f64>, Error> {
+        let columns = (0..m).filter(|&j| active[j]).collect::<Vec<_>>();
+        let sub = DMatrix::from_fn(n, columns.len(), |i, k| a[(i, columns[k])]);
+        let s = sub
+            .svd(true, true)
+            .solve(b, 1e-12)
+            .map_err(|e| error!("Least squares failed: {}", e))?;
+        let mut full = vec![0.0; m];
+        for (k, &j) in columns.iter().enumerate() {
+            full[j] = s[k];
+        }
+        Ok(full)
+    };
+
+    for _ in 0..3 * m.max(1) * MAX_ITERATIONS {
+        let residual = b - a * DVector::from_column_slice(&x);
+        let w = a.transpose() * residual;
+        let next = (0..m)
+            .filter(|&j| !active[j] && w[j] > tol)
+            .max_by(|&i, &j| w[i].partial_cmp(&w[j]).unwrap());
+        let j = match next {
+            Some(j) => j,
+            None => return Ok(x),
+        };
+        active[j] = true;
+
+        loop {
+            let s = solve(&active)?;
+            if (0..m).filter(|&i| active[i]).all(|i| s[i] > 0.0) {
+                x = s;
+                break;
+            }
+            let alpha = (0..m)
+                .filter(|&i| active[i] && s[i] <= 0.0)
+                .map(|i| x[i] / (x[i] - s[i]))
+                .fold(f64::INFINITY, f64::min);
+            for i in 0..m {
+                x[i] += alpha * (s[i] - x[i]);
+                if active[i] && x[i] <= 1e-15 {
+                    active[i] = false;
+                    x[i] = 0.0;
+                }
+            }
+            if !active.iter().any(|&a| a) {
+                break;
+            }
+        }
+    }
+    bail!("NNLS did not converge")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nnls_clamps_negative_coefficients() {
+        let a = DMatrix::from_row_slice(3, 2, &[1.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+        let x = nnls(&a, &DVector::from_column_slice(&[2.0, 3.0, 5.0])).unwrap();
+        assert!(
+            (x[0] - 2.0).abs() < 1e-10 && (x[1] - 3.0).abs() < 1e-10,
+            "{:?}",
+            x
+        );
+        // Unconstrained, the solution would be (3, −2).
+        let x = nnls(&a, &DVector::from_column_slice(&[3.0, -2.0, 1.0])).unwrap();
+        assert!((x[0] - 2.0).abs() < 1e-10 && x[1] == 0.0, "{:?}", x);
+        assert!(nnls(&a, &DVector::from_column_slice(&[1.0, 2.0])).is_err());
+    }
+}
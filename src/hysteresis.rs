@@ -0,0 +1,378 @@
+use std::io::BufRead;
+
+use crate::math;
+use crate::spectrum::Spectrum;
+use crate::Error;
+
+/// One field sweep direction: the signal against field, sorted by ascending
+/// field with repeated fields averaged.
+#[derive(Debug, Clone)]
+pub struct Branch {
+    signal: Spectrum,
+}
+
+impl Branch {
+    fn from_points(points: &[(f64, f64)]) -> Result<Branch, Error> {
+        let field = points.iter().map(|p| p.0).collect::<Vec<_>>();
+        let signal = points.iter().map(|p| p.1).collect::<Vec<_>>();
+        Ok(Branch {
+            signal: Spectrum::from_unsorted(&field, &signal)?,
+        })
+    }
+
+    pub fn field(&self) -> &[f64] {
+        self.signal.energy()
+    }
+
+    pub fn signal(&self) -> &[f64] {
+        self.signal.values()
+    }
+
+    pub fn at(&self, field: f64) -> f64 {
+        self.signal.at(field)
+    }
+
+    /// Fields where the signal changes sign, linearly interpolated.
+    pub fn zero_crossings(&self) -> Vec<f64> {
+        self.field()
+            .windows(2)
+            .zip(self.signal().windows(2))
+            .filter(|(_, s)| s[0] == 0.0 || s[0].signum() != s[1].signum())
+            .map(|(h, s)| {
+                if s[0] == s[1] {
+                    h[0]
+                } else {
+                    h[0] - s[0] * (h[1] - h[0]) / (s[1] - s[0])
+                }
+            })
+            .collect()
+    }
+}
+
+/// Matches every point of `a` with a point of `b` at the same field, in
+/// the order of `a`. A field visited several times, once per sweep
+/// direction, pairs its visits in order. Fields count as equal within half
+/// the smallest field step of `a`, which absorbs readback jitter without
+/// reaching the neighbouring setpoint.
+fn pair(a: &[(f64, f64)], b: &[(f64, f64)], what: &str) -> Result<Vec<(f64, f64, f64)>, Error> {
+    let step = a
+        .windows(2)
+        .map(|w| (w[1].0 - w[0].0).abs())
+        .filter(|&d| d > 0.0)
+        .fold(f64::INFINITY, f64::min);
+    let tolerance = if step.is_finite() { 0.5 * step } else { 0.0 };
+    let mut used = vec![false; b.len()];
+    let mut pairs = Vec::with_capacity(a.len());
+    for &(h, x) in a {
+        let j = (0..b.len())
+            .find(|&j| !used[j] && (b[j].0 - h).abs() <= tolerance)
+            .ok_or_else(|| error!("No {} at field {}", what, h))?;
+        used[j] = true;
+        pairs.push((h, x, b[j].1));
+    }
+    if let Some(j) = used.iter().position(|&u| !u) {
+        bail!("Unmatched {} at field {}", what, b[j].0);
+    }
+    Ok(pairs)
+}
+
+/// Element-specific hysteresis loop measured at fixed photon energy while
+/// sweeping the magnetic field.
+#[derive(Debug, Clone)]
+pub struct Hysteresis {
+    descending: Branch,
+    ascending: Branch,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LoopParameters {
+    /// Half width of the loop at zero signal.
+    pub coercive_field: f64,
+    /// Horizontal shift of the loop centre.
+    pub exchange_bias: f64,
+    pub remanence: f64,
+    pub saturation: f64,
+}
+
+impl Hysteresis {
+    /// Reads four columns: field, photon energy, intensity and helicity,
+    /// of which only the sign counts and zero is an error. Points taken at
+    /// `edge` are divided by the points taken at the pre-edge `reference`
+    /// energy, if given, at the same field. With both helicities present
+    /// the signal is the asymmetry (I+ − I−)/(I+ + I−) of the points at the
+    /// same field; otherwise it is the normalized intensity itself.
+    pub fn new<R>(mut input: R, edge: f64, reference: Option<f64>) -> Result<Hysteresis, Error>
+    where
+        R: BufRead,
+    {
+        let mut points = Vec::new();
+        let mut buffer = String::new();
+        let mut n = 0;
+        while input.read_line(&mut buffer)? > 0 {
+            n += 1;
+            let line = buffer.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                let values = line
+                    .split_whitespace()
+                    .map(|s| s.parse::<f64>())
+                    .collect::<Result<Vec<_>, _>>()?;
+                if values.len() < 4 {
+                    bail!(
+                        "Expected field, energy, intensity and helicity columns: {:?}",
+                        line
+                    );
+                }
+                if values[3] == 0.0 || values[3].is_nan() {
+                    bail!(
+                        "line {}: helicity must be positive or negative, found {}",
+                        n,
+                        values[3]
+                    );
+                }
+                points.push((values[0], values[1], values[2], values[3].signum()));
+            }
+            buffer.clear();
+        }
+
+        let is_edge = |energy: f64| match reference {
+            Some(reference) => (energy - edge).abs() < (energy - reference).abs(),
+            None => true,
+        };
+        let normalized = |helicity: f64| -> Result<Vec<(f64, f64)>, Error> {
+            let select = |at_edge: bool| {
+                points
+                    .iter()
+                    .filter(|p| p.3 == helicity && is_edge(p.1) == at_edge)
+                    .map(|p| (p.0, p.2))
+                    .collect::<Vec<_>>()
+            };
+            let on = select(true);
+            if reference.is_none() {
+                return Ok(on);
+            }
+            let off = select(false);
+            let what = format!("reference point for helicity {}", helicity);
+            Ok(pair(&on, &off, &what)?
+                .into_iter()
+                .map(|(h, a, b)| (h, a / b))
+                .collect())
+        };
+
+        let plus = normalized(1.0)?;
+        let minus = normalized(-1.0)?;
+        let sweep = match (plus.is_empty(), minus.is_empty()) {
+            (true, true) => bail!("No points at the edge energy {}", edge),
+            (false, true) => plus,
+            (true, false) => minus,
+            (false, false) => pair(&plus, &minus, "negative helicity point")?
+                .into_iter()
+                .map(|(h, p, m)| (h, (p - m) / (p + m)))
+                .collect(),
+        };
+
+        Hysteresis::from_sweep(&sweep)
+    }
+
+    /// Splits a sequence of `(field, signal)` points into descending and
+    /// ascending branches by the direction of the field change. A half loop,
+    /// which leaves one branch with fewer than two points, is an error.
+    pub fn from_sweep(sweep: &[(f64, f64)]) -> Result<Hysteresis, Error> {
+        if let Some(p) = sweep.iter().find(|p| !p.0.is_finite() || !p.1.is_finite()) {
+            bail!("Non-finite point ({}, {})", p.0, p.1);
+        }
+        let mut descending = Vec::new();
+        let mut ascending = Vec::new();
+        let mut falling = sweep.len() > 1 && sweep[1].0 < sweep[0].0;
+        for (i, &point) in sweep.iter().enumerate() {
+            if i > 0 && sweep[i].0 != sweep[i - 1].0 {
+                falling = sweep[i].0 < sweep[i - 1].0;
+            }
+            if falling {
+                descending.push(point);
+            } else {
+                ascending.push(point);
+            }
+        }
+        for (name, branch) in &[("descending", &descending), ("ascending", &ascending)] {
+            if branch.len() < 2 {
+                bail!(
+                    "The {} branch has {} points; a loop needs both sweep directions",
+                    name,
+                    branch.len()
+                );
+            }
+        }
+        Ok(Hysteresis {
+            descending: Branch::from_points(&descending)?,
+            ascending: Branch::from_points(&ascending)?,
+        })
+    }
+
+    /// Sweep from positive to negative field.
+    pub fn descending(&self) -> &Branch {
+        &self.descending
+    }
+
+    /// Sweep from negative to positive field.
+    pub fn ascending(&self) -> &Branch {
+        &self.ascending
+    }
+
+    /// Odd part of the loop, M↓(H) = (M↓(H) − M↑(−H)) / 2, which removes
+    /// field-even backgrounds and offsets.
+    pub fn antisymmetrized(&self) -> Result<Hysteresis, Error> {
+        self.combined(-1.0)
+    }
+
+    /// Even part of the loop, (M↓(H) + M↑(−H)) / 2: the non-magnetic
+    /// background that [`antisymmetrized`](Self::antisymmetrized) removes.
+    pub fn symmetrized(&self) -> Result<Hysteresis, Error> {
+        self.combined(1.0)
+    }
+
+    /// (M↓(H) + `sign`·M↑(−H)) / 2 on the descending fields, mirrored onto
+    /// the ascending branch with the same parity.
+    fn combined(&self, sign: f64) -> Result<Hysteresis, Error> {
+        let descending = self
+            .descending
+            .field()
+            .iter()
+            .zip(self.descending.signal())
+            .map(|(&h, &m)| (h, 0.5 * (m + sign * self.ascending.at(-h))))
+            .collect::<Vec<_>>();
+        let ascending = descending
+            .iter()
+            .map(|&(h, m)| (-h, sign * m))
+            .collect::<Vec<_>>();
+        Ok(Hysteresis {
+            descending: Branch::from_points(&descending)?,
+            ascending: Branch::from_points(&ascending)?,
+        })
+    }
+
+    /// Coercive field, exchange bias, remanence and saturation; saturation
+    /// is averaged over the outer tenth of the field range.
+    pub fn parameters(&self) -> Result<LoopParameters, Error> {
+        let nearest_zero = |crossings: Vec<f64>| {
+            crossings
+                .into_iter()
+                .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+        };
+        let down = nearest_zero(self.descending.zero_crossings())
+            .ok_or_else(|| error!("Descending branch does not cross zero"))?;
+        let up = nearest_zero(self.ascending.zero_crossings())
+            .ok_or_else(|| error!("Ascending branch does not cross zero"))?;
+
+        let h_max = self
+            .descending
+            .field()
+            .iter()
+            .chain(self.ascending.field())
+            .fold(0.0f64, |acc, h| acc.max(h.abs()));
+        let outer = |branch: &Branch, positive: bool| {
+            let (lo, hi) = if positive {
+                (0.9 * h_max, h_max)
+            } else {
+                (-h_max, -0.9 * h_max)
+            };
+            math::mean_in(branch.field(), branch.signal(), lo, hi)
+        };
+        let top = outer(&self.descending, true).or_else(|| outer(&self.ascending, true));
+        let bottom = outer(&self.ascending, false).or_else(|| outer(&self.descending, false));
+        let saturation = match (top, bottom) {
+            (Some(top), Some(bottom)) => 0.5 * (top - bottom),
+            _ => bail!("No points in the saturation region"),
+        };
+
+        Ok(LoopParameters {
+            coercive_field: 0.5 * (up - down),
+            exchange_bias: 0.5 * (up + down),
+            remanence: 0.5 * (self.descending.at(0.0) - self.ascending.at(0.0)),
+            saturation,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Square-ish loop of coercive field 1 swept from +3 to −3 and back.
+    fn sweep() -> Vec<(f64, f64)> {
+        let down = (0..=6)
+            .map(|i| 3.0 - i as f64)
+            .map(|h| (h, (h + 1.0).tanh()));
+        let up = (0..=6)
+            .map(|i| -3.0 + i as f64)
+            .map(|h| (h, (h - 1.0).tanh()));
+        down.chain(up.skip(1)).collect()
+    }
+
+    #[test]
+    fn full_loop_has_symmetric_coercivity() {
+        let parameters = Hysteresis::from_sweep(&sweep())
+            .unwrap()
+            .parameters()
+            .unwrap();
+        assert!((parameters.coercive_field - 1.0).abs() < 1e-9);
+        assert!(parameters.exchange_bias.abs() < 1e-9);
+    }
+
+    #[test]
+    fn half_loop_is_an_error() {
+        let half = sweep().into_iter().take(7).collect::<Vec<_>>();
+        assert!(Hysteresis::from_sweep(&half).is_err());
+        assert!(Hysteresis::from_sweep(&[]).is_err());
+    }
+
+    /// Edge and reference points of both helicities for [`sweep`], in
+    /// `order` per field; the reference intensity is 2 and I± = 1 ± m.
+    fn scan(order: &[(f64, f64)]) -> String {
+        let mut lines = String::new();
+        for (h, m) in sweep() {
+            for &(energy, helicity) in order {
+                let intensity = if energy == 780.0 {
+                    1.0 + helicity * m
+                } else {
+                    2.0
+                };
+                lines += &format!("{} {} {} {}\n", h, energy, intensity, helicity);
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn interleaved_points_pair_by_field() {
+        let expected = Hysteresis::from_sweep(&sweep()).unwrap();
+        let order = [(770.0, -1.0), (780.0, 1.0), (780.0, -1.0), (770.0, 1.0)];
+        // Move the reference points of the first field to zero field: the
+        // counts still agree, but index pairing would shift every pair.
+        let input = scan(&order)
+            .replacen("3 770 2 -1\n", "", 1)
+            .replacen("3 770 2 1\n", "", 1)
+            + "0 770 2 -1\n0 770 2 1\n";
+        assert!(Hysteresis::new(input.as_bytes(), 780.0, Some(770.0)).is_err());
+
+        let loop_ = Hysteresis::new(scan(&order).as_bytes(), 780.0, Some(770.0)).unwrap();
+        for (a, b) in [
+            (loop_.descending(), expected.descending()),
+            (loop_.ascending(), expected.ascending()),
+        ]
+        .iter()
+        {
+            assert_eq!(a.field(), b.field());
+            for (x, y) in a.signal().iter().zip(b.signal()) {
+                assert!((x - y).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn helicity_must_have_a_sign() {
+        let input = scan(&[(780.0, 1.0), (780.0, -1.0)]);
+        assert!(Hysteresis::new(input.as_bytes(), 780.0, None).is_ok());
+        let input = input.replace(" -1\n", " 0\n");
+        assert!(Hysteresis::new(input.as_bytes(), 780.0, None).is_err());
+    }
+}
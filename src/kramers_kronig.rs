@@ -0,0 +1,308 @@
+use std::f64::consts::PI;
+
+use crate::elem::{Database, Element};
+use crate::math;
+use crate::spectrum::Spectrum;
+use crate::xmcd::Xmcd;
+use crate::Error;
+
+/// Classical electron radius, cm.
+const R_E: f64 = 2.817_940_326_2e-13;
+/// Planck constant times speed of light, eV·cm.
+const HC: f64 = 1.239_841_984e-4;
+/// Avogadro constant, 1/mol.
+const AVOGADRO: f64 = 6.022_140_76e23;
+/// Points per decade of the tabulated grid outside the measured range.
+const POINTS_PER_DECADE: f64 = 200.0;
+
+/// Atomic scattering factors and optical constants on the measured energy
+/// grid. `n = 1 − δ + iβ`; the magnetic parts come from the XMCD.
+#[derive(Debug, Clone)]
+pub struct OpticalConstants {
+    f1: Spectrum,
+    f2: Spectrum,
+    f1_mag: Spectrum,
+    f2_mag: Spectrum,
+    delta: Spectrum,
+    beta: Spectrum,
+    delta_mag: Spectrum,
+    beta_mag: Spectrum,
+}
+
+impl OpticalConstants {
+    pub fn energy(&self) -> &[f64] {
+        self.f1.energy()
+    }
+
+    pub fn f1(&self) -> &Spectrum {
+        &self.f1
+    }
+
+    pub fn f2(&self) -> &Spectrum {
+        &self.f2
+    }
+
+    pub fn f1_mag(&self) -> &Spectrum {
+        &self.f1_mag
+    }
+
+    pub fn f2_mag(&self) -> &Spectrum {
+        &self.f2_mag
+    }
+
+    pub fn delta(&self) -> &Spectrum {
+        &self.delta
+    }
+
+    pub fn beta(&self) -> &Spectrum {
+        &self.beta
+    }
+
+    pub fn delta_mag(&self) -> &Spectrum {
+        &self.delta_mag
+    }
+
+    pub fn beta_mag(&self) -> &Spectrum {
+        &self.beta_mag
+    }
+}
+
+/// Kramers–Kronig transform of a measured edge spliced onto the tabulated
+/// photoabsorption of `elem.dat`.
+///
+/// The normalized spectrum `m(E)` is put on an absolute scale as
+/// `σ(E) = σbelow(E) + m(E)·Δσ`, where `σbelow` is the cross section
+/// without the edge and `Δσ` the tabulated jump at the end of the measured
+/// range, so that pre- and post-edge join the tables. Outside the measured
+/// range the tables are used as they are. The XMCD is scaled the same way
+/// and transformed on its own, as it vanishes off resonance.
+#[derive(Debug, Clone)]
+pub struct KramersKronig {
+    pub element: String,
+    pub edge: String,
+    /// Density in g/cm³; the elemental density from `elem.dat` if `None`.
+    pub density: Option<f64>,
+}
+
+impl KramersKronig {
+    pub fn new(element: &str, edge: &str) -> KramersKronig {
+        KramersKronig {
+            element: element.to_string(),
+            edge: edge.to_string(),
+            density: None,
+        }
+    }
+
+    pub fn apply(&self, db: &Database, xmcd: &Xmcd) -> Result<OpticalConstants, Error> {
+        self.transform(db, xmcd.xas(), xmcd.xmcd())
+    }
+
+    /// Transforms a normalized absorption spectrum and its dichroism on the
+    /// part of the grid of `xas` covered by `xmcd`.
+    pub fn transform(
+        &self,
+        db: &Database,
+        xas: &Spectrum,
+        xmcd: &Spectrum,
+    ) -> Result<OpticalConstants, Error> {
+        let (xas, xmcd) = xas.matched(xmcd)?;
+        if xas.len() < 2 {
+            bail!("Need at least two points");
+        }
+        let (energy, xas, xmcd) = (xas.energy(), xas.values(), xmcd.values());
+        let element = db.require(&self.element)?;
+        let edge = element
+            .edge(&self.edge)
+            .ok_or_else(|| error!("{} has no {} edge", element.symbol, self.edge))?;
+        let to_f2 = f2_factor(element);
+
+        let (lo, hi) = (energy[0], energy[energy.len() - 1]);
+        if hi <= edge.energy {
+            bail!("Measured range ends below the {} edge", edge.name);
+        }
+        // The spectrum is normalized to the post-edge, so the edge jump is
+        // taken at the end of the measured range.
+        let jump = element.photo(hi) - element.photo_below(edge, hi);
+        let table_lo = element.photo.log_energy[0].exp();
+        let table_hi = element.photo.log_energy[element.photo.log_energy.len() - 1].exp();
+        if lo <= table_lo || hi >= table_hi {
+            bail!("Measured range lies outside the tabulated cross sections");
+        }
+
+        // Logarithmic grid over the tables with the measured points spliced in.
+        let decades = (table_hi / table_lo).log10();
+        let n = (decades * POINTS_PER_DECADE) as usize;
+        let mut grid = (0..=n)
+            .map(|i| table_lo * 10f64.powf(decades * i as f64 / n as f64))
+            .filter(|&e| e < lo || e > hi)
+            .collect::<Vec<_>>();
+        grid.extend_from_slice(energy);
+        grid.sort_by(|a, b| a.total_cmp(b));
+
+        let f2 = grid
+            .iter()
+            .map(|&e| {
+                let sigma = if e < lo || e > hi {
+                    element.photo(e)
+                } else {
+                    element.photo_below(edge, e) + math::interp(energy, xas, e) * jump
+                };
+                sigma * to_f2 * e
+            })
+            .collect::<Vec<_>>();
+        let f2_mag = grid
+            .iter()
+            .map(|&e| {
+                if e < lo || e > hi {
+                    0.0
+                } else {
+                    math::interp(energy, xmcd, e) * jump * to_f2 * e
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let z = element.z as f64;
+        let z_star = z - (z / 82.5).powf(2.37);
+        let start = grid.iter().position(|&e| e >= lo).unwrap();
+        let measured = start..start + energy.len();
+
+        let f1 = measured
+            .clone()
+            .map(|i| z_star + principal_value(&grid, &f2, i))
+            .collect::<Vec<_>>();
+        let f1_mag = measured
+            .clone()
+            .map(|i| principal_value(&grid, &f2_mag, i))
+            .collect::<Vec<_>>();
+        let f2 = f2[measured.clone()].to_vec();
+        let f2_mag = f2_mag[measured].to_vec();
+
+        // δ, β = r_e·λ²·n_a·(f1, f2) / 2π, with n_a atoms per cm³.
+        let density = self.density.unwrap_or(element.density);
+        let n_a = density * AVOGADRO / element.atomic_weight;
+        let scale = |e: f64| R_E * (HC / e).powi(2) * n_a / (2.0 * PI);
+        let optical = |f: &[f64]| {
+            let values = f.iter().zip(energy).map(|(f, &e)| f * scale(e)).collect();
+            Spectrum::new(energy.to_vec(), values)
+        };
+        let spectrum = |f: Vec<f64>| Spectrum::new(energy.to_vec(), f);
+
+        Ok(OpticalConstants {
+            delta: optical(&f1)?,
+            beta: optical(&f2)?,
+            delta_mag: optical(&f1_mag)?,
+            beta_mag: optical(&f2_mag)?,
+            f1: spectrum(f1)?,
+            f2: spectrum(f2)?,
+            f1_mag: spectrum(f1_mag)?,
+            f2_mag: spectrum(f2_mag)?,
+        })
+    }
+}
+
+/// Converts `(μ/ρ)·E` in cm²/g·eV to f2: `f2 = σa / (2·r_e·λ)` with `σa`
+/// the cross section per atom.
+fn f2_factor(element: &Element) -> f64 {
+    element.atomic_weight / AVOGADRO / (2.0 * R_E * HC)
+}
+
+/// `(2/π)·P∫ ε·f2(ε) / (E² − ε²) dε` at `E = grid[k]`. The singular part is
+/// subtracted and integrated analytically; the remainder is smooth and
+/// integrated with the trapezoidal rule.
+fn principal_value(grid: &[f64], f2: &[f64], k: usize) -> f64 {
+    let e = grid[k];
+    let f2e = f2[k];
+    let slope = if k == 0 {
+        (f2[1] - f2[0]) / (grid[1] - grid[0])
+    } else if k == grid.len() - 1 {
+        (f2[k] - f2[k - 1]) / (grid[k] - grid[k - 1])
+    } else {
+        (f2[k + 1] - f2[k - 1]) / (grid[k + 1] - grid[k - 1])
+    };
+
+    let integrand = grid
+        .iter()
+        .zip(f2)
+        .enumerate()
+        .map(|(i, (&x, &f))| {
+            if i == k {
+                -0.5 * slope
+            } else {
+                x * (f - f2e) / (e * e - x * x)
+            }
+        })
+        .collect::<Vec<_>>();
+    let regular = math::trapz(grid, &integrand);
+
+    let (a, b) = (grid[0], grid[grid.len() - 1]);
+    let singular = -0.5 * f2e * ((e * e - b * b) / (e * e - a * a)).abs().ln();
+
+    2.0 / PI * (regular + singular)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn principal_value_inverts_a_lorentz_oscillator() {
+        // χ = 1 / (ω0² − ω² − iγω); with this sign convention the transform
+        // of Im χ is −Re χ.
+        let (w0, gamma) = (10.0, 1.0);
+        let d = |w: f64| (w0 * w0 - w * w).powi(2) + (gamma * w).powi(2);
+        let n = 40_000;
+        let grid = (0..=n)
+            .map(|i| 1e-2 * 10f64.powf(5.0 * i as f64 / n as f64))
+            .collect::<Vec<_>>();
+        let im = grid.iter().map(|&w| gamma * w / d(w)).collect::<Vec<_>>();
+        for (k, &w) in grid
+            .iter()
+            .enumerate()
+            .filter(|(_, &w)| w > 5.0 && w < 15.0)
+        {
+            let re = (w0 * w0 - w * w) / d(w);
+            let pv = principal_value(&grid, &im, k);
+            assert!((pv + re).abs() < 1e-4, "{} {} {}", w, pv, -re);
+        }
+    }
+
+    #[test]
+    fn transform_joins_the_tables() {
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+        let db = Database::load(data.join("elem.dat")).unwrap();
+        let energy = (0..=600)
+            .map(|i| 760.0 + 0.1 * i as f64)
+            .collect::<Vec<_>>();
+        let step = energy
+            .iter()
+            .map(|&e| 0.5 + (e - 778.1).atan() / PI)
+            .collect::<Vec<_>>();
+        // Zero at the start and one at the end of the range, as normalized.
+        let (first, last) = (step[0], step[step.len() - 1]);
+        let xas = Spectrum::new(
+            energy.clone(),
+            step.iter().map(|s| (s - first) / (last - first)).collect(),
+        )
+        .unwrap();
+        let xmcd = xas.map(|_, _| 0.0).unwrap();
+        let constants = KramersKronig::new("Co", "L3")
+            .transform(&db, &xas, &xmcd)
+            .unwrap();
+
+        let co = db.require("Co").unwrap();
+        let tabulated = |e: f64| co.photo(e) * f2_factor(co) * e;
+        let f2 = constants.f2().values();
+        for &(i, e) in &[(0, energy[0]), (f2.len() - 1, energy[energy.len() - 1])] {
+            assert!(
+                (f2[i] / tabulated(e) - 1.0).abs() < 1e-9,
+                "{} {}",
+                f2[i],
+                tabulated(e)
+            );
+        }
+        assert!(constants.f2_mag().values().iter().all(|&f| f == 0.0));
+        assert!(constants.f1().values().iter().all(|f| f.is_finite()));
+    }
+}
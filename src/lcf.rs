@@ -0,0 +1,383 @@
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+use nalgebra::{DMatrix, DVector};
+
+use crate::fit;
+use crate::spectrum::Spectrum;
+use crate::Error;
+
+/// Weight of the extra row that holds the weights to a sum of one in the
+/// non-negative start, relative to the data.
+const SUM_WEIGHT: f64 = 1e3;
+
+/// Normalized reference spectrum.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub name: String,
+    pub spectrum: Spectrum,
+}
+
+impl Reference {
+    pub fn new(name: &str, spectrum: Spectrum) -> Result<Reference, Error> {
+        if spectrum.len() < 2 {
+            bail!("Reference {} needs at least two points", name);
+        }
+        Ok(Reference {
+            name: name.to_string(),
+            spectrum,
+        })
+    }
+
+    /// Two whitespace-separated columns, energy and normalized μ; lines
+    /// starting with `#` are skipped.
+    pub fn from_columns<R>(name: &str, input: R) -> Result<Reference, Error>
+    where
+        R: BufRead,
+    {
+        let mut energy = Vec::new();
+        let mut mu = Vec::new();
+        for (n, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let mut next = || -> Result<f64, Error> {
+                let field = fields
+                    .next()
+                    .ok_or_else(|| error!("{} line {}: expected two columns", name, n + 1))?;
+                Ok(field.parse::<f64>()?)
+            };
+            energy.push(next()?);
+            mu.push(next()?);
+        }
+        let spectrum = Spectrum::new(energy, mu).map_err(|e| error!("{}: {}", name, e))?;
+        Reference::new(name, spectrum)
+    }
+
+    /// Loads a reference named after the file stem.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Reference, Error> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let file = fs::File::open(path)?;
+        Reference::from_columns(&name, io::BufReader::new(file))
+    }
+
+    /// Interpolated reference at `energy`, shifted by `shift` eV.
+    pub fn at(&self, energy: f64, shift: f64) -> f64 {
+        self.spectrum.at(energy - shift)
+    }
+}
+
+/// Linear combination fit of a normalized spectrum to reference spectra.
+///
+/// Non-negative weights are found by NNLS; the components that survive are
+/// then refined by Levenberg–Marquardt, together with one energy shift each
+/// if `shifts` is set, which also yields the uncertainties. Components
+/// whose weight turns negative in the refinement are dropped and the fit
+/// repeated.
+#[derive(Debug, Clone)]
+pub struct Lcf {
+    pub references: Vec<Reference>,
+    pub shifts: bool,
+    /// Constrain the weights to sum to one; the last weight is then
+    /// dependent and its uncertainty ignores correlations.
+    pub sum_to_one: bool,
+    /// Energy range of the fit; the whole spectrum if `None`.
+    pub range: Option<(f64, f64)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LcfResult {
+    pub names: Vec<String>,
+    pub weights: Vec<f64>,
+    pub weight_errors: Vec<f64>,
+    pub shifts: Vec<f64>,
+    pub shift_errors: Vec<f64>,
+    /// `Σ(data − fit)² / Σ data²`.
+    pub r_factor: f64,
+    pub chi2: f64,
+    pub reduced_chi2: f64,
+    pub energy: Vec<f64>,
+    pub fit: Vec<f64>,
+}
+
+impl Lcf {
+    pub fn new(references: Vec<Reference>) -> Lcf {
+        Lcf {
+            references,
+            shifts: false,
+            sum_to_one: false,
+            range: None,
+        }
+    }
+
+    /// Fits `spectrum` with all references.
+    pub fn fit(&self, spectrum: &Spectrum) -> Result<LcfResult, Error> {
+        let all = (0..self.references.len()).collect::<Vec<_>>();
+        self.fit_subset(spectrum, &all)
+    }
+
+    /// Fits every subset of up to `max_components` references and returns
+    /// the results sorted by R-factor, best first. Subsets whose fit fails
+    /// are left out; the first failure is returned only if none succeeds.
+    pub fn rank(
+        &self,
+        spectrum: &Spectrum,
+        max_components: usize,
+    ) -> Result<Vec<LcfResult>, Error> {
+        let n = self.references.len();
+        let mut results: Vec<LcfResult> = Vec::new();
+        let mut failure = None;
+        for k in 1..=max_components.min(n) {
+            let mut subset = (0..k).collect::<Vec<_>>();
+            loop {
+                match self.fit_subset(spectrum, &subset) {
+                    // Subsets that lose a component repeat a smaller one.
+                    Ok(result) => {
+                        if !results.iter().any(|r| r.names == result.names) {
+                            results.push(result);
+                        }
+                    }
+                    Err(e) => {
+                        failure.get_or_insert(e);
+                    }
+                }
+                if !next_combination(&mut subset, n) {
+                    break;
+                }
+            }
+        }
+        if let (true, Some(e)) = (results.is_empty(), failure) {
+            return Err(e);
+        }
+        results.sort_by(|a, b| a.r_factor.total_cmp(&b.r_factor));
+        Ok(results)
+    }
+
+    fn fit_subset(&self, spectrum: &Spectrum, subset: &[usize]) -> Result<LcfResult, Error> {
+        if subset.is_empty() {
+            bail!("Need at least one reference");
+        }
+        let (lo, hi) = self.range.unwrap_or((f64::NEG_INFINITY, f64::INFINITY));
+        let (x, y, _) = spectrum.crop(lo, hi)?.into_parts();
+
+        let mut components = subset.to_vec();
+        loop {
+            let refs = components
+                .iter()
+                .map(|&i| &self.references[i])
+                .collect::<Vec<_>>();
+            let start = self.start(&x, &y, &refs)?;
+            let keep = components
+                .iter()
+                .zip(&start)
+                .filter(|(_, &w)| w > 0.0)
+                .map(|(&i, _)| i)
+                .collect::<Vec<_>>();
+            if keep.is_empty() {
+                bail!("No reference contributes to the fit");
+            }
+            if keep.len() < components.len() {
+                components = keep;
+                continue;
+            }
+
+            let result = self.refine(&x, &y, &refs, &start)?;
+            let negative = result.weights.iter().any(|&w| w < 0.0);
+            if negative && components.len() > 1 {
+                let worst = (0..components.len())
+                    .min_by(|&a, &b| result.weights[a].partial_cmp(&result.weights[b]).unwrap())
+                    .unwrap();
+                components.remove(worst);
+                continue;
+            }
+            return Ok(result);
+        }
+    }
+
+    /// Non-negative weights at zero shift.
+    fn start(&self, x: &[f64], y: &[f64], refs: &[&Reference]) -> Result<Vec<f64>, Error> {
+        let extra = if self.sum_to_one { 1 } else { 0 };
+        let scale = SUM_WEIGHT * (y.iter().map(|v| v * v).sum::<f64>() / y.len() as f64).sqrt();
+        let a = DMatrix::from_fn(x.len() + extra, refs.len(), |i, j| {
+            if i < x.len() {
+                refs[j].at(x[i], 0.0)
+            } else {
+                scale
+            }
+        });
+        let b = DVector::from_fn(
+            x.len() + extra,
+            |i, _| if i < x.len() { y[i] } else { scale },
+        );
+        fit::nnls(&a, &b)
+    }
+
+    fn refine(
+        &self,
+        x: &[f64],
+        y: &[f64],
+        refs: &[&Reference],
+        start: &[f64],
+    ) -> Result<LcfResult, Error> {
+        let m = refs.len();
+        // Free weights, then shifts; with the sum constraint the last weight
+        // is one minus the others.
+        let free = if self.sum_to_one { m - 1 } else { m };
+        let unpack = |p: &[f64]| {
+            let mut weights = p[..free].to_vec();
+            if self.sum_to_one {
+                weights.push(1.0 - weights.iter().sum::<f64>());
+            }
+            let shifts = if self.shifts {
+                p[free..].to_vec()
+            } else {
+                vec![0.0; m]
+            };
+            (weights, shifts)
+        };
+        let model = |p: &[f64], e: f64| {
+            let (weights, shifts) = unpack(p);
+            refs.iter()
+                .zip(weights.iter().zip(&shifts))
+                .map(|(r, (w, s))| w * r.at(e, *s))
+                .sum::<f64>()
+        };
+
+        let mut p0 = start[..free].to_vec();
+        if self.shifts {
+            p0.extend(vec![0.0; m]);
+        }
+        let (params, errors, reduced_chi2) = if p0.is_empty() {
+            let p = Vec::new();
+            let chi2 = x
+                .iter()
+                .zip(y)
+                .map(|(&e, y)| (y - model(&p, e)).powi(2))
+                .sum::<f64>();
+            (p, Vec::new(), chi2 / x.len() as f64)
+        } else {
+            let fit = fit::levenberg_marquardt(model, x, y, &p0)?;
+            (fit.params, fit.errors, fit.reduced_chi2)
+        };
+
+        let (weights, shifts) = unpack(&params);
+        let mut weight_errors = errors[..free].to_vec();
+        if self.sum_to_one {
+            weight_errors.push(weight_errors.iter().map(|e| e * e).sum::<f64>().sqrt());
+        }
+        let shift_errors = if self.shifts {
+            errors[free..].to_vec()
+        } else {
+            vec![0.0; m]
+        };
+        let fitted = x.iter().map(|&e| model(&params, e)).collect::<Vec<_>>();
+        let chi2 = y
+            .iter()
+            .zip(&fitted)
+            .map(|(y, f)| (y - f).powi(2))
+            .sum::<f64>();
+        let norm = y.iter().map(|y| y * y).sum::<f64>();
+
+        Ok(LcfResult {
+            names: refs.iter().map(|r| r.name.clone()).collect(),
+            weights,
+            weight_errors,
+            shifts,
+            shift_errors,
+            r_factor: chi2 / norm,
+            chi2,
+            reduced_chi2,
+            energy: x.to_vec(),
+            fit: fitted,
+        })
+    }
+}
+
+impl std::fmt::Display for LcfResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for i in 0..self.names.len() {
+            writeln!(
+                f,
+                "{:<16} w = {:.4} ± {:.4}  ΔE = {:.3} ± {:.3} eV",
+                self.names[i],
+                self.weights[i],
+                self.weight_errors[i],
+                self.shifts[i],
+                self.shift_errors[i]
+            )?;
+        }
+        write!(
+            f,
+            "R = {:.6}  χ² = {:.6}  reduced χ² = {:.3e}",
+            self.r_factor, self.chi2, self.reduced_chi2
+        )
+    }
+}
+
+/// Advances `indices`, increasing and below `n`, to the next combination in
+/// lexicographic order; false after the last.
+fn next_combination(indices: &mut [usize], n: usize) -> bool {
+    let k = indices.len();
+    let i = match (0..k).rev().find(|&i| indices[i] < n - k + i) {
+        Some(i) => i,
+        None => return false,
+    };
+    indices[i] += 1;
+    for j in i + 1..k {
+        indices[j] = indices[j - 1] + 1;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combinations_of_many_references() {
+        let mut indices = vec![0, 1];
+        let mut count = 1;
+        while next_combination(&mut indices, 70) {
+            count += 1;
+        }
+        assert_eq!(count, 70 * 69 / 2);
+        assert_eq!(indices, vec![68, 69]);
+    }
+
+    #[test]
+    fn rank_skips_failing_subsets() {
+        let energy = (0..50).map(|i| 700.0 + i as f64).collect::<Vec<_>>();
+        let reference = |f: &dyn Fn(f64) -> f64| {
+            Spectrum::new(energy.clone(), energy.iter().map(|&e| f(e)).collect()).unwrap()
+        };
+        let a = reference(&|e| 1.0 / (1.0 + (-(e - 720.0)).exp()));
+        let b = reference(&|e| (-((e - 725.0) / 3.0).powi(2)).exp());
+        let zero = reference(&|_| 0.0);
+        let mix = Spectrum::new(
+            energy.clone(),
+            a.values()
+                .iter()
+                .zip(b.values())
+                .map(|(a, b)| 0.7 * a + 0.3 * b)
+                .collect(),
+        )
+        .unwrap();
+        let mut references = vec![Reference::new("zero", zero).unwrap()];
+        for i in 0..70 {
+            let spectrum = if i == 10 { b.clone() } else { a.clone() };
+            references.push(Reference::new(&format!("r{}", i), spectrum).unwrap());
+        }
+        let results = Lcf::new(references).rank(&mix, 2).unwrap();
+        assert_eq!(results[0].names.len(), 2);
+        assert!(results[0].names.contains(&"r10".to_string()));
+        assert!(results[0].r_factor < 1e-8);
+    }
+}
@@ -1,32 +1,72 @@
-pub mod xas;
-
 #[macro_use]
 mod macros;
 
+pub mod columns;
+pub mod config;
+pub mod delay;
+pub mod elem;
+pub mod exafs;
+pub mod export;
+pub mod factor;
+pub mod fit;
+pub mod hysteresis;
+pub mod kramers_kronig;
+pub mod lcf;
+mod math;
+pub mod montecarlo;
+pub mod pipeline;
+pub mod plot;
+pub mod recipe;
+pub mod selfabs;
+pub mod series;
+pub mod spec;
+pub mod spectrum;
+pub mod sumrules;
+pub mod synthetic;
+pub mod tey;
+pub mod xas;
+pub mod xdi;
+pub mod xmcd;
+pub mod xmld;
+
 pub use self::error::Error;
 pub use self::reader::Reader;
 
+/// Processing mode of the former single-command interface.
+#[deprecated(note = "use the xas, xmcd, xmld and `sumrules --errors` subcommands; \
+            `xmld::LinearDichroism` tells Xmld from Xnld")]
 #[derive(Debug)]
 pub enum Mode {
     Xas,
     Xmcd,
+    Xmld,
+    Xnld,
+    Errors,
 }
 
+#[allow(deprecated)]
 impl std::fmt::Display for Mode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Xas => write!(f, "xas"),
             Self::Xmcd => write!(f, "xmcd"),
+            Self::Xmld => write!(f, "xmld"),
+            Self::Xnld => write!(f, "xnld"),
+            Self::Errors => write!(f, "errors"),
         }
     }
 }
 
+#[allow(deprecated)]
 impl std::str::FromStr for Mode {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = match s {
             "xas" | "XAS" | "Xas" => Mode::Xas,
             "xmcd" | "XMCD" | "Xmcd" => Mode::Xmcd,
+            "xmld" | "XMLD" | "Xmld" => Mode::Xmld,
+            "xnld" | "XNLD" | "Xnld" => Mode::Xnld,
+            "errors" | "ERRORS" | "Errors" => Mode::Errors,
             _ => bail!("Incorrect mode"),
         };
         Ok(s)
@@ -41,6 +81,7 @@ mod error {
         Custom(String),
         Io(io::Error),
         Parse(std::num::ParseIntError),
+        ParseFloat(std::num::ParseFloatError),
     }
 
     impl fmt::Display for Error {
@@ -49,6 +90,7 @@ mod error {
                 Self::Custom(s) => write!(f, "{}", s),
                 Self::Io(s) => write!(f, "{}", s),
                 Self::Parse(s) => write!(f, "{}", s),
+                Self::ParseFloat(s) => write!(f, "{}", s),
             }
         }
     }
@@ -65,6 +107,12 @@ mod error {
         }
     }
 
+    impl From<std::num::ParseFloatError> for Error {
+        fn from(e: std::num::ParseFloatError) -> Self {
+            Self::ParseFloat(e)
+        }
+    }
+
     impl error::Error for Error {}
 }
 mod reader {
@@ -1,21 +1,410 @@
+use std::ffi::OsStr;
 use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use xmcd_rs::Reader;
-use xmcd_rs::{Error, Mode};
+use xmcd_rs::columns::Columns;
+use xmcd_rs::config::{ElementConfig, Ini};
+use xmcd_rs::elem::Database;
+use xmcd_rs::export::{Export, Format};
+use xmcd_rs::montecarlo::{Distribution, Jitter, MomentErrors};
+use xmcd_rs::pipeline::Pipeline;
+use xmcd_rs::plot::{Backend, PlotOptions};
+use xmcd_rs::recipe::{Recipe, Source};
+use xmcd_rs::series::Series;
+use xmcd_rs::spec::{self, Selection, SpecFile};
+use xmcd_rs::spectrum::Spectrum;
+use xmcd_rs::sumrules::SumRules;
+use xmcd_rs::synthetic::{LineShape, Synthetic};
+use xmcd_rs::xas::Xas;
+use xmcd_rs::xmcd::Xmcd;
+use xmcd_rs::xmld::{LinearDichroism, Xmld};
+use xmcd_rs::{bail, error, Error, Reader};
 
 use structopt::StructOpt;
+
+/// Processing of X-ray absorption and dichroism spectra. Every command
+/// writes whitespace-separated columns or `key value` lines, with `#`
+/// header lines, to stdout.
 #[derive(Debug, StructOpt)]
-struct Opt {
-    /// Simulation mode.
-    mode: Mode,
+enum Command {
+    /// Normalize one scan of energy, I0 and I1 columns
+    Xas {
+        #[structopt(flatten)]
+        input: Input,
+        #[structopt(flatten)]
+        element: ElementOpt,
+        #[structopt(flatten)]
+        plot: PlotOpt,
+        #[structopt(flatten)]
+        format: FormatOpt,
+    },
+    /// Process a helicity pair, or a manifest of pairs into a series table
+    Xmcd {
+        #[structopt(flatten)]
+        input: PairInput,
+        /// Manifest of `value path` lines; writes one table row per pair
+        #[structopt(long, conflicts_with_all = &["input", "plus", "minus"])]
+        manifest: Option<PathBuf>,
+        /// Name of the manifest parameter
+        #[structopt(long, default_value = "value")]
+        parameter: String,
+        #[structopt(flatten)]
+        element: ElementOpt,
+        #[structopt(flatten)]
+        geometry: Geometry,
+        #[structopt(flatten)]
+        plot: PlotOpt,
+        #[structopt(flatten)]
+        format: FormatOpt,
+    },
+    /// Report the sum-rule moments of a helicity pair
+    Sumrules {
+        #[structopt(flatten)]
+        input: PairInput,
+        #[structopt(flatten)]
+        element: ElementOpt,
+        #[structopt(flatten)]
+        geometry: Geometry,
+        /// Add Monte Carlo errors with the `jitter.*` keys of the config
+        #[structopt(long)]
+        errors: bool,
+        /// Random seed of the Monte Carlo error analysis
+        #[structopt(long, default_value = "0")]
+        seed: u64,
+    },
+    /// Generate a helicity pair with known moments, as read by the xmcd and
+    /// sumrules commands
+    Synth {
+        #[structopt(flatten)]
+        element: ElementOpt,
+        #[structopt(flatten)]
+        geometry: Geometry,
+        /// Path to the elem.dat database, for the edge energies
+        #[structopt(long, default_value = "data/elem.dat")]
+        elem: PathBuf,
+        /// Orbital moment in μB
+        #[structopt(long, default_value = "0.15")]
+        m_orb: f64,
+        /// Effective spin moment in μB
+        #[structopt(long, default_value = "1.6")]
+        m_spin: f64,
+        /// Lorentzian half width of the white lines in eV
+        #[structopt(long, default_value = "1")]
+        width: f64,
+        /// Gaussian σ in eV; gives pseudo-Voigt instead of Lorentzian lines
+        #[structopt(long)]
+        sigma: Option<f64>,
+        /// Area of both white lines, in edge jumps × eV
+        #[structopt(long, default_value = "10")]
+        white_line: f64,
+        /// Absorption below the edge, in edge jumps
+        #[structopt(long, default_value = "0.5")]
+        pre_edge: f64,
+        /// Expected counts at unit absorption, for Poisson noise; noiseless
+        /// if not supplied
+        #[structopt(long)]
+        counts: Option<f64>,
+        /// Random seed of the noise
+        #[structopt(long, default_value = "0")]
+        seed: u64,
+    },
+    /// Linear dichroism of energy, μH and μV columns, or natural linear
+    /// dichroism of two LH scans at different angles
+    Xmld {
+        #[structopt(flatten)]
+        input: Input,
+        #[structopt(flatten)]
+        element: ElementOpt,
+        /// Angle of incidence in degrees from the surface normal
+        #[structopt(short = "a", long, default_value = "0")]
+        angle_from_normal: f64,
+        /// Natural (XNLD) instead of magnetic linear dichroism
+        #[structopt(long, conflicts_with = "reference")]
+        natural: bool,
+        /// LH scan (energy, I0, I1) at --reference-angle-from-normal; the
+        /// input is then an LH scan of the same kind at --angle-from-normal,
+        /// and the natural dichroism is their difference over the change in
+        /// projection
+        #[structopt(long, requires = "reference-angle-from-normal")]
+        reference: Option<PathBuf>,
+        /// Angle of incidence of the --reference scan, in degrees from the
+        /// surface normal
+        #[structopt(long, requires = "reference")]
+        reference_angle_from_normal: Option<f64>,
+        #[structopt(flatten)]
+        plot: PlotOpt,
+        #[structopt(flatten)]
+        format: FormatOpt,
+    },
+    /// Show the elem.dat data of an element
+    Info {
+        /// Element symbol
+        symbol: String,
+        /// Path to the elem.dat database
+        #[structopt(long, default_value = "data/elem.dat")]
+        elem: PathBuf,
+        /// Energies (eV) at which to list cross sections
+        #[structopt(long)]
+        energy: Vec<f64>,
+    },
+    /// Convert tab-, comma- or whitespace-separated columns with text
+    /// headers into the space-separated format read by the other commands
+    Convert {
+        #[structopt(flatten)]
+        input: Input,
+        /// Output file; stdout if not supplied
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+        /// Zero-based indices of the columns to keep, e.g. 0,1,2
+        #[structopt(long, use_delimiter = true)]
+        columns: Vec<usize>,
+    },
+    /// Process every helicity-pair file of a directory, or merged SPEC
+    /// scans, into a table of moments
+    Batch {
+        /// Directories of three-column (energy, μ+, μ−) files, such files,
+        /// or SPEC scans to merge into one pair, as `file.spec:12-18`
+        #[structopt(required = true)]
+        inputs: Vec<PathBuf>,
+        /// Only files with this extension
+        #[structopt(long)]
+        extension: Option<String>,
+        /// Energy, μ+ and μ− columns of SPEC scans, by `#L` label or
+        /// zero-based index
+        #[structopt(long, use_delimiter = true, default_value = "0,1,2")]
+        spec_columns: Vec<String>,
+        /// Directory for the processed spectra, under the same file names
+        #[structopt(short, long)]
+        output: Option<PathBuf>,
+        #[structopt(flatten)]
+        element: ElementOpt,
+        #[structopt(flatten)]
+        geometry: Geometry,
+    },
+    /// List the scans of a SPEC file, or write selected scans as columns
+    Spec {
+        /// SPEC file, or `file.spec:12-18` to write those scans averaged
+        /// on the energy grid of the first
+        input: String,
+        /// Energy column of the selected scans, by `#L` label or
+        /// zero-based index
+        #[structopt(long, default_value = "0")]
+        energy: String,
+    },
+    /// Run the analysis described by a TOML recipe
+    Run {
+        /// Path to the recipe
+        recipe: PathBuf,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct Input {
     /// Optional path to input file; if not supplied will read from stdin
     input: Option<PathBuf>,
 }
 
+#[derive(Debug, StructOpt)]
+struct PairInput {
+    /// Optional path to a three-column (energy, μ+, μ−) file; if not
+    /// supplied will read from stdin
+    input: Option<PathBuf>,
+    /// Scan of the positive helicity (energy, I0, I1), used with --minus
+    #[structopt(long, requires = "minus", conflicts_with = "input")]
+    plus: Option<PathBuf>,
+    /// Scan of the negative helicity (energy, I0, I1), used with --plus
+    #[structopt(long, requires = "plus")]
+    minus: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+struct ElementOpt {
+    /// Element section of the config file
+    #[structopt(short, long, default_value = "Fe")]
+    element: String,
+    /// Path to the element config file
+    #[structopt(short, long, default_value = "data/element.ini")]
+    config: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct Geometry {
+    /// Angle in degrees between beam and magnetization; overrides the
+    /// config value
+    #[structopt(short, long)]
+    angle: Option<f64>,
+    /// Degree of circular polarization; overrides the config value
+    #[structopt(long)]
+    pc: Option<f64>,
+}
+
+#[derive(Debug, StructOpt)]
+struct FormatOpt {
+    /// Write csv, json or xdi with element, edge, E0, provenance and units
+    /// instead of plain columns
+    #[structopt(long)]
+    format: Option<Format>,
+}
+
+#[derive(Debug, StructOpt)]
+struct PlotOpt {
+    /// Render the figure into this file; the extension picks PNG, SVG or
+    /// PDF
+    #[structopt(long)]
+    plot: Option<PathBuf>,
+    /// Skip the figure even if --plot is given
+    #[structopt(long)]
+    no_plot: bool,
+    /// Figure size in pixels
+    #[structopt(long, default_value = "1200x800", parse(try_from_str = parse_size))]
+    plot_size: (u32, u32),
+    /// Plot backend: native, or gnuplot which also writes PDF
+    #[structopt(long, default_value = "native")]
+    plot_backend: Backend,
+}
+
+impl PlotOpt {
+    fn options(&self) -> Result<Option<PlotOptions>, Error> {
+        match &self.plot {
+            Some(path) if !self.no_plot => {
+                let (width, height) = self.plot_size;
+                let options = PlotOptions::new(path)?
+                    .size(width, height)
+                    .backend(self.plot_backend);
+                Ok(Some(options))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Parses `WIDTHxHEIGHT`.
+fn parse_size(s: &str) -> Result<(u32, u32), Error> {
+    let mut parts = s.splitn(2, 'x');
+    match (parts.next(), parts.next()) {
+        (Some(width), Some(height)) => Ok((width.trim().parse()?, height.trim().parse()?)),
+        _ => bail!("Expected WIDTHxHEIGHT, got {:?}", s),
+    }
+}
+
+impl ElementOpt {
+    fn load(&self, geometry: &Geometry) -> Result<ElementConfig, Error> {
+        self.load_from(&Ini::load(&self.config)?, geometry)
+    }
+
+    fn load_from(&self, ini: &Ini, geometry: &Geometry) -> Result<ElementConfig, Error> {
+        let mut config = ElementConfig::new(ini, &self.element)?;
+        if let Some(pc) = geometry.pc {
+            config.pc = pc;
+        }
+        if let Some(angle) = geometry.angle {
+            config.angle = angle;
+        }
+        Ok(config)
+    }
+}
+
+fn open<'a>(path: &Option<PathBuf>, stdin: &'a io::Stdin) -> Result<Reader<'a>, Error> {
+    Ok(match path {
+        Some(path) => Reader::File(io::BufReader::new(open_file(path)?)),
+        None => Reader::Stdin(stdin.lock()),
+    })
+}
+
+fn create(path: &Path) -> Result<io::BufWriter<fs::File>, Error> {
+    let file = fs::File::create(path).map_err(|e| error!("{}: {}", path.display(), e))?;
+    Ok(io::BufWriter::new(file))
+}
+
+fn open_file(path: &Path) -> Result<fs::File, Error> {
+    fs::File::open(path).map_err(|e| Error::Custom(format!("{}: {}", path.display(), e)))
+}
+
+/// One row of the batch table.
+enum BatchItem {
+    /// Three-column file.
+    File(PathBuf),
+    /// SPEC scans merged into one pair.
+    Spec(Selection),
+}
+
+impl BatchItem {
+    /// File name, with the scan numbers of a selection.
+    fn name(&self) -> String {
+        match self {
+            BatchItem::File(path) => path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            BatchItem::Spec(selection) => format!(
+                "{}:{}",
+                selection
+                    .path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy(),
+                spec::format_ranges(&selection.ranges)
+            ),
+        }
+    }
+
+    /// `columns` name the energy, μ+ and μ− columns of SPEC scans.
+    fn load(&self, columns: &[String], config: &ElementConfig) -> Result<Xmcd, Error> {
+        match self {
+            BatchItem::File(path) => {
+                Xmcd::from_columns(io::BufReader::new(open_file(path)?), config)
+            }
+            BatchItem::Spec(selection) => {
+                let table = selection.load(&columns[0])?;
+                let column = |key: &str| table.index(key).map(|i| table.data[i].clone());
+                let energy = column(&columns[0])?;
+                let plus = Spectrum::new(energy.clone(), column(&columns[1])?)?;
+                let minus = Spectrum::new(energy, column(&columns[2])?)?;
+                Xmcd::new(&plus, &minus, config)
+            }
+        }
+    }
+}
+
+impl PairInput {
+    /// μ+ and μ−, from two scans or from a three-column file.
+    fn load(&self, stdin: &io::Stdin) -> Result<(Spectrum, Spectrum), Error> {
+        match (&self.plus, &self.minus) {
+            (Some(plus), Some(minus)) => {
+                let load = |path: &Path| -> Result<Spectrum, Error> {
+                    let xas = Xas::new(io::BufReader::new(open_file(path)?))?;
+                    Ok(xas
+                        .spectrum
+                        .with_metadata("Scan.file", &path.display().to_string()))
+                };
+                Ok((load(plus)?, load(minus)?))
+            }
+            _ => {
+                let (plus, minus) = Xas::load_pair(open(&self.input, stdin)?)?;
+                Ok(match &self.input {
+                    Some(path) => {
+                        let file = path.display().to_string();
+                        (
+                            plus.with_metadata("Scan.file", &file),
+                            minus.with_metadata("Scan.file", &file),
+                        )
+                    }
+                    None => (plus, minus),
+                })
+            }
+        }
+    }
+}
+
 fn main() {
     if let Err(e) = run() {
+        if let Error::Io(e) = &e {
+            if e.kind() == io::ErrorKind::BrokenPipe {
+                return;
+            }
+        }
         eprintln!("{}", e);
         let mut e: &dyn std::error::Error = &e;
         while let Some(source) = e.source() {
@@ -27,28 +416,409 @@ fn main() {
 }
 
 fn run() -> Result<(), Error> {
-    let opt = Opt::from_args();
+    let command = Command::from_args();
 
     let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
 
-    let input = match opt.input {
-        Some(path) => {
-            let file = fs::File::open(path)?;
-            let reader = io::BufReader::new(file);
-            Reader::File(reader)
+    match command {
+        Command::Xas {
+            input,
+            element,
+            plot,
+            format,
+        } => {
+            let config = ElementConfig::load(&element.config, &element.element)?;
+            let xas = Xas::new(open(&input.input, &stdin)?)?;
+            let norm = xas.normalized(&config)?;
+            if let Some(options) = plot.options()? {
+                xas.plot(&options)?;
+            }
+            if let Some(format) = format.format {
+                return Export::from_xas(&xas, &config)?.write(format, &mut out);
+            }
+            writeln!(out, "# e0 = {}", xas.e0)?;
+            writeln!(out, "# energy mu norm")?;
+            let spectrum = &xas.spectrum;
+            for ((e, mu), norm) in spectrum
+                .energy()
+                .iter()
+                .zip(spectrum.values())
+                .zip(norm.values())
+            {
+                writeln!(out, "{} {} {}", e, mu, norm)?;
+            }
+        }
+        Command::Xmcd {
+            input,
+            manifest,
+            parameter,
+            element,
+            geometry,
+            plot,
+            format,
+        } => {
+            let config = element.load(&geometry)?;
+            let options = plot.options()?;
+            if let Some(manifest) = manifest {
+                let base = manifest.parent().unwrap_or_else(|| Path::new("."));
+                let file = io::BufReader::new(open_file(&manifest)?);
+                let series =
+                    Series::from_manifest(&parameter, file, base, &config, &Pipeline::new())?;
+                let table = series.table()?;
+                if let Some(options) = options {
+                    table.plot(&options)?;
+                }
+                match format.format {
+                    Some(format) => table.export().write(format, &mut out)?,
+                    None => table.write(&mut out)?,
+                }
+            } else {
+                let (plus, minus) = input.load(&stdin)?;
+                let xmcd = Xmcd::new(&plus, &minus, &config)?;
+                if let Some(options) = options {
+                    xmcd.plot(&options)?;
+                }
+                match format.format {
+                    Some(format) => Export::from_xmcd(&xmcd)
+                        .sumrules(&SumRules::new(&xmcd))
+                        .write(format, &mut out)?,
+                    None => write_xmcd(&mut out, &xmcd)?,
+                }
+            }
+        }
+        Command::Sumrules {
+            input,
+            element,
+            geometry,
+            errors,
+            seed,
+        } => {
+            let ini = Ini::load(&element.config)?;
+            let config = element.load_from(&ini, &geometry)?;
+            let (plus, minus) = input.load(&stdin)?;
+            let xmcd = Xmcd::new(&plus, &minus, &config)?;
+            write_sumrules(&mut out, &SumRules::new(&xmcd))?;
+            if errors {
+                let jitter = Jitter::new(&ini, &element.element)?;
+                let errors = MomentErrors::new(&plus, &minus, &config, &jitter, seed)?;
+                write_errors(&mut out, &errors)?;
+            }
+        }
+        Command::Synth {
+            element,
+            geometry,
+            elem,
+            m_orb,
+            m_spin,
+            width,
+            sigma,
+            white_line,
+            pre_edge,
+            counts,
+            seed,
+        } => {
+            let config = element.load(&geometry)?;
+            let mut synthetic = Synthetic::new(&Database::load(&elem)?, &config)?;
+            synthetic.shape = match sigma {
+                Some(sigma) => LineShape::Voigt { sigma },
+                None => LineShape::Lorentzian,
+            };
+            synthetic.width = width;
+            synthetic.white_line = white_line;
+            synthetic.m_orb = m_orb;
+            synthetic.m_spin = m_spin;
+            synthetic.pre_edge = pre_edge;
+            synthetic.counts = counts;
+            synthetic.seed = seed;
+            let spectra = synthetic.generate()?;
+            writeln!(out, "# m_orb = {} m_spin = {}", m_orb, m_spin)?;
+            writeln!(out, "# energy mu_plus mu_minus")?;
+            spectra.write(&mut out)?;
         }
-        None => {
-            let guard = stdin.lock();
-            Reader::Stdin(guard)
+        Command::Xmld {
+            input,
+            element,
+            angle_from_normal,
+            natural,
+            reference,
+            reference_angle_from_normal,
+            plot,
+            format,
+        } => {
+            let config = ElementConfig::load(&element.config, &element.element)?;
+            let xmld = match (reference, reference_angle_from_normal) {
+                (Some(reference), Some(reference_angle_from_normal)) => {
+                    let mu = Xas::new(open(&input.input, &stdin)?)?;
+                    let reference = Xas::new(io::BufReader::new(open_file(&reference)?))?;
+                    Xmld::from_angles(
+                        &mu.spectrum,
+                        angle_from_normal,
+                        &reference.spectrum,
+                        reference_angle_from_normal,
+                        &config,
+                    )?
+                }
+                _ => {
+                    let kind = if natural {
+                        LinearDichroism::Natural
+                    } else {
+                        LinearDichroism::Magnetic
+                    };
+                    Xmld::from_columns(
+                        kind,
+                        open(&input.input, &stdin)?,
+                        angle_from_normal,
+                        &config,
+                    )?
+                }
+            };
+            if let Some(options) = plot.options()? {
+                xmld.plot(&options)?;
+            }
+            if let Some(format) = format.format {
+                return Export::from_xmld(&xmld).write(format, &mut out);
+            }
+            writeln!(out, "# energy xas dichroism")?;
+            let (xas, dichroism) = (xmld.xas().values(), xmld.dichroism().values());
+            for (i, e) in xmld.energy().iter().enumerate() {
+                writeln!(out, "{} {} {}", e, xas[i], dichroism[i])?;
+            }
         }
-    };
+        Command::Info {
+            symbol,
+            elem,
+            energy,
+        } => {
+            let db = Database::load(&elem)?;
+            let element = db.require(&symbol)?;
+            writeln!(out, "symbol {}", element.symbol)?;
+            writeln!(out, "z {}", element.z)?;
+            writeln!(out, "atomic_weight {}", element.atomic_weight)?;
+            writeln!(out, "density {}", element.density)?;
+            writeln!(
+                out,
+                "# edge energy fluorescence_yield jump_ratio fluorescence_energy"
+            )?;
+            for edge in &element.edges {
+                writeln!(
+                    out,
+                    "{} {} {} {} {}",
+                    edge.name,
+                    edge.energy,
+                    edge.fluorescence_yield,
+                    edge.jump_ratio,
+                    edge.fluorescence_energy().unwrap_or(f64::NAN)
+                )?;
+            }
+            if !energy.is_empty() {
+                writeln!(out, "# energy photo total (cm²/g)")?;
+                for e in energy {
+                    writeln!(out, "{} {} {}", e, element.photo(e), element.total(e))?;
+                }
+            }
+        }
+        Command::Convert {
+            input,
+            output,
+            columns,
+        } => {
+            let mut table = Columns::read(open(&input.input, &stdin)?)?;
+            if !columns.is_empty() {
+                table = table.select(&columns)?;
+            }
+            match output {
+                Some(path) => table.write(io::BufWriter::new(fs::File::create(path)?))?,
+                None => table.write(&mut out)?,
+            }
+        }
+        Command::Batch {
+            inputs,
+            extension,
+            spec_columns,
+            output,
+            element,
+            geometry,
+        } => {
+            let config = element.load(&geometry)?;
+            if spec_columns.len() != 3 {
+                bail!("--spec-columns takes energy, μ+ and μ− columns");
+            }
+            let mut items = Vec::new();
+            for input in &inputs {
+                if input.is_dir() {
+                    let mut files = fs::read_dir(input)?
+                        .map(|entry| entry.map(|e| e.path()))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    files.retain(|path| path.is_file());
+                    if let Some(ext) = &extension {
+                        files.retain(|path| path.extension() == Some(OsStr::new(ext)));
+                    }
+                    files.sort();
+                    items.extend(files.into_iter().map(BatchItem::File));
+                    continue;
+                }
+                let selection = match input.to_str().and_then(Selection::parse) {
+                    Some(selection) if !input.exists() => selection?,
+                    _ => {
+                        items.push(BatchItem::File(input.clone()));
+                        continue;
+                    }
+                };
+                items.push(BatchItem::Spec(selection));
+            }
+            if let Some(output) = &output {
+                fs::create_dir_all(output)?;
+            }
+
+            writeln!(
+                out,
+                "# file m_orb m_spin ratio edge_jump peak_position amplitude"
+            )?;
+            let mut failed = 0;
+            for item in &items {
+                let name = item.name();
+                let xmcd = match item.load(&spec_columns, &config) {
+                    Ok(xmcd) => xmcd,
+                    Err(e) => {
+                        eprintln!("{}: {}", name, e);
+                        failed += 1;
+                        continue;
+                    }
+                };
+                let rules = SumRules::new(&xmcd);
+                writeln!(
+                    out,
+                    "{} {} {} {} {} {} {}",
+                    name,
+                    rules.m_orb,
+                    rules.m_spin,
+                    rules.ratio(),
+                    xmcd.edge_jump(),
+                    xmcd.peak_position(),
+                    xmcd.amplitude()
+                )?;
+                if let Some(output) = &output {
+                    let file = fs::File::create(output.join(name.replace(':', "_")))?;
+                    write_xmcd(io::BufWriter::new(file), &xmcd)?;
+                }
+            }
+            if failed > 0 {
+                bail!("{} of {} files failed", failed, items.len());
+            }
+        }
+        Command::Spec { input, energy } => match Selection::parse(&input) {
+            Some(selection) => selection?.load(&energy)?.write(&mut out)?,
+            None => {
+                let file = SpecFile::load(&input).map_err(|e| error!("{}: {}", input, e))?;
+                writeln!(out, "# scan points command")?;
+                for scan in &file.scans {
+                    writeln!(out, "{} {} {}", scan.id(), scan.columns.len(), scan.command)?;
+                }
+            }
+        },
+        Command::Run { recipe } => {
+            let recipe = Recipe::load(&recipe)?;
+            let ini = Ini::load(&recipe.config)?;
+            let config = recipe.element_config(&ini)?;
+            let options = recipe.output.plot_options()?;
+            if let Source::Manifest { path, parameter } = &recipe.source {
+                let base = path.parent().unwrap_or_else(|| Path::new("."));
+                let file = io::BufReader::new(open_file(path)?);
+                let series =
+                    Series::from_manifest(parameter, file, base, &config, &recipe.pipeline)?;
+                let table = series.table()?;
+                if let Some(options) = options {
+                    table.plot(&options)?;
+                }
+                match &recipe.output.table {
+                    Some(path) => match Format::from_path(path) {
+                        Some(format) => table.export().write(format, create(path)?)?,
+                        None => table.write(create(path)?)?,
+                    },
+                    None => table.write(&mut out)?,
+                }
+                return Ok(());
+            }
+
+            let (plus, minus) = recipe.spectra()?;
+            let xmcd = Xmcd::new(&plus, &minus, &config)?;
+            let rules = SumRules::new(&xmcd);
+            if let Some(options) = options {
+                xmcd.plot(&options)?;
+            }
+            if let Some(path) = &recipe.output.xmcd {
+                match Format::from_path(path) {
+                    Some(format) => Export::from_xmcd(&xmcd)
+                        .sumrules(&rules)
+                        .write(format, create(path)?)?,
+                    None => write_xmcd(create(path)?, &xmcd)?,
+                }
+            }
+            let mut report: Box<dyn Write> = match &recipe.output.sumrules {
+                Some(path) => Box::new(create(path)?),
+                None => Box::new(&mut out),
+            };
+            write_sumrules(&mut report, &rules)?;
+            if recipe.errors {
+                let jitter = Jitter::new(&ini, &recipe.element)?;
+                let errors = MomentErrors::new(&plus, &minus, &config, &jitter, recipe.seed)?;
+                write_errors(&mut report, &errors)?;
+            }
+        }
+    }
+
+    Ok(())
+}
 
-    let xas = xmcd_rs::xas::Xas::new(input)?;
-    // println!("{:?}", xas);
-    xas.plot()?;
-    for i in 0..xas.mui.len() {
-        println!("{} {}", xas.energy[i], xas.mui[i]);
+fn write_xmcd<W: Write>(mut out: W, xmcd: &Xmcd) -> Result<(), Error> {
+    writeln!(out, "# energy xas xmcd_raw xmcd")?;
+    let (xas, raw, corrected) = (
+        xmcd.xas().values(),
+        xmcd.xmcd_raw().values(),
+        xmcd.xmcd().values(),
+    );
+    for (i, e) in xmcd.energy().iter().enumerate() {
+        writeln!(out, "{} {} {} {}", e, xas[i], raw[i], corrected[i])?;
     }
+    Ok(())
+}
+
+fn write_sumrules<W: Write>(mut out: W, rules: &SumRules) -> Result<(), Error> {
+    writeln!(out, "p {}", rules.p)?;
+    writeln!(out, "q {}", rules.q)?;
+    writeln!(out, "r {}", rules.r)?;
+    writeln!(out, "m_orb {}", rules.m_orb)?;
+    writeln!(out, "m_spin {}", rules.m_spin)?;
+    writeln!(out, "m_orb_raw {}", rules.m_orb_raw)?;
+    writeln!(out, "m_spin_raw {}", rules.m_spin_raw)?;
+    writeln!(out, "ratio {}", rules.ratio())?;
+    Ok(())
+}
+
+fn write_errors<W: Write>(mut out: W, errors: &MomentErrors) -> Result<(), Error> {
+    write_distribution(&mut out, "m_orb", &errors.m_orb)?;
+    write_distribution(&mut out, "m_spin", &errors.m_spin)?;
+    write_distribution(&mut out, "ratio", &errors.ratio)?;
+    writeln!(out, "failed {}", errors.failed)?;
+    Ok(())
+}
 
+fn write_distribution<W: Write>(mut out: W, name: &str, d: &Distribution) -> Result<(), Error> {
+    writeln!(
+        out,
+        "{}_mean {}\n{}_std {}\n{}_p2.5 {}\n{}_p50 {}\n{}_p97.5 {}",
+        name,
+        d.mean,
+        name,
+        d.std,
+        name,
+        d.percentile(2.5),
+        name,
+        d.percentile(50.0),
+        name,
+        d.percentile(97.5)
+    )?;
     Ok(())
 }
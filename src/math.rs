@@ -0,0 +1,317 @@
+use crate::Error;
+
+/// Evenly spaced grid from `start` to `stop` (inclusive) with the given step.
+pub(crate) fn arange(start: f64, stop: f64, step: f64) -> Vec<f64> {
+    let num = ((stop - start) / step + 1e-9).floor() as usize + 1;
+    (0..num).map(|i| start + i as f64 * step).collect()
+}
+
+/// Linear interpolation of `(x, y)` at `at`; `x` must be sorted ascending.
+/// Values outside of `x` are clamped to the end points.
+pub(crate) fn interp(x: &[f64], y: &[f64], at: f64) -> f64 {
+    let last = x.len() - 1;
+    if at <= x[0] {
+        return y[0];
+    }
+    if at >= x[last] {
+        return y[last];
+    }
+    let i = match x.binary_search_by(|v| v.partial_cmp(&at).unwrap()) {
+        Ok(i) => return y[i],
+        Err(i) => i,
+    };
+    let t = (at - x[i - 1]) / (x[i] - x[i - 1]);
+    y[i - 1] + t * (y[i] - y[i - 1])
+}
+
+pub(crate) fn resample(x: &[f64], y: &[f64], grid: &[f64]) -> Vec<f64> {
+    grid.iter().map(|&e| interp(x, y, e)).collect()
+}
+
+/// Trapezoidal integral of `y` over the whole of `x`.
+pub(crate) fn trapz(x: &[f64], y: &[f64]) -> f64 {
+    x.windows(2)
+        .zip(y.windows(2))
+        .map(|(x, y)| 0.5 * (x[1] - x[0]) * (y[0] + y[1]))
+        .sum()
+}
+
+/// Running trapezoidal integral of `y`, starting at zero.
+pub(crate) fn cumtrapz(x: &[f64], y: &[f64]) -> Vec<f64> {
+    let mut total = 0.0;
+    let mut out = Vec::with_capacity(x.len());
+    if !x.is_empty() {
+        out.push(0.0);
+    }
+    for (x, y) in x.windows(2).zip(y.windows(2)) {
+        total += 0.5 * (x[1] - x[0]) * (y[0] + y[1]);
+        out.push(total);
+    }
+    out
+}
+
+/// Mean of `y` where `lo <= x <= hi`, or `None` if no point falls inside.
+pub(crate) fn mean_in(x: &[f64], y: &[f64], lo: f64, hi: f64) -> Option<f64> {
+    let (sum, n) = x
+        .iter()
+        .zip(y)
+        .filter(|(&x, _)| x >= lo && x <= hi)
+        .fold((0.0, 0), |(sum, n), (_, &y)| (sum + y, n + 1));
+    if n > 0 {
+        Some(sum / n as f64)
+    } else {
+        None
+    }
+}
+
+/// Index of the largest element; NaNs are never selected.
+pub(crate) fn argmax(y: &[f64]) -> Option<usize> {
+    y.iter()
+        .enumerate()
+        .filter(|(_, v)| !v.is_nan())
+        .fold(None, |acc: Option<(usize, f64)>, (i, &v)| match acc {
+            Some((_, best)) if best >= v => acc,
+            _ => Some((i, v)),
+        })
+        .map(|(i, _)| i)
+}
+
+/// Parses a float that may also be written as a fraction (`1/2`) or with a
+/// leading plus sign (`+8`), as found in `element.ini`.
+pub(crate) fn parse_number(s: &str) -> Result<f64, Error> {
+    let s = s.trim().trim_start_matches('+');
+    match s.find('/') {
+        Some(i) => {
+            let num = s[..i].trim().parse::<f64>()?;
+            let den = s[i + 1..].trim().parse::<f64>()?;
+            if den == 0.0 {
+                bail!("Zero denominator in {:?}", s);
+            }
+            Ok(num / den)
+        }
+        None => Ok(s.parse::<f64>()?),
+    }
+}
+
+/// Trapezoidal integral of `y` over the points with `lo <= x <= hi`.
+pub(crate) fn integrate(x: &[f64], y: &[f64], lo: f64, hi: f64) -> f64 {
+    let start = x.iter().position(|&v| v >= lo).unwrap_or(x.len());
+    let end = x.iter().rposition(|&v| v <= hi).map_or(0, |i| i + 1);
+    if start >= end {
+        return 0.0;
+    }
+    trapz(&x[start..end], &y[start..end])
+}
+
+/// `exp(a) * erfc(x)`, evaluated without overflow for large `a` and `x`
+/// (Numerical Recipes' Chebyshev fit, relative error below 1.2e-7).
+pub(crate) fn exp_erfc(a: f64, x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    if x >= 0.0 {
+        t * (a - z * z + poly).exp()
+    } else {
+        a.exp() * (2.0 - t * (-z * z + poly).exp())
+    }
+}
+
+pub(crate) fn erfc(x: f64) -> f64 {
+    exp_erfc(0.0, x)
+}
+
+/// In-place radix-2 FFT of `(re, im)`; the length must be a power of two.
+/// The inverse transform is not scaled by `1/n`.
+pub(crate) fn fft(re: &mut [f64], im: &mut [f64], inverse: bool) {
+    let n = re.len();
+    debug_assert!(n.is_power_of_two() && im.len() == n);
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * std::f64::consts::PI / len as f64;
+        let (w_re, w_im) = (angle.cos(), angle.sin());
+        for start in (0..n).step_by(len) {
+            let (mut c, mut s) = (1.0, 0.0);
+            for k in 0..len / 2 {
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * c - im[b] * s;
+                let t_im = re[b] * s + im[b] * c;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+                let next = c * w_re - s * w_im;
+                s = c * w_im + s * w_re;
+                c = next;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Modified Bessel function of the first kind, order zero (power series).
+pub(crate) fn bessel_i0(x: f64) -> f64 {
+    let q = 0.25 * x * x;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    let mut k = 1.0;
+    while term > 1e-16 * sum {
+        term *= q / (k * k);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+/// Natural cubic spline through `(x, y)` evaluated at `at`; `x` must be
+/// sorted ascending with at least two points.
+pub(crate) fn spline(x: &[f64], y: &[f64], at: &[f64]) -> Vec<f64> {
+    let n = x.len();
+    // Second derivatives from the tridiagonal system, zero at both ends.
+    let mut y2 = vec![0.0; n];
+    let mut u = vec![0.0; n];
+    for i in 1..n - 1 {
+        let sig = (x[i] - x[i - 1]) / (x[i + 1] - x[i - 1]);
+        let p = sig * y2[i - 1] + 2.0;
+        y2[i] = (sig - 1.0) / p;
+        let d = (y[i + 1] - y[i]) / (x[i + 1] - x[i]) - (y[i] - y[i - 1]) / (x[i] - x[i - 1]);
+        u[i] = (6.0 * d / (x[i + 1] - x[i - 1]) - sig * u[i - 1]) / p;
+    }
+    for i in (0..n - 1).rev() {
+        y2[i] = y2[i] * y2[i + 1] + u[i];
+    }
+    y2[n - 1] = 0.0;
+    y2[0] = 0.0;
+
+    at.iter()
+        .map(|&v| {
+            let hi = x.iter().position(|&xi| xi > v).unwrap_or(n - 1).max(1);
+            let lo = hi - 1;
+            let h = x[hi] - x[lo];
+            let a = (x[hi] - v) / h;
+            let b = (v - x[lo]) / h;
+            a * y[lo]
+                + b * y[hi]
+                + ((a * a * a - a) * y2[lo] + (b * b * b - b) * y2[hi]) * h * h / 6.0
+        })
+        .collect()
+}
+
+/// Least-squares straight line `(slope, intercept)` through the points with
+/// `lo <= x <= hi`, or `None` if fewer than two fall inside.
+pub(crate) fn line_fit(x: &[f64], y: &[f64], lo: f64, hi: f64) -> Option<(f64, f64)> {
+    let points = x
+        .iter()
+        .zip(y)
+        .filter(|(&x, _)| x >= lo && x <= hi)
+        .collect::<Vec<_>>();
+    let n = points.len() as f64;
+    if points.len() < 2 {
+        return None;
+    }
+    let mx = points.iter().map(|(x, _)| *x).sum::<f64>() / n;
+    let my = points.iter().map(|(_, y)| *y).sum::<f64>() / n;
+    let sxx = points.iter().map(|(x, _)| (*x - mx).powi(2)).sum::<f64>();
+    let sxy = points
+        .iter()
+        .map(|(x, y)| (*x - mx) * (*y - my))
+        .sum::<f64>();
+    if sxx == 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    Some((slope, my - slope * mx))
+}
+
+/// Natural logarithm of the gamma function for `x > 0` (Lanczos).
+pub(crate) fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.120_865_097_386_617_9e-2,
+        -0.539_523_938_495_3e-5,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000_000_000_190_015, |acc, (i, c)| {
+            acc + c / (x + 1.0 + i as f64)
+        });
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+/// Regularized incomplete beta function `I_x(a, b)` (continued fraction,
+/// Numerical Recipes' `betai`).
+pub(crate) fn beta_inc(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let (qab, qap, qam) = (a + b, a + 1.0, a - 1.0);
+    let mut c = 1.0;
+    let mut d = 1.0 - qab * x / qap;
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..300 {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+        for aa in &[
+            m * (b - m) * x / ((qam + m2) * (a + m2)),
+            -(a + m) * (qab + m) * x / ((a + m2) * (qap + m2)),
+        ] {
+            d = 1.0 + aa * d;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = 1.0 + aa / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 3e-14 {
+            break;
+        }
+    }
+    h
+}
@@ -0,0 +1,510 @@
+use std::fmt;
+
+use crate::config::ElementConfig;
+use crate::math;
+use crate::spectrum::{Metadata, Spectrum};
+use crate::Error;
+
+/// Prefix of the metadata keys written by [`Pipeline::run`], followed by
+/// the position of the step with at least two digits, e.g. `Process.01`
+/// or `Process.100`.
+pub const PROVENANCE_PREFIX: &str = "Process.";
+
+/// One processing step of a [`Pipeline`].
+pub trait Step: fmt::Debug {
+    /// Short lowercase name, e.g. `normalize`.
+    fn name(&self) -> &'static str;
+
+    /// Parameter names and values, in a fixed order.
+    fn parameters(&self) -> Vec<(&'static str, f64)>;
+
+    fn apply(&self, spectrum: &Spectrum) -> Result<Spectrum, Error>;
+
+    /// `name key=value ...`, as recorded in the metadata.
+    fn describe(&self) -> String {
+        let mut text = self.name().to_string();
+        for (key, value) in self.parameters() {
+            text += &format!(" {}={}", key, value);
+        }
+        text
+    }
+}
+
+/// Steps applied in order. Each step that runs is recorded in the metadata
+/// of the result under `Process.NN`, after any steps recorded before.
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    pub steps: Vec<Box<dyn Step>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    /// Appends `step`.
+    pub fn step<S: Step + 'static>(mut self, step: S) -> Pipeline {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    pub fn push(&mut self, step: Box<dyn Step>) {
+        self.steps.push(step);
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn run(&self, spectrum: &Spectrum) -> Result<Spectrum, Error> {
+        let mut spectrum = spectrum.clone();
+        for step in &self.steps {
+            spectrum = step
+                .apply(&spectrum)
+                .map_err(|e| error!("{}: {}", step.name(), e))?;
+            record(&mut spectrum.metadata, step.describe());
+        }
+        Ok(spectrum)
+    }
+}
+
+/// Records `description` in `metadata` as the step after those already
+/// recorded.
+pub fn record(metadata: &mut Metadata, description: String) {
+    let n = provenance(metadata).len() + 1;
+    metadata.insert(format!("{}{:02}", PROVENANCE_PREFIX, n), description);
+}
+
+/// The steps recorded in `metadata`, in the order they ran. Positions are
+/// compared as numbers, so that `Process.100` follows `Process.99`.
+pub fn provenance(metadata: &Metadata) -> Vec<&str> {
+    let mut steps = metadata
+        .range(PROVENANCE_PREFIX.to_string()..)
+        .take_while(|(key, _)| key.starts_with(PROVENANCE_PREFIX))
+        .filter_map(|(key, value)| {
+            let n = key[PROVENANCE_PREFIX.len()..].parse::<usize>().ok()?;
+            Some((n, value.as_str()))
+        })
+        .collect::<Vec<_>>();
+    steps.sort_by_key(|&(n, _)| n);
+    steps.into_iter().map(|(_, value)| value).collect()
+}
+
+/// Replaces single-point spikes by the mean of their neighbours. A point is
+/// a glitch if it departs from that mean by more than `threshold` times
+/// the robust spread of all such departures, and by more than either
+/// neighbour does; a spike also pulls its neighbours' departures the other
+/// way, and those are left alone. A replaced point carries the
+/// uncertainty of the mean of its neighbours.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Deglitch {
+    pub threshold: f64,
+}
+
+impl Default for Deglitch {
+    fn default() -> Deglitch {
+        Deglitch { threshold: 5.0 }
+    }
+}
+
+impl Step for Deglitch {
+    fn name(&self) -> &'static str {
+        "deglitch"
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, f64)> {
+        vec![("threshold", self.threshold)]
+    }
+
+    fn apply(&self, spectrum: &Spectrum) -> Result<Spectrum, Error> {
+        if self.threshold <= 0.0 {
+            bail!("Threshold must be positive");
+        }
+        let y = spectrum.values();
+        if y.len() < 3 {
+            return Ok(spectrum.clone());
+        }
+        let departure = |i: usize| y[i] - 0.5 * (y[i - 1] + y[i + 1]);
+        let mut spread = (1..y.len() - 1)
+            .map(|i| departure(i).abs())
+            .collect::<Vec<_>>();
+        spread.sort_by(|a, b| a.total_cmp(b));
+        // Median absolute departure scaled to a standard deviation, or the
+        // mean absolute departure if most points lie on a straight line.
+        let sigma = match 1.4826 * spread[spread.len() / 2] {
+            s if s > 0.0 => s,
+            _ => 1.2533 * spread.iter().sum::<f64>() / spread.len() as f64,
+        };
+        if sigma == 0.0 {
+            return Ok(spectrum.clone());
+        }
+        let size = |i: usize| {
+            if i == 0 || i == y.len() - 1 {
+                0.0
+            } else {
+                departure(i).abs()
+            }
+        };
+        let mut values = y.to_vec();
+        let mut uncertainty = spectrum.uncertainty().map(<[f64]>::to_vec);
+        for i in 1..y.len() - 1 {
+            let d = size(i);
+            if d > self.threshold * sigma && d >= size(i - 1) && d >= size(i + 1) {
+                values[i] = 0.5 * (y[i - 1] + y[i + 1]);
+                if let (Some(u), Some(s)) = (uncertainty.as_mut(), spectrum.uncertainty()) {
+                    u[i] = 0.5 * s[i - 1].hypot(s[i + 1]);
+                }
+            }
+        }
+        spectrum.with_values_and_uncertainty(values, uncertainty)
+    }
+}
+
+/// Scales to zero at the mean of the pre-edge window and one at the mean
+/// of the post-edge window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Normalize {
+    pub pre: (f64, f64),
+    pub post: (f64, f64),
+}
+
+impl Normalize {
+    /// The pre- and post-edge windows of an element configuration.
+    pub fn from_config(config: &ElementConfig) -> Normalize {
+        Normalize {
+            pre: (
+                config.preedge_start,
+                config.preedge_start + config.preedge_width,
+            ),
+            post: (config.post_start(), config.end_energy),
+        }
+    }
+}
+
+impl Step for Normalize {
+    fn name(&self) -> &'static str {
+        "normalize"
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, f64)> {
+        vec![
+            ("pre_start", self.pre.0),
+            ("pre_end", self.pre.1),
+            ("post_start", self.post.0),
+            ("post_end", self.post.1),
+        ]
+    }
+
+    fn apply(&self, spectrum: &Spectrum) -> Result<Spectrum, Error> {
+        let (energy, mu) = (spectrum.energy(), spectrum.values());
+        let level = |(lo, hi): (f64, f64)| {
+            math::mean_in(energy, mu, lo, hi)
+                .ok_or_else(|| error!("No points between {} and {} eV", lo, hi))
+        };
+        let (pre, post) = (level(self.pre)?, level(self.post)?);
+        if post == pre {
+            bail!("Zero edge jump");
+        }
+        spectrum.map(|_, y| y - pre)?.scale(1.0 / (post - pre))
+    }
+}
+
+/// Shifts the energy so that the steepest rise of the spectrum lands on
+/// `e0`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Align {
+    pub e0: f64,
+}
+
+impl Step for Align {
+    fn name(&self) -> &'static str {
+        "align"
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, f64)> {
+        vec![("e0", self.e0)]
+    }
+
+    fn apply(&self, spectrum: &Spectrum) -> Result<Spectrum, Error> {
+        let (energy, mu) = (spectrum.energy(), spectrum.values());
+        let derivative = energy
+            .windows(3)
+            .zip(mu.windows(3))
+            .map(|(e, m)| (m[2] - m[0]) / (e[2] - e[0]))
+            .collect::<Vec<_>>();
+        let i = math::argmax(&derivative).ok_or_else(|| error!("Need at least three points"))?;
+        spectrum.shift(self.e0 - energy[i + 1])
+    }
+}
+
+/// Moving average over `2·half_width + 1` points, narrower at the ends.
+/// Uncertainties are propagated as for independent points, so a full
+/// window divides a constant σ by `√(2·half_width + 1)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Smooth {
+    pub half_width: usize,
+}
+
+impl Step for Smooth {
+    fn name(&self) -> &'static str {
+        "smooth"
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, f64)> {
+        vec![("half_width", self.half_width as f64)]
+    }
+
+    fn apply(&self, spectrum: &Spectrum) -> Result<Spectrum, Error> {
+        let n = spectrum.len();
+        let window = |i: usize| {
+            let lo = i.saturating_sub(self.half_width);
+            (lo, (i + self.half_width + 1).min(n))
+        };
+        let average = |x: &[f64], f: fn(f64) -> f64| {
+            (0..n)
+                .map(|i| {
+                    let (lo, hi) = window(i);
+                    x[lo..hi].iter().map(|&v| f(v)).sum::<f64>() / (hi - lo) as f64
+                })
+                .collect::<Vec<_>>()
+        };
+        let values = average(spectrum.values(), |y| y);
+        let uncertainty = spectrum.uncertainty().map(|sigma| {
+            average(sigma, |s| s * s)
+                .iter()
+                .enumerate()
+                .map(|(i, mean)| {
+                    let (lo, hi) = window(i);
+                    (mean / (hi - lo) as f64).sqrt()
+                })
+                .collect()
+        });
+        spectrum.with_values_and_uncertainty(values, uncertainty)
+    }
+}
+
+/// Subtracts the straight line fitted between `start` and `end`, usually
+/// the pre-edge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Background {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Step for Background {
+    fn name(&self) -> &'static str {
+        "background"
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, f64)> {
+        vec![("start", self.start), ("end", self.end)]
+    }
+
+    fn apply(&self, spectrum: &Spectrum) -> Result<Spectrum, Error> {
+        let (slope, intercept) =
+            math::line_fit(spectrum.energy(), spectrum.values(), self.start, self.end).ok_or_else(
+                || error!("Need two points between {} and {} eV", self.start, self.end),
+            )?;
+        spectrum.map(|e, y| y - (slope * e + intercept))
+    }
+}
+
+/// Linear interpolation onto `start, start + step, ...` up to `stop`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Resample {
+    pub start: f64,
+    pub stop: f64,
+    pub step: f64,
+}
+
+impl Step for Resample {
+    fn name(&self) -> &'static str {
+        "resample"
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, f64)> {
+        vec![
+            ("start", self.start),
+            ("stop", self.stop),
+            ("step", self.step),
+        ]
+    }
+
+    fn apply(&self, spectrum: &Spectrum) -> Result<Spectrum, Error> {
+        if self.step <= 0.0 || self.stop < self.start {
+            bail!("Need a positive step and start <= stop");
+        }
+        spectrum.resample(&math::arange(self.start, self.stop, self.step))
+    }
+}
+
+/// Keeps the points between `start` and `end`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crop {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Step for Crop {
+    fn name(&self) -> &'static str {
+        "crop"
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, f64)> {
+        vec![("start", self.start), ("end", self.end)]
+    }
+
+    fn apply(&self, spectrum: &Spectrum) -> Result<Spectrum, Error> {
+        spectrum.crop(self.start, self.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[derive(Debug)]
+    struct Offset(f64);
+
+    impl Step for Offset {
+        fn name(&self) -> &'static str {
+            "offset"
+        }
+
+        fn parameters(&self) -> Vec<(&'static str, f64)> {
+            vec![("by", self.0)]
+        }
+
+        fn apply(&self, spectrum: &Spectrum) -> Result<Spectrum, Error> {
+            spectrum.with_values(spectrum.values().iter().map(|y| y + self.0).collect())
+        }
+    }
+
+    #[test]
+    fn deglitch_removes_a_spike_without_smearing_it() {
+        let energy = (0..20).map(f64::from).collect::<Vec<_>>();
+        let mut y = vec![1.0; 20];
+        y[10] = 5.0;
+        let spectrum = Spectrum::new(energy, y).unwrap();
+        let clean = Deglitch::default().apply(&spectrum).unwrap();
+        assert!(
+            clean.values().iter().all(|&v| v == 1.0),
+            "{:?}",
+            clean.values()
+        );
+    }
+
+    #[test]
+    fn deglitch_gives_a_replaced_point_its_neighbours_uncertainty() {
+        let energy = (0..20).map(f64::from).collect::<Vec<_>>();
+        let mut y = vec![1.0; 20];
+        y[10] = 5.0;
+        let mut sigma = vec![0.1; 20];
+        sigma[9] = 0.3;
+        sigma[11] = 0.4;
+        let spectrum = Spectrum::new(energy, y)
+            .unwrap()
+            .with_uncertainty(sigma)
+            .unwrap();
+        let clean = Deglitch::default().apply(&spectrum).unwrap();
+        let sigma = clean.uncertainty().unwrap();
+        assert!((sigma[10] - 0.25).abs() < 1e-12);
+        assert_eq!(sigma[9], 0.3);
+        assert_eq!(sigma[0], 0.1);
+    }
+
+    #[test]
+    fn smooth_averages_down_the_uncertainty() {
+        let energy = (0..10).map(f64::from).collect::<Vec<_>>();
+        let y = energy.iter().map(|e| e * e).collect::<Vec<_>>();
+        let spectrum = Spectrum::new(energy, y)
+            .unwrap()
+            .with_uncertainty(vec![0.3; 10])
+            .unwrap();
+        let smooth = Smooth { half_width: 1 }.apply(&spectrum).unwrap();
+        assert!((smooth.values()[5] - (16.0 + 25.0 + 36.0) / 3.0).abs() < 1e-12);
+        assert!((smooth.values()[0] - 0.5).abs() < 1e-12);
+        let sigma = smooth.uncertainty().unwrap();
+        assert!((sigma[5] - 0.3 / 3f64.sqrt()).abs() < 1e-12);
+        assert!((sigma[0] - 0.3 / 2f64.sqrt()).abs() < 1e-12);
+    }
+
+    /// An arctan edge at 780 eV of height 2 on a sloping background.
+    fn edge() -> Spectrum {
+        let energy = math::arange(760.0, 820.0, 0.5);
+        let y = energy
+            .iter()
+            .map(|&e| 0.5 + 0.01 * (e - 760.0) + 2.0 * (0.5 + ((e - 780.0) / 0.5).atan() / PI))
+            .collect();
+        Spectrum::new(energy, y).unwrap()
+    }
+
+    #[test]
+    fn normalize_maps_the_windows_to_zero_and_one() {
+        let step = Normalize {
+            pre: (760.0, 770.0),
+            post: (805.0, 820.0),
+        };
+        let norm = step.apply(&edge()).unwrap();
+        let (energy, mu) = (norm.energy(), norm.values());
+        let pre = math::mean_in(energy, mu, 760.0, 770.0).unwrap();
+        let post = math::mean_in(energy, mu, 805.0, 820.0).unwrap();
+        assert!(pre.abs() < 1e-12);
+        assert!((post - 1.0).abs() < 1e-12);
+        let flat = Spectrum::new(vec![760.0, 810.0], vec![1.0, 1.0]).unwrap();
+        assert!(step.apply(&flat).is_err());
+    }
+
+    #[test]
+    fn align_puts_the_steepest_rise_on_e0() {
+        let aligned = Align { e0: 778.1 }.apply(&edge()).unwrap();
+        let energy = aligned.energy();
+        assert!((energy[0] - 758.1).abs() < 1e-9);
+        let mu = aligned.values();
+        let i = (1..mu.len() - 1)
+            .max_by(|&a, &b| (mu[a + 1] - mu[a - 1]).total_cmp(&(mu[b + 1] - mu[b - 1])))
+            .unwrap();
+        assert!((energy[i] - 778.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn background_removes_the_pre_edge_line() {
+        let clean = Background {
+            start: 760.0,
+            end: 770.0,
+        }
+        .apply(&edge())
+        .unwrap();
+        let (energy, mu) = (clean.energy(), clean.values());
+        assert!(math::mean_in(energy, mu, 760.0, 770.0).unwrap().abs() < 0.01);
+        let (slope, _) = math::line_fit(energy, mu, 790.0, 820.0).unwrap();
+        assert!(slope.abs() < 1e-3);
+        assert!(Background {
+            start: 900.0,
+            end: 910.0
+        }
+        .apply(&edge())
+        .is_err());
+    }
+
+    #[test]
+    fn provenance_keeps_order_past_99_steps() {
+        let mut pipeline = Pipeline::new();
+        for n in 1..=120 {
+            pipeline.push(Box::new(Offset(f64::from(n))));
+        }
+        let spectrum = Spectrum::new(vec![0.0, 1.0], vec![0.0, 0.0]).unwrap();
+        let result = pipeline.run(&spectrum).unwrap();
+        let steps = provenance(&result.metadata);
+        assert_eq!(steps.len(), 120);
+        assert_eq!(
+            steps[98..101],
+            ["offset by=99", "offset by=100", "offset by=101"]
+        );
+    }
+}
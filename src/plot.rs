@@ -0,0 +1,310 @@
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+#[cfg(feature = "plot")]
+mod gnuplot;
+#[cfg(feature = "plot")]
+mod native;
+
+#[cfg(feature = "plot")]
+pub use self::gnuplot::Gnuplot;
+#[cfg(feature = "plot")]
+pub use self::native::Native;
+
+/// Renders a [`Figure`] into the file described by [`PlotOptions`].
+pub trait Plotter {
+    fn render(&self, figure: &Figure, options: &PlotOptions) -> Result<(), Error>;
+}
+
+/// Backend-independent figure: panels laid out row by row on a grid.
+#[derive(Debug, Clone)]
+pub struct Figure {
+    pub panels: Vec<Panel>,
+    /// Panels per row.
+    pub columns: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Panel {
+    pub title: String,
+    pub x_label: String,
+    pub y_label: String,
+    pub traces: Vec<Trace>,
+    /// Shaded x ranges, drawn beneath the traces.
+    pub spans: Vec<Span>,
+    pub notes: Vec<Note>,
+}
+
+/// Shaded band over `lo..hi` across the full height of a panel.
+#[derive(Debug, Clone, Copy)]
+pub struct Span {
+    pub lo: f64,
+    pub hi: f64,
+    pub color: Color,
+}
+
+/// Marked point with a text label, in data coordinates.
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub text: String,
+    pub x: f64,
+    pub y: f64,
+}
+
+/// One curve of a panel; an empty `label` keeps it out of the legend.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub label: String,
+    pub x: Vec<f64>,
+    pub y: Vec<f64>,
+    pub style: Style,
+    pub color: Color,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Line,
+    /// Dashed line, e.g. a fit overlay.
+    Dashed,
+    Points,
+    LinePoints,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color(pub u8, pub u8, pub u8);
+
+impl Color {
+    pub const BLACK: Color = Color(0, 0, 0);
+    pub const RED: Color = Color(214, 39, 40);
+    pub const BLUE: Color = Color(31, 119, 180);
+    pub const GREEN: Color = Color(44, 160, 44);
+    pub const ORANGE: Color = Color(255, 127, 14);
+    pub const GRAY: Color = Color(127, 127, 127);
+
+    pub fn hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+}
+
+impl Figure {
+    /// Panels stacked in one column.
+    pub fn new(panels: Vec<Panel>) -> Figure {
+        Figure { panels, columns: 1 }
+    }
+
+    pub fn grid(panels: Vec<Panel>, columns: usize) -> Figure {
+        Figure {
+            panels,
+            columns: columns.max(1),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.panels.len().div_ceil(self.columns)
+    }
+
+    /// Renders with the backend chosen in `options`.
+    pub fn save(&self, options: &PlotOptions) -> Result<(), Error> {
+        if self.panels.is_empty() {
+            bail!("Empty figure");
+        }
+        if options.width == 0 || options.height == 0 {
+            bail!("Plot size must be positive");
+        }
+        match options.backend {
+            #[cfg(feature = "plot")]
+            Backend::Native => Native.render(self, options),
+            #[cfg(feature = "plot")]
+            Backend::Gnuplot => Gnuplot.render(self, options),
+            #[cfg(not(feature = "plot"))]
+            _ => bail!("xmcd_rs was built without the `plot` feature"),
+        }
+    }
+}
+
+impl Panel {
+    pub fn new(x_label: &str, y_label: &str) -> Panel {
+        Panel {
+            x_label: x_label.to_string(),
+            y_label: y_label.to_string(),
+            ..Panel::default()
+        }
+    }
+
+    pub fn title(mut self, title: &str) -> Panel {
+        self.title = title.to_string();
+        self
+    }
+
+    pub fn trace(mut self, label: &str, x: &[f64], y: &[f64], style: Style, color: Color) -> Panel {
+        self.traces.push(Trace {
+            label: label.to_string(),
+            x: x.to_vec(),
+            y: y.to_vec(),
+            style,
+            color,
+        });
+        self
+    }
+
+    pub fn span(mut self, lo: f64, hi: f64, color: Color) -> Panel {
+        if hi > lo {
+            self.spans.push(Span { lo, hi, color });
+        }
+        self
+    }
+
+    pub fn note(mut self, text: &str, x: f64, y: f64) -> Panel {
+        self.notes.push(Note {
+            text: text.to_string(),
+            x,
+            y,
+        });
+        self
+    }
+
+    /// Smallest and largest finite x and y over all traces and notes,
+    /// padded as drawn.
+    pub fn bounds(&self) -> ((f64, f64), (f64, f64)) {
+        let mut x = (f64::INFINITY, f64::NEG_INFINITY);
+        let mut y = (f64::INFINITY, f64::NEG_INFINITY);
+        for trace in &self.traces {
+            for (&u, &v) in trace.x.iter().zip(&trace.y) {
+                if u.is_finite() && v.is_finite() {
+                    x = (x.0.min(u), x.1.max(u));
+                    y = (y.0.min(v), y.1.max(v));
+                }
+            }
+        }
+        for note in &self.notes {
+            if note.x.is_finite() && note.y.is_finite() {
+                x = (x.0.min(note.x), x.1.max(note.x));
+                y = (y.0.min(note.y), y.1.max(note.y));
+            }
+        }
+        (pad(x, 0.01), pad(y, 0.05))
+    }
+}
+
+/// Widens `range` by `fraction` on each side, and empty or zero-width
+/// ranges to a unit one.
+fn pad(range: (f64, f64), fraction: f64) -> (f64, f64) {
+    let (lo, hi) = range;
+    if lo > hi {
+        return (0.0, 1.0);
+    }
+    if hi == lo {
+        return (lo - 0.5, hi + 0.5);
+    }
+    let margin = fraction * (hi - lo);
+    (lo - margin, hi + margin)
+}
+
+/// Plotting backend used by [`Figure::save`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// Pure-Rust rendering with `plotters`; PNG and SVG.
+    Native,
+    /// An external gnuplot process; PNG, SVG and PDF.
+    Gnuplot,
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Native => write!(f, "native"),
+            Self::Gnuplot => write!(f, "gnuplot"),
+        }
+    }
+}
+
+impl std::str::FromStr for Backend {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = match s {
+            "native" | "Native" | "plotters" => Backend::Native,
+            "gnuplot" | "Gnuplot" => Backend::Gnuplot,
+            _ => bail!("Unknown plot backend {:?}; use native or gnuplot", s),
+        };
+        Ok(s)
+    }
+}
+
+/// File format of a rendered figure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Png,
+    Svg,
+    Pdf,
+}
+
+impl Format {
+    /// Format named by the extension of `path`.
+    pub fn from_path(path: &Path) -> Result<Format, Error> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => ext.parse(),
+            None => bail!("{}: no extension to tell the plot format", path.display()),
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Png => write!(f, "png"),
+            Self::Svg => write!(f, "svg"),
+            Self::Pdf => write!(f, "pdf"),
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = match s {
+            "png" | "PNG" => Format::Png,
+            "svg" | "SVG" => Format::Svg,
+            "pdf" | "PDF" => Format::Pdf,
+            _ => bail!("Unknown plot format {:?}; use png, svg or pdf", s),
+        };
+        Ok(s)
+    }
+}
+
+/// Output file, format, size in pixels and backend of a figure.
+#[derive(Debug, Clone)]
+pub struct PlotOptions {
+    pub path: PathBuf,
+    pub format: Format,
+    pub width: u32,
+    pub height: u32,
+    pub backend: Backend,
+}
+
+impl PlotOptions {
+    /// A 1200×800 figure in the format given by the extension of `path`,
+    /// drawn by the native backend.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Result<PlotOptions, Error> {
+        let path = path.into();
+        let format = Format::from_path(&path)?;
+        Ok(PlotOptions {
+            path,
+            format,
+            width: 1200,
+            height: 800,
+            backend: Backend::Native,
+        })
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> PlotOptions {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> PlotOptions {
+        self.backend = backend;
+        self
+    }
+}
@@ -0,0 +1,93 @@
+use gnuplot::{Axis, FillAlpha, LineStyle, LineWidth, PointSize, PointSymbol, TextOffset};
+use gnuplot::{Caption, Color as LineColor, DashType, Figure as GnuplotFigure};
+
+use super::{Figure, Format, PlotOptions, Plotter, Style};
+use crate::Error;
+
+/// Backend that drives an external gnuplot process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Gnuplot;
+
+impl Plotter for Gnuplot {
+    fn render(&self, figure: &Figure, options: &PlotOptions) -> Result<(), Error> {
+        let path = options
+            .path
+            .to_str()
+            .ok_or_else(|| error!("{}: plot path is not UTF-8", options.path.display()))?;
+        let (width, height) = (options.width, options.height);
+        let terminal = match options.format {
+            Format::Png => format!("pngcairo size {},{}", width, height),
+            Format::Svg => format!("svg size {},{}", width, height),
+            // pdfcairo sizes are in inches; take 100 pixels per inch.
+            Format::Pdf => format!(
+                "pdfcairo size {}in,{}in",
+                f64::from(width) / 100.0,
+                f64::from(height) / 100.0
+            ),
+        };
+
+        let mut fg = GnuplotFigure::new();
+        fg.set_terminal(&terminal, path);
+        let columns = figure.columns;
+        let rows = figure.rows();
+        let (w, h) = (1.0 / columns as f64, 1.0 / rows as f64);
+        for (i, panel) in figure.panels.iter().enumerate() {
+            let (row, column) = (i / columns, i % columns);
+            let ax = fg
+                .axes2d()
+                .set_size(w, h)
+                .set_pos(column as f64 * w, 1.0 - (row + 1) as f64 * h)
+                .set_title(&panel.title, &[])
+                .set_x_label(&panel.x_label, &[])
+                .set_y_label(&panel.y_label, &[]);
+            let (_, (y0, y1)) = panel.bounds();
+            for span in &panel.spans {
+                let color = span.color.hex();
+                ax.fill_between(
+                    [span.lo, span.hi],
+                    [y0, y0],
+                    [y1, y1],
+                    &[LineColor(color.as_str()), FillAlpha(0.15)],
+                );
+            }
+            for trace in &panel.traces {
+                let color = trace.color.hex();
+                let mut style = vec![LineColor(color.as_str())];
+                if !trace.label.is_empty() {
+                    style.push(Caption(trace.label.as_str()));
+                }
+                match trace.style {
+                    Style::Line => style.push(LineWidth(1.5)),
+                    Style::Dashed => {
+                        style.push(LineWidth(1.5));
+                        style.push(LineStyle(DashType::Dash));
+                    }
+                    Style::Points => {
+                        style.push(PointSymbol('o'));
+                        style.push(PointSize(0.6));
+                    }
+                    Style::LinePoints => style.push(PointSymbol('O')),
+                }
+                let (x, y) = (&trace.x, &trace.y);
+                match trace.style {
+                    Style::Line | Style::Dashed => ax.lines(x, y, &style),
+                    Style::Points => ax.points(x, y, &style),
+                    Style::LinePoints => ax.lines_points(x, y, &style),
+                };
+            }
+            for note in &panel.notes {
+                ax.points([note.x], [note.y], &[LineColor("black"), PointSymbol('O')]);
+                ax.label(
+                    &note.text,
+                    Axis(note.x),
+                    Axis(note.y),
+                    &[TextOffset(1.0, 1.0)],
+                );
+            }
+        }
+        fg.show()
+            .map_err(|e| error!("Cannot run gnuplot: {:?}", e))?;
+        fg.close();
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use plotters::style::Color as _;
+
+use super::{Color, Figure, Format, Panel, PlotOptions, Plotter, Style};
+use crate::Error;
+
+/// Pure-Rust backend built on `plotters`, writing PNG or SVG.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Native;
+
+impl Plotter for Native {
+    fn render(&self, figure: &Figure, options: &PlotOptions) -> Result<(), Error> {
+        let size = (options.width, options.height);
+        match options.format {
+            Format::Png => draw(
+                BitMapBackend::new(&options.path, size).into_drawing_area(),
+                figure,
+            ),
+            Format::Svg => draw(
+                SVGBackend::new(&options.path, size).into_drawing_area(),
+                figure,
+            ),
+            Format::Pdf => bail!("The native plot backend cannot write PDF; use gnuplot"),
+        }
+    }
+}
+
+fn failed<E: std::fmt::Display>(e: E) -> Error {
+    error!("Cannot draw the figure: {}", e)
+}
+
+fn draw<DB: DrawingBackend>(root: DrawingArea<DB, Shift>, figure: &Figure) -> Result<(), Error> {
+    root.fill(&WHITE).map_err(failed)?;
+    let areas = root.split_evenly((figure.rows(), figure.columns));
+    for (panel, area) in figure.panels.iter().zip(&areas) {
+        draw_panel(area, panel)?;
+    }
+    root.present().map_err(failed)
+}
+
+fn draw_panel<DB: DrawingBackend>(
+    area: &DrawingArea<DB, Shift>,
+    panel: &Panel,
+) -> Result<(), Error> {
+    let ((x0, x1), (y0, y1)) = panel.bounds();
+    let mut builder = ChartBuilder::on(area);
+    builder
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60);
+    if !panel.title.is_empty() {
+        builder.caption(&panel.title, ("sans-serif", 18));
+    }
+    let mut chart = builder.build_cartesian_2d(x0..x1, y0..y1).map_err(failed)?;
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .x_desc(panel.x_label.as_str())
+        .y_desc(panel.y_label.as_str())
+        .draw()
+        .map_err(failed)?;
+    chart
+        .draw_series(
+            panel
+                .spans
+                .iter()
+                .map(|s| Rectangle::new([(s.lo, y0), (s.hi, y1)], rgb(s.color).mix(0.15).filled())),
+        )
+        .map_err(failed)?;
+
+    for trace in &panel.traces {
+        let color = rgb(trace.color);
+        let points = trace
+            .x
+            .iter()
+            .zip(&trace.y)
+            .filter(|(x, y)| x.is_finite() && y.is_finite())
+            .map(|(&x, &y)| (x, y))
+            .collect::<Vec<_>>();
+        let line = color.stroke_width(2);
+        let drawn = match trace.style {
+            Style::Line => chart.draw_series(LineSeries::new(points, line)),
+            Style::Dashed => chart.draw_series(DashedLineSeries::new(points, 8, 5, line)),
+            Style::Points => chart.draw_series(
+                points
+                    .into_iter()
+                    .map(|p| Circle::new(p, 2, color.filled())),
+            ),
+            Style::LinePoints => {
+                chart
+                    .draw_series(LineSeries::new(points.clone(), line))
+                    .map_err(failed)?;
+                chart.draw_series(
+                    points
+                        .into_iter()
+                        .map(|p| Circle::new(p, 4, color.filled())),
+                )
+            }
+        }
+        .map_err(failed)?;
+        if !trace.label.is_empty() {
+            drawn
+                .label(trace.label.as_str())
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], line));
+        }
+    }
+
+    let notes = panel
+        .notes
+        .iter()
+        .filter(|n| n.x.is_finite() && n.y.is_finite());
+    chart
+        .draw_series(notes.map(|n| {
+            EmptyElement::at((n.x, n.y))
+                + Circle::new((0, 0), 3, BLACK.filled())
+                + Text::new(n.text.clone(), (6, -18), ("sans-serif", 14))
+        }))
+        .map_err(failed)?;
+
+    if panel.traces.iter().any(|t| !t.label.is_empty()) {
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::UpperRight)
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()
+            .map_err(failed)?;
+    }
+    Ok(())
+}
+
+fn rgb(color: Color) -> RGBColor {
+    RGBColor(color.0, color.1, color.2)
+}
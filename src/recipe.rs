@@ -0,0 +1,748 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::value::{Table, Value};
+
+use crate::columns::{Columns, Detection};
+use crate::config::{ElementConfig, Ini};
+use crate::elem::{Composition, Database};
+use crate::pipeline::{
+    Align, Background, Crop, Deglitch, Normalize, Pipeline, Resample, Smooth, Step,
+};
+use crate::plot::{Backend, PlotOptions};
+use crate::selfabs::SelfAbsorption;
+use crate::spectrum::Spectrum;
+use crate::tey::{Attenuation, TeySaturation};
+use crate::xdi::Xdi;
+use crate::Error;
+
+/// Names of the step kinds a recipe accepts.
+const STEP_KINDS: &str =
+    "deglitch, normalize, align, smooth, background, resample, crop, selfabs, tey";
+
+/// Step kinds that model normalized spectra and so need a `normalize`
+/// step before them.
+const NEEDS_NORMALIZE: &[&str] = &["selfabs", "tey"];
+
+/// Largest `width` or `height` of a plot.
+const MAX_PIXELS: u32 = 10_000;
+
+/// A complete analysis described in TOML:
+///
+/// ```toml
+/// [input]
+/// plus = "Fe_1.txt"         # or `file`, or `manifest`
+/// minus = "Fe_2.txt"
+/// mode = "yield"            # transmission, fluorescence, yield or mu
+///
+/// [input.columns]           # names or zero-based indices
+/// energy = 0
+/// i0 = 1
+/// signal = 2
+///
+/// [element]
+/// symbol = "Fe"
+/// config = "element.ini"
+///
+/// [[step]]
+/// kind = "smooth"
+/// half_width = 2
+///
+/// [sumrules]
+/// errors = true
+///
+/// [output]
+/// xmcd = "fe_xmcd.txt"
+/// plot = "fe.png"
+/// ```
+///
+/// A `plus` and `minus` pair of `.xdi` files without `mode` or `columns`
+/// is read as their column labels say. Relative paths are taken from the
+/// directory of the recipe, including the `elem` database of a `selfabs`
+/// or `tey` step, `elem.dat` by default. Unknown
+/// tables and keys, and values of the wrong type, are errors.
+#[derive(Debug)]
+pub struct Recipe {
+    pub source: Source,
+    pub element: String,
+    pub config: PathBuf,
+    /// Overrides of the config values.
+    pub angle: Option<f64>,
+    pub pc: Option<f64>,
+    pub holes: Option<f64>,
+    pub tz_ratio: Option<f64>,
+    pub spin_correction: Option<f64>,
+    /// Applied to each helicity spectrum before the XMCD.
+    pub pipeline: Pipeline,
+    /// Monte Carlo errors with the `jitter.*` keys of the config.
+    pub errors: bool,
+    pub seed: u64,
+    pub output: Output,
+}
+
+/// Where the helicity spectra come from. Columns are given by header name
+/// or zero-based index.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// One file with energy, μ+ and μ− columns, and an I0 column unless
+    /// the mode is `mu`.
+    File {
+        path: PathBuf,
+        mode: Detection,
+        energy: String,
+        i0: Option<String>,
+        plus: String,
+        minus: String,
+    },
+    /// One scan per helicity with the same columns.
+    Pair {
+        plus: PathBuf,
+        minus: PathBuf,
+        mode: Detection,
+        energy: String,
+        i0: Option<String>,
+        signal: String,
+    },
+    /// One XDI file per helicity, mapped by its column labels.
+    Xdi { plus: PathBuf, minus: PathBuf },
+    /// `value path` lines of three-column files, each run through the
+    /// steps and processed into a series table.
+    Manifest { path: PathBuf, parameter: String },
+}
+
+/// Files written by a recipe; the sum rules, or the series table of a
+/// manifest, go to stdout unless given. An `xmcd` or `table` file ending in
+/// `.csv`, `.json` or `.xdi` is written in that format, with the results
+/// and the processing steps; other names get plain columns.
+#[derive(Debug, Clone, Default)]
+pub struct Output {
+    pub xmcd: Option<PathBuf>,
+    pub sumrules: Option<PathBuf>,
+    pub table: Option<PathBuf>,
+    pub plot: Option<PathBuf>,
+    pub backend: Option<Backend>,
+    pub size: Option<(u32, u32)>,
+}
+
+impl Output {
+    pub fn plot_options(&self) -> Result<Option<PlotOptions>, Error> {
+        let path = match &self.plot {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let mut options = PlotOptions::new(path)?;
+        if let Some(backend) = self.backend {
+            options = options.backend(backend);
+        }
+        if let Some((width, height)) = self.size {
+            options = options.size(width, height);
+        }
+        Ok(Some(options))
+    }
+}
+
+impl Recipe {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Recipe, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| error!("{}: {}", path.display(), e))?;
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        Recipe::parse(&text, base).map_err(|e| error!("{}: {}", path.display(), e))
+    }
+
+    /// Reads a recipe whose relative paths start at `base`.
+    pub fn parse(text: &str, base: &Path) -> Result<Recipe, Error> {
+        let table = toml::from_str::<Table>(text).map_err(|e| error!("{}", e))?;
+        let mut root = Fields::new("", Value::Table(table))?;
+        root.allow(&["input", "element", "step", "sumrules", "output"])?;
+        let path = |s: String| base.join(s);
+
+        let mut input = root.table("input")?;
+        let mut columns = input.table("columns")?;
+        let has_columns = !columns.is_empty();
+        let file = input.string("file")?.map(path);
+        let plus = input.string("plus")?.map(path);
+        let minus = input.string("minus")?.map(path);
+        let manifest = input.string("manifest")?.map(path);
+        let parameter = input.string("parameter")?;
+        let mode = input
+            .string("mode")?
+            .map(|m| m.parse().map_err(|e| error!("input.mode: {}", e)))
+            .transpose()?;
+        let energy = columns.column("energy")?;
+        let i0 = columns.column("i0")?;
+        let plus_column = columns.column("plus")?;
+        let minus_column = columns.column("minus")?;
+        let signal = columns.column("signal")?;
+        columns.finish()?;
+        input.finish()?;
+
+        let energy = energy.unwrap_or_else(|| "0".to_string());
+        let source = match (file, plus, minus, manifest) {
+            (Some(path), None, None, None) => {
+                if signal.is_some() {
+                    bail!("input.columns: `signal` applies to `plus` and `minus` scans; use `plus` and `minus` columns");
+                }
+                let mode = mode.unwrap_or(Detection::Mu);
+                let default = if i0.is_some() { (2, 3) } else { (1, 2) };
+                Source::File {
+                    path,
+                    mode,
+                    energy,
+                    i0,
+                    plus: plus_column.unwrap_or_else(|| default.0.to_string()),
+                    minus: minus_column.unwrap_or_else(|| default.1.to_string()),
+                }
+            }
+            (None, Some(plus), Some(minus), None)
+                if mode.is_none() && !has_columns && is_xdi(&plus) && is_xdi(&minus) =>
+            {
+                Source::Xdi { plus, minus }
+            }
+            (None, Some(plus), Some(minus), None) => {
+                if plus_column.is_some() || minus_column.is_some() {
+                    bail!(
+                        "input.columns: `plus` and `minus` apply to a single `file`; use `signal`"
+                    );
+                }
+                let mode = mode.unwrap_or(Detection::Yield);
+                let i0 = match (mode, i0) {
+                    (Detection::Mu, i0) => i0,
+                    (_, i0) => Some(i0.unwrap_or_else(|| "1".to_string())),
+                };
+                let default = if i0.is_some() { "2" } else { "1" };
+                Source::Pair {
+                    plus,
+                    minus,
+                    mode,
+                    energy,
+                    i0,
+                    signal: signal.unwrap_or_else(|| default.to_string()),
+                }
+            }
+            (None, None, None, Some(path)) => {
+                if mode.is_some() || has_columns {
+                    bail!("input: `mode` and `columns` do not apply to a manifest");
+                }
+                Source::Manifest {
+                    path,
+                    parameter: parameter.clone().unwrap_or_else(|| "value".to_string()),
+                }
+            }
+            _ => bail!("input: give either `file`, both `plus` and `minus`, or `manifest`"),
+        };
+        if parameter.is_some() && !matches!(source, Source::Manifest { .. }) {
+            bail!("input: `parameter` only applies to a manifest");
+        }
+
+        let mut element = root.table("element")?;
+        let symbol = element.require_string("symbol")?;
+        let config = path(
+            element
+                .string("config")?
+                .unwrap_or_else(|| "element.ini".to_string()),
+        );
+        let angle = element.number("angle")?;
+        let pc = element.number("pc")?;
+        element.finish()?;
+
+        let mut pipeline = Pipeline::new();
+        for (i, mut step) in root.tables("step")?.into_iter().enumerate() {
+            step.path = format!("step {}", i + 1);
+            let built = build_step(&mut step, base)?;
+            if NEEDS_NORMALIZE.contains(&built.name())
+                && pipeline.steps.iter().all(|s| s.name() != "normalize")
+            {
+                bail!(
+                    "{}: a `{}` step needs a `normalize` step before it",
+                    step.path,
+                    built.name()
+                );
+            }
+            pipeline.push(built);
+            step.finish()?;
+        }
+
+        let mut sumrules = root.table("sumrules")?;
+        let errors = sumrules.boolean("errors")?.unwrap_or(false);
+        let seed = sumrules.integer("seed")?.unwrap_or(0);
+        let holes = sumrules.number("holes")?;
+        let tz_ratio = sumrules.number("tz_ratio")?;
+        let spin_correction = sumrules.number("spin_correction")?;
+        sumrules.finish()?;
+
+        let mut output = root.table("output")?;
+        let out = Output {
+            xmcd: output.string("xmcd")?.map(path),
+            sumrules: output.string("sumrules")?.map(path),
+            table: output.string("table")?.map(path),
+            plot: output.string("plot")?.map(path),
+            backend: output.string("backend")?.map(|b| b.parse()).transpose()?,
+            size: match (output.pixels("width")?, output.pixels("height")?) {
+                (Some(w), Some(h)) => Some((w, h)),
+                (None, None) => None,
+                _ => bail!("output: give both `width` and `height`"),
+            },
+        };
+        output.finish()?;
+        root.finish()?;
+
+        let manifest = matches!(source, Source::Manifest { .. });
+        if manifest && (out.xmcd.is_some() || out.sumrules.is_some() || errors) {
+            bail!("output: a manifest writes a `table`, not `xmcd` or `sumrules`");
+        }
+        if !manifest && out.table.is_some() {
+            bail!("output: `table` only applies to a manifest");
+        }
+
+        Ok(Recipe {
+            source,
+            element: symbol,
+            config,
+            angle,
+            pc,
+            holes,
+            tz_ratio,
+            spin_correction,
+            pipeline,
+            errors,
+            seed: seed as u64,
+            output: out,
+        })
+    }
+
+    /// The element section of `ini` with the overrides of the recipe.
+    pub fn element_config(&self, ini: &Ini) -> Result<ElementConfig, Error> {
+        let mut config = ElementConfig::new(ini, &self.element)?;
+        if let Some(angle) = self.angle {
+            config.angle = angle;
+        }
+        if let Some(pc) = self.pc {
+            config.pc = pc;
+        }
+        if let Some(holes) = self.holes {
+            config.holes = holes;
+        }
+        if let Some(tz_ratio) = self.tz_ratio {
+            config.tz_ratio = tz_ratio;
+        }
+        if let Some(spin_correction) = self.spin_correction {
+            config.spin_correction = spin_correction;
+        }
+        Ok(config)
+    }
+
+    /// μ+ and μ− after the steps of the recipe; an error for a manifest.
+    pub fn spectra(&self) -> Result<(Spectrum, Spectrum), Error> {
+        let load = |path: &Path, i0: &Option<String>, signal: &str, energy: &str, mode| {
+            Columns::load(path)
+                .and_then(|table| table.spectrum(energy, i0.as_deref(), signal, mode))
+                .map(|s| s.with_metadata("Scan.file", &path.display().to_string()))
+                .map_err(|e| error!("{}: {}", path.display(), e))
+        };
+        let (plus, minus) = match &self.source {
+            Source::File {
+                path,
+                mode,
+                energy,
+                i0,
+                plus,
+                minus,
+            } => (
+                load(path, i0, plus, energy, *mode)?,
+                load(path, i0, minus, energy, *mode)?,
+            ),
+            Source::Pair {
+                plus,
+                minus,
+                mode,
+                energy,
+                i0,
+                signal,
+            } => (
+                load(plus, i0, signal, energy, *mode)?,
+                load(minus, i0, signal, energy, *mode)?,
+            ),
+            Source::Xdi { plus, minus } => {
+                let load = |path: &Path| {
+                    Xdi::load(path)
+                        .and_then(|xdi| xdi.spectrum())
+                        .map(|s| s.with_metadata("Scan.file", &path.display().to_string()))
+                        .map_err(|e| error!("{}: {}", path.display(), e))
+                };
+                (load(plus)?, load(minus)?)
+            }
+            Source::Manifest { .. } => bail!("A manifest holds no single pair of spectra"),
+        };
+        Ok((self.pipeline.run(&plus)?, self.pipeline.run(&minus)?))
+    }
+}
+
+fn is_xdi(path: &Path) -> bool {
+    path.extension()
+        .filter(|e| e.eq_ignore_ascii_case("xdi"))
+        .is_some()
+}
+
+/// One `[[step]]` table; `elem` paths start at `base`.
+fn build_step(fields: &mut Fields, base: &Path) -> Result<Box<dyn Step>, Error> {
+    let kind = fields.require_string("kind")?;
+    let step: Box<dyn Step> = match kind.as_str() {
+        "deglitch" => Box::new(Deglitch {
+            threshold: fields
+                .number("threshold")?
+                .unwrap_or(Deglitch::default().threshold),
+        }),
+        "normalize" => Box::new(Normalize {
+            pre: (
+                fields.require_number("pre_start")?,
+                fields.require_number("pre_end")?,
+            ),
+            post: (
+                fields.require_number("post_start")?,
+                fields.require_number("post_end")?,
+            ),
+        }),
+        "align" => Box::new(Align {
+            e0: fields.require_number("e0")?,
+        }),
+        "smooth" => Box::new(Smooth {
+            half_width: fields
+                .integer("half_width")?
+                .ok_or_else(|| fields.missing("half_width"))? as usize,
+        }),
+        "background" => Box::new(Background {
+            start: fields.require_number("start")?,
+            end: fields.require_number("end")?,
+        }),
+        "resample" => Box::new(Resample {
+            start: fields.require_number("start")?,
+            stop: fields.require_number("stop")?,
+            step: fields.require_number("step")?,
+        }),
+        "crop" => Box::new(Crop {
+            start: fields.require_number("start")?,
+            end: fields.require_number("end")?,
+        }),
+        "selfabs" => Box::new(SelfAbsorption {
+            db: database(fields, base)?,
+            composition: composition(fields)?,
+            absorber: fields.require_string("absorber")?,
+            edge: fields.require_string("edge")?,
+            incidence_from_surface: fields.require_number("incidence_from_surface")?,
+            exit_from_surface: fields.require_number("exit_from_surface")?,
+            thickness: fields.number("thickness")?,
+            norm_energy: fields.number("norm_energy")?,
+        }),
+        "tey" => {
+            // The measured scale if given, otherwise the tables.
+            let attenuation = match fields.number("edge_jump")? {
+                Some(edge_jump) => Attenuation::Measured {
+                    pre_edge: fields.require_number("pre_edge")?,
+                    edge_jump,
+                },
+                None => Attenuation::Tables {
+                    db: database(fields, base)?,
+                    composition: composition(fields)?,
+                    absorber: fields.require_string("absorber")?,
+                    edge: fields.require_string("edge")?,
+                },
+            };
+            Box::new(TeySaturation {
+                attenuation,
+                escape_depth: fields.require_number("escape_depth")?,
+                angle_from_normal: fields.require_number("angle_from_normal")?,
+                norm_energy: fields.number("norm_energy")?,
+            })
+        }
+        _ => bail!(
+            "{}: unknown kind {:?}; expected one of {}",
+            fields.path,
+            kind,
+            STEP_KINDS
+        ),
+    };
+    Ok(step)
+}
+
+/// The `elem` database of a step, `elem.dat` by default.
+fn database(fields: &mut Fields, base: &Path) -> Result<Database, Error> {
+    let path = base.join(
+        fields
+            .string("elem")?
+            .unwrap_or_else(|| "elem.dat".to_string()),
+    );
+    Database::load(&path).map_err(|e| error!("{}: {}", path.display(), e))
+}
+
+/// The `formula` and `density` of a step.
+fn composition(fields: &mut Fields) -> Result<Composition, Error> {
+    let formula = fields.require_string("formula")?;
+    let density = fields.require_number("density")?;
+    Composition::new(&formula, density).map_err(|e| error!("{}: {}", fields.key("formula"), e))
+}
+
+/// Keys of one recipe table, taken one at a time so that whatever is left
+/// can be reported as unknown, together with the keys that were asked for.
+struct Fields {
+    path: String,
+    table: Table,
+    known: Vec<&'static str>,
+}
+
+impl Fields {
+    fn new(path: &str, value: Value) -> Result<Fields, Error> {
+        match value {
+            Value::Table(table) => Ok(Fields {
+                path: path.to_string(),
+                table,
+                known: Vec::new(),
+            }),
+            value => bail!("{}: expected a table, found {}", path, value.type_str()),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        if self.path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", self.path, key)
+        }
+    }
+
+    fn take(&mut self, key: &'static str) -> Option<Value> {
+        self.known.push(key);
+        self.table.remove(key)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    fn mismatch(&self, key: &str, expected: &str, value: &Value) -> Error {
+        let shown = match value {
+            Value::Table(_) => "{...}".to_string(),
+            value => value.to_string(),
+        };
+        error!(
+            "{}: expected {}, found {} {}",
+            self.key(key),
+            expected,
+            value.type_str(),
+            shown
+        )
+    }
+
+    /// The table at `key`; empty if missing.
+    fn table(&mut self, key: &'static str) -> Result<Fields, Error> {
+        let path = self.key(key);
+        match self.take(key) {
+            Some(value) => Fields::new(&path, value),
+            None => Fields::new(&path, Value::Table(Table::new())),
+        }
+    }
+
+    /// The tables of the `[[key]]` array; empty if missing.
+    fn tables(&mut self, key: &'static str) -> Result<Vec<Fields>, Error> {
+        let path = self.key(key);
+        match self.take(key) {
+            None => Ok(Vec::new()),
+            Some(Value::Array(values)) => values
+                .into_iter()
+                .map(|value| Fields::new(&path, value))
+                .collect(),
+            Some(value) => Err(self.mismatch(key, "an array of tables", &value)),
+        }
+    }
+
+    fn string(&mut self, key: &'static str) -> Result<Option<String>, Error> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(value) => Err(self.mismatch(key, "a string", &value)),
+        }
+    }
+
+    fn number(&mut self, key: &'static str) -> Result<Option<f64>, Error> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(Value::Integer(i)) => Ok(Some(i as f64)),
+            Some(Value::Float(x)) if x.is_finite() => Ok(Some(x)),
+            Some(value) => Err(self.mismatch(key, "a finite number", &value)),
+        }
+    }
+
+    /// A non-negative integer.
+    fn integer(&mut self, key: &'static str) -> Result<Option<i64>, Error> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(Value::Integer(i)) if i >= 0 => Ok(Some(i)),
+            Some(value) => Err(self.mismatch(key, "a non-negative integer", &value)),
+        }
+    }
+
+    /// An image size in pixels, from 1 to [`MAX_PIXELS`].
+    fn pixels(&mut self, key: &'static str) -> Result<Option<u32>, Error> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(Value::Integer(i)) if (1..=i64::from(MAX_PIXELS)).contains(&i) => {
+                Ok(Some(i as u32))
+            }
+            Some(value) => {
+                let expected = format!("a size from 1 to {} pixels", MAX_PIXELS);
+                Err(self.mismatch(key, &expected, &value))
+            }
+        }
+    }
+
+    fn boolean(&mut self, key: &'static str) -> Result<Option<bool>, Error> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(Value::Boolean(b)) => Ok(Some(b)),
+            Some(value) => Err(self.mismatch(key, "true or false", &value)),
+        }
+    }
+
+    /// A column name or zero-based index.
+    fn column(&mut self, key: &'static str) -> Result<Option<String>, Error> {
+        match self.take(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(Value::Integer(i)) if i >= 0 => Ok(Some(i.to_string())),
+            Some(value) => Err(self.mismatch(key, "a column name or index", &value)),
+        }
+    }
+
+    fn require_string(&mut self, key: &'static str) -> Result<String, Error> {
+        self.string(key)?.ok_or_else(|| self.missing(key))
+    }
+
+    fn require_number(&mut self, key: &'static str) -> Result<f64, Error> {
+        self.number(key)?.ok_or_else(|| self.missing(key))
+    }
+
+    /// Fails on the first key that was never asked for.
+    fn finish(self) -> Result<(), Error> {
+        self.check(&self.known)
+    }
+
+    /// Fails on the first key not in `known`, before any is taken.
+    fn allow(&self, known: &[&str]) -> Result<(), Error> {
+        self.check(known)
+    }
+
+    fn check(&self, known: &[&str]) -> Result<(), Error> {
+        if let Some(key) = self.table.keys().find(|k| !known.contains(&k.as_str())) {
+            let mut known = known.to_vec();
+            known.sort_unstable();
+            known.dedup();
+            let place = if self.path.is_empty() {
+                "recipe"
+            } else {
+                &self.path
+            };
+            bail!(
+                "{}: unknown key `{}`; expected one of {}",
+                place,
+                key,
+                known.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// A required key is missing; a misspelling of it would still be among
+    /// the keys left.
+    fn missing(&self, key: &str) -> Error {
+        let left = self.table.keys().cloned().collect::<Vec<_>>();
+        if left.is_empty() {
+            error!("{}: missing `{}`", self.path, key)
+        } else {
+            error!(
+                "{}: missing `{}`; unknown keys: {}",
+                self.path,
+                key,
+                left.join(", ")
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAIR: &str = "[input]\nplus = \"p.txt\"\nminus = \"m.txt\"\n\
+        [element]\nsymbol = \"Co\"\n";
+
+    const NORMALIZE: &str = "[[step]]\nkind = \"normalize\"\npre_start = 760\npre_end = 770\n\
+        post_start = 806\npost_end = 820\n[[step]]";
+
+    fn parse(extra: &str) -> Result<Recipe, Error> {
+        Recipe::parse(&format!("{}{}", PAIR, extra), Path::new("."))
+    }
+
+    #[test]
+    fn plot_size_is_checked() {
+        let recipe = parse("[output]\nplot = \"x.png\"\nwidth = 800\nheight = 600\n").unwrap();
+        assert_eq!(recipe.output.size, Some((800, 600)));
+        for size in &["0", "-1", "4294967296", "1.5"] {
+            let text = format!("[output]\nwidth = {}\nheight = 600\n", size);
+            let error = parse(&text).unwrap_err().to_string();
+            assert!(
+                error.starts_with("output.width: expected a size"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn schema_errors_name_the_key() {
+        let error = parse("[output]\nxmdc = \"x.txt\"\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("unknown key `xmdc`"), "{}", error);
+        let error = parse("[[step]]\nkind = \"smooth\"\nhalf_width = \"2\"\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("step 1.half_width"), "{}", error);
+        let error = parse("[sumrules]\nerrors = \n").unwrap_err().to_string();
+        assert!(error.contains("line 7"), "{}", error);
+    }
+
+    #[test]
+    fn selfabs_step_records_the_sample() {
+        let selfabs = format!(
+            "{}[[step]]\nkind = \"selfabs\"\nformula = \"CoO\"\ndensity = 6.44\n\
+             absorber = \"Co\"\nedge = \"L3\"\nincidence_from_surface = 45\nexit_from_surface = 45\n",
+            PAIR
+        );
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+        let error = Recipe::parse(&selfabs, &data).unwrap_err().to_string();
+        assert!(error.contains("needs a `normalize` step"), "{}", error);
+
+        let text = selfabs.replace("[[step]]", NORMALIZE);
+        let recipe = Recipe::parse(&text, &data).unwrap();
+        assert_eq!(
+            recipe.pipeline.steps[1].describe(),
+            "selfabs formula=CoO absorber=Co edge=L3 density=6.44 incidence_from_surface=45 exit_from_surface=45"
+        );
+        let error = Recipe::parse(&text.replace("density = 6.44\n", ""), &data)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("missing `density`"), "{}", error);
+    }
+
+    #[test]
+    fn tey_step_takes_the_measured_scale() {
+        let tey = "[[step]]\nkind = \"tey\"\nescape_depth = 2.5\nangle_from_normal = 60\n\
+                   pre_edge = 1e4\nedge_jump = 3e4\n";
+        let error = parse(tey).unwrap_err().to_string();
+        assert!(error.starts_with("step 1: a `tey` step needs"), "{}", error);
+        let recipe = parse(&tey.replace("[[step]]", NORMALIZE)).unwrap();
+        assert_eq!(
+            recipe.pipeline.steps[1].describe(),
+            "tey escape_depth=2.5 angle_from_normal=60 pre_edge=10000 edge_jump=30000"
+        );
+    }
+}
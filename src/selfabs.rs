@@ -0,0 +1,286 @@
+use crate::elem::{Composition, Database, Edge, Element};
+use crate::pipeline::Step;
+use crate::spectrum::Spectrum;
+use crate::xmcd::Xmcd;
+use crate::Error;
+
+const MAX_ITERATIONS: usize = 100;
+
+/// Self-absorption (saturation) correction of fluorescence-yield spectra.
+///
+/// The measured, edge-normalized yield of an absorber with absorption
+/// `μa(E)` is modelled as
+///
+/// `I(E) ∝ μa(E) / (μt(E) + g·μt(Ef)) · (1 − exp(−(μt(E) + g·μt(Ef))·d / sin φ))`
+///
+/// with `g = sin φ / sin θ`, `φ` and `θ` the incidence and exit angles
+/// from the surface and `Ef` the fluorescence energy. All cross sections
+/// except the absorber's own edge come from `elem.dat`; `μa(E)` is solved
+/// for and returned normalized to one at the normalization energy.
+///
+/// As a pipeline [`Step`] it needs the spectrum normalized to unit edge
+/// jump; a recipe only accepts it after a `normalize` step.
+#[derive(Debug, Clone)]
+pub struct SelfAbsorption {
+    /// Cross sections and emission lines, from `elem.dat`.
+    pub db: Database,
+    pub composition: Composition,
+    pub absorber: String,
+    pub edge: String,
+    /// Incidence angle from the sample surface, in degrees.
+    pub incidence_from_surface: f64,
+    /// Detector (exit) angle from the sample surface, in degrees.
+    pub exit_from_surface: f64,
+    /// Sample thickness in nm; `None` uses the closed-form thick limit,
+    /// otherwise the finite-thickness equation is solved iteratively.
+    pub thickness: Option<f64>,
+    /// Energy at which a spectrum corrected as a pipeline step is
+    /// normalized to one; `None` takes its highest energy.
+    pub norm_energy: Option<f64>,
+}
+
+/// Splits the attenuation of a sample into the jump at one absorber edge
+/// and everything else.
+pub(crate) struct Absorber<'a> {
+    db: &'a Database,
+    composition: &'a Composition,
+    element: &'a Element,
+    pub edge: &'a Edge,
+    weight: f64,
+}
+
+impl<'a> Absorber<'a> {
+    pub fn new(
+        db: &'a Database,
+        composition: &'a Composition,
+        absorber: &str,
+        edge: &str,
+    ) -> Result<Absorber<'a>, Error> {
+        let fractions = composition.mass_fractions(db)?;
+        let (element, weight) = fractions
+            .into_iter()
+            .find(|(e, _)| e.symbol.eq_ignore_ascii_case(absorber))
+            .ok_or_else(|| error!("{} is not part of the sample", absorber))?;
+        let edge = element
+            .edge(edge)
+            .ok_or_else(|| error!("{} has no {} edge", element.symbol, edge))?;
+        Ok(Absorber {
+            db,
+            composition,
+            element,
+            edge,
+            weight,
+        })
+    }
+
+    /// Absorption due to the edge jump at `energy`, in 1/cm.
+    pub fn edge_jump(&self, energy: f64) -> f64 {
+        let el = self.element;
+        self.composition.density
+            * self.weight
+            * (el.photo(energy) - el.photo_below(self.edge, energy))
+    }
+
+    /// Attenuation without the edge jump at `energy`, in 1/cm.
+    pub fn background(&self, energy: f64) -> Result<f64, Error> {
+        Ok(self.composition.attenuation(self.db, energy)? - self.edge_jump(energy))
+    }
+}
+
+impl SelfAbsorption {
+    /// Corrects one normalized spectrum; `norm_energy` is where it equals one.
+    pub fn correct(&self, spectrum: &Spectrum, norm_energy: f64) -> Result<Spectrum, Error> {
+        let db = &self.db;
+        let (energy, normalized) = (spectrum.energy(), spectrum.values());
+        let absorber = Absorber::new(db, &self.composition, &self.absorber, &self.edge)?;
+        let fluorescence = absorber
+            .edge
+            .fluorescence_energy()
+            .ok_or_else(|| error!("No emission lines for the {} edge", absorber.edge.name))?;
+
+        let sin_in = self.incidence_from_surface.to_radians().sin();
+        let sin_out = self.exit_from_surface.to_radians().sin();
+        if sin_in <= 0.0 || sin_out <= 0.0 {
+            bail!("Incidence and exit angles must be above the surface");
+        }
+        let g = sin_in / sin_out;
+        let mu_f = self.composition.attenuation(db, fluorescence)?;
+
+        let alpha = |e: f64| -> Result<f64, Error> { Ok(absorber.background(e)? + g * mu_f) };
+
+        let mu_n = absorber.edge_jump(norm_energy);
+        if mu_n <= 0.0 {
+            bail!("Normalization energy {} eV is below the edge", norm_energy);
+        }
+        let alpha_n = alpha(norm_energy)?;
+        let alphas = energy
+            .iter()
+            .map(|&e| alpha(e))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Thick limit: m = c·μa / (μa + α), with m(En) = 1.
+        let c = (mu_n + alpha_n) / mu_n;
+        let mut mu_a = normalized
+            .iter()
+            .zip(&alphas)
+            .map(|(&m, &a)| {
+                if self.thickness.is_none() && m >= c {
+                    bail!("Normalized yield {} exceeds the saturation limit {}", m, c);
+                }
+                Ok(m * a / (c - m).abs())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        if let Some(thickness) = self.thickness {
+            let d = thickness * 1e-7;
+            // Finite thickness: solve k·μa·T(μa) = m·(μa + α) per point by
+            // bisection, starting from the thick-limit bracket.
+            let transmission = |mu: f64, a: f64| 1.0 - (-(mu + a) * d / sin_in).exp();
+            let k = c / transmission(mu_n, alpha_n);
+            for i in 0..mu_a.len() {
+                let (m, a) = (normalized[i], alphas[i]);
+                if m <= 0.0 {
+                    mu_a[i] = m * a / (k * transmission(0.0, a) - m);
+                    continue;
+                }
+                let f = |mu: f64| k * mu * transmission(mu, a) - m * (mu + a);
+                let mut hi = mu_a[i].max(mu_n);
+                let mut n = 0;
+                while f(hi) < 0.0 {
+                    hi *= 2.0;
+                    n += 1;
+                    if n > MAX_ITERATIONS {
+                        bail!("Normalized yield {} exceeds the saturation limit", m);
+                    }
+                }
+                let mut lo = 0.0;
+                for _ in 0..MAX_ITERATIONS {
+                    let mid = 0.5 * (lo + hi);
+                    if f(mid) < 0.0 {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
+                    if hi - lo < 1e-12 * mu_n {
+                        break;
+                    }
+                }
+                mu_a[i] = 0.5 * (lo + hi);
+            }
+        }
+
+        spectrum.with_values(mu_a.iter().map(|mu| mu / mu_n).collect())
+    }
+
+    /// Corrects both helicities of an XMCD measurement at its
+    /// [`norm_energy`](Xmcd::norm_energy).
+    pub fn apply(&self, xmcd: &Xmcd) -> Result<Xmcd, Error> {
+        let norm_energy = xmcd.norm_energy();
+        xmcd.correct_with(self.describe(), |mu| self.correct(mu, norm_energy))
+    }
+}
+
+impl Step for SelfAbsorption {
+    fn name(&self) -> &'static str {
+        "selfabs"
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, f64)> {
+        let mut parameters = vec![
+            ("density", self.composition.density),
+            ("incidence_from_surface", self.incidence_from_surface),
+            ("exit_from_surface", self.exit_from_surface),
+        ];
+        if let Some(thickness) = self.thickness {
+            parameters.push(("thickness", thickness));
+        }
+        if let Some(norm_energy) = self.norm_energy {
+            parameters.push(("norm_energy", norm_energy));
+        }
+        parameters
+    }
+
+    fn apply(&self, spectrum: &Spectrum) -> Result<Spectrum, Error> {
+        let norm_energy = self.norm_energy.unwrap_or_else(|| spectrum.range().1);
+        self.correct(spectrum, norm_energy)
+    }
+
+    fn describe(&self) -> String {
+        let mut text = format!(
+            "{} formula={} absorber={} edge={}",
+            self.name(),
+            self.composition,
+            self.absorber,
+            self.edge
+        );
+        for (key, value) in self.parameters() {
+            text += &format!(" {}={}", key, value);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    /// Normalized yield rising through the Co L3 edge to a white line of 1.5
+    /// and settling at one.
+    fn yield_spectrum() -> Spectrum {
+        let energy = (0..=400)
+            .map(|i| 770.0 + 0.1 * i as f64)
+            .collect::<Vec<_>>();
+        let values = energy
+            .iter()
+            .map(|&e| {
+                let step = 0.5 + ((e - 778.1) / 0.5).atan() / std::f64::consts::PI;
+                step + 0.5 * (-((e - 778.6) / 1.0).powi(2)).exp()
+            })
+            .collect();
+        Spectrum::new(energy, values).unwrap()
+    }
+
+    fn coo(thickness: Option<f64>, incidence: f64, exit: f64) -> SelfAbsorption {
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+        SelfAbsorption {
+            db: Database::load(data.join("elem.dat")).unwrap(),
+            composition: Composition::new("CoO", 6.44).unwrap(),
+            absorber: "Co".to_string(),
+            edge: "L3".to_string(),
+            incidence_from_surface: incidence,
+            exit_from_surface: exit,
+            thickness,
+            norm_energy: None,
+        }
+    }
+
+    /// Largest difference between the corrected and the measured yield.
+    fn change(correction: &SelfAbsorption) -> f64 {
+        let measured = yield_spectrum();
+        let corrected = Step::apply(correction, &measured).unwrap();
+        measured
+            .values()
+            .iter()
+            .zip(corrected.values())
+            .map(|(m, c)| (m - c).abs())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn thin_samples_and_grazing_exit_need_no_correction() {
+        // d ≪ 1/μ: the yield is proportional to μa.
+        let thin = change(&coo(Some(1e-4), 45.0, 45.0));
+        assert!(thin < 1e-5, "{}", thin);
+        // sin θ → 0: g·μt(Ef) outweighs μt(E), and the thick-limit yield
+        // μa / (μt + g·μt(Ef)) is proportional to μa.
+        let grazing = change(&coo(None, 45.0, 0.01));
+        assert!(grazing < 1e-3, "{}", grazing);
+        // A thick sample at 45° saturates the white line.
+        let thick = change(&coo(None, 45.0, 45.0));
+        assert!(thick > 0.1, "{}", thick);
+        let corrected = Step::apply(&coo(None, 45.0, 45.0), &yield_spectrum()).unwrap();
+        assert!(corrected.at(778.6) > yield_spectrum().at(778.6));
+    }
+}
@@ -0,0 +1,416 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::ElementConfig;
+use crate::export::Export;
+use crate::math::{self, parse_number};
+use crate::pipeline::Pipeline;
+use crate::plot::{Color, Figure, Panel, PlotOptions, Style};
+use crate::spectrum::{Metadata, Spectrum};
+use crate::sumrules::SumRules;
+use crate::xas::Xas;
+use crate::xmcd::Xmcd;
+use crate::Error;
+
+/// A set of helicity pairs labeled by an external parameter such as pump
+/// fluence or temperature.
+#[derive(Debug, Clone)]
+pub struct Series {
+    parameter: String,
+    config: ElementConfig,
+    points: Vec<SeriesPoint>,
+}
+
+/// One pair of a [`Series`]: μ+ and μ− as given and, if they reach the
+/// pre- and post-edge windows of the element, their XMCD.
+#[derive(Debug, Clone)]
+pub struct SeriesPoint {
+    value: f64,
+    plus: Spectrum,
+    minus: Spectrum,
+    xmcd: Option<Xmcd>,
+}
+
+impl SeriesPoint {
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    pub fn plus(&self) -> &Spectrum {
+        &self.plus
+    }
+
+    pub fn minus(&self) -> &Spectrum {
+        &self.minus
+    }
+
+    /// `None` if the spectra cannot be normalized, e.g. a scan over the
+    /// first edge alone.
+    pub fn xmcd(&self) -> Option<&Xmcd> {
+        self.xmcd.as_ref()
+    }
+}
+
+impl Series {
+    pub fn new(parameter: &str, config: &ElementConfig) -> Series {
+        Series {
+            parameter: parameter.to_string(),
+            config: config.clone(),
+            points: Vec::new(),
+        }
+    }
+
+    /// Adds a pair, with its XMCD if the spectra start before the end of
+    /// the pre-edge and reach the post-edge region.
+    pub fn push(&mut self, value: f64, plus: Spectrum, minus: Spectrum) -> Result<(), Error> {
+        let (first, last) = plus.matched(&minus)?.0.range();
+        let xmcd = if first <= self.config.pre_end() && last >= self.config.post_start() {
+            Some(Xmcd::new(&plus, &minus, &self.config)?)
+        } else {
+            None
+        };
+        self.points.push(SeriesPoint {
+            value,
+            plus,
+            minus,
+            xmcd,
+        });
+        self.points.sort_by(|a, b| a.value.total_cmp(&b.value));
+        Ok(())
+    }
+
+    pub fn parameter(&self) -> &str {
+        &self.parameter
+    }
+
+    pub fn config(&self) -> &ElementConfig {
+        &self.config
+    }
+
+    /// The pairs by ascending parameter value.
+    pub fn points(&self) -> &[SeriesPoint] {
+        &self.points
+    }
+
+    /// Loads three-column (energy, μ+, μ−) files, one per parameter value,
+    /// and runs `pipeline` on each helicity spectrum.
+    pub fn load<P>(
+        parameter: &str,
+        files: &[(f64, P)],
+        config: &ElementConfig,
+        pipeline: &Pipeline,
+    ) -> Result<Series, Error>
+    where
+        P: AsRef<Path>,
+    {
+        let mut series = Series::new(parameter, config);
+        for (value, path) in files {
+            let path = path.as_ref();
+            let pair = || -> Result<(Spectrum, Spectrum), Error> {
+                let file = fs::File::open(path)?;
+                let (plus, minus) = Xas::load_pair(io::BufReader::new(file))?;
+                let file = path.display().to_string();
+                let plus = plus.with_metadata("Scan.file", &file);
+                let minus = minus.with_metadata("Scan.file", &file);
+                Ok((pipeline.run(&plus)?, pipeline.run(&minus)?))
+            };
+            let (plus, minus) = pair().map_err(|e| error!("{}: {}", path.display(), e))?;
+            series
+                .push(*value, plus, minus)
+                .map_err(|e| error!("{}: {}", path.display(), e))?;
+        }
+        Ok(series)
+    }
+
+    /// Reads a manifest with one `value path` pair per line; `#` starts a
+    /// comment and relative paths are resolved against `base`.
+    pub fn from_manifest<R>(
+        parameter: &str,
+        input: R,
+        base: &Path,
+        config: &ElementConfig,
+        pipeline: &Pipeline,
+    ) -> Result<Series, Error>
+    where
+        R: BufRead,
+    {
+        let mut files: Vec<(f64, PathBuf)> = Vec::new();
+        for (n, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (value, path) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(value), Some(path), None) => (value, path),
+                _ => bail!("manifest line {}: expected `value path`", n + 1),
+            };
+            let value =
+                parse_number(value).map_err(|e| error!("manifest line {}: {}", n + 1, e))?;
+            files.push((value, base.join(path)));
+        }
+        if files.is_empty() {
+            bail!("Empty manifest");
+        }
+        Series::load(parameter, &files, config, pipeline)
+    }
+
+    /// Collects the peak and asymmetry of every pair and, where there is an
+    /// XMCD, its amplitude, edge jump and sum-rule moments against the
+    /// parameter.
+    pub fn table(&self) -> Result<SeriesTable, Error> {
+        let mut rows = Vec::with_capacity(self.points.len());
+        for point in &self.points {
+            let (plus, minus) = point.plus.matched(&point.minus)?;
+            let sum = plus
+                .values()
+                .iter()
+                .zip(minus.values())
+                .map(|(p, m)| p + m)
+                .collect::<Vec<_>>();
+            let peak = math::argmax(&sum).ok_or_else(|| error!("Empty spectrum"))?;
+            let asymmetry = (plus.values()[peak] - minus.values()[peak]) / sum[peak];
+            let mut row = SeriesRow {
+                value: point.value,
+                peak_position: plus.energy()[peak],
+                asymmetry,
+                amplitude: f64::NAN,
+                edge_jump: f64::NAN,
+                m_orb: f64::NAN,
+                m_spin: f64::NAN,
+            };
+            if let Some(xmcd) = &point.xmcd {
+                let rules = SumRules::new(xmcd);
+                row.amplitude = xmcd.amplitude();
+                row.edge_jump = xmcd.edge_jump();
+                row.m_orb = rules.m_orb;
+                row.m_spin = rules.m_spin;
+            }
+            rows.push(row);
+        }
+        let spectra = self.points.iter().flat_map(|p| vec![&p.plus, &p.minus]);
+        Ok(SeriesTable {
+            parameter: self.parameter.clone(),
+            config: self.config.clone(),
+            metadata: common(spectra),
+            rows,
+        })
+    }
+}
+
+/// The entries every spectrum has with the same value, such as the steps
+/// of a shared pipeline.
+fn common<'a>(mut spectra: impl Iterator<Item = &'a Spectrum>) -> Metadata {
+    let mut metadata = match spectra.next() {
+        Some(first) => first.metadata.clone(),
+        None => return Metadata::new(),
+    };
+    for spectrum in spectra {
+        metadata.retain(|key, value| spectrum.metadata.get(key) == Some(value));
+    }
+    metadata
+}
+
+/// Quantities of one pair of a series. Those of the XMCD are NaN if the
+/// pair has none.
+#[derive(Debug, Clone, Copy)]
+pub struct SeriesRow {
+    pub value: f64,
+    /// Energy of the maximum of μ+ + μ−.
+    pub peak_position: f64,
+    /// (μ+ − μ−) / (μ+ + μ−) at the peak, before normalization.
+    pub asymmetry: f64,
+    /// Signed extremum of the corrected XMCD.
+    pub amplitude: f64,
+    pub edge_jump: f64,
+    pub m_orb: f64,
+    pub m_spin: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SeriesTable {
+    parameter: String,
+    config: ElementConfig,
+    metadata: Metadata,
+    rows: Vec<SeriesRow>,
+}
+
+impl SeriesTable {
+    pub fn parameter(&self) -> &str {
+        &self.parameter
+    }
+
+    pub fn config(&self) -> &ElementConfig {
+        &self.config
+    }
+
+    /// Metadata shared by all spectra of the series, including the steps
+    /// run on each of them.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn rows(&self) -> &[SeriesRow] {
+        &self.rows
+    }
+
+    pub fn column(&self, f: impl Fn(&SeriesRow) -> f64) -> Vec<f64> {
+        self.rows.iter().map(f).collect()
+    }
+
+    /// Writes whitespace-separated columns preceded by a `#` header line.
+    pub fn write<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        writeln!(
+            out,
+            "# {} peak_position asymmetry amplitude edge_jump m_orb m_spin",
+            self.parameter
+        )?;
+        for row in &self.rows {
+            writeln!(
+                out,
+                "{} {} {} {} {} {} {}",
+                row.value,
+                row.peak_position,
+                row.asymmetry,
+                row.amplitude,
+                row.edge_jump,
+                row.m_orb,
+                row.m_spin
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Peak position, asymmetry and, if any pair has an XMCD, its
+    /// amplitude, moments and edge jump against the parameter, two panels
+    /// per row.
+    pub fn figure(&self) -> Figure {
+        let x = self.column(|r| r.value);
+        let panel = |label: &str, y: Vec<f64>| {
+            Panel::new(&self.parameter, label).trace("", &x, &y, Style::LinePoints, Color::RED)
+        };
+        let mut panels = vec![
+            panel("peak position (eV)", self.column(|r| r.peak_position)),
+            panel("asymmetry at the peak", self.column(|r| r.asymmetry)),
+        ];
+        if self.rows.iter().any(|r| r.amplitude.is_finite()) {
+            let moments = Panel::new(&self.parameter, "m_orb, m_spin (μB)")
+                .trace(
+                    "m_orb",
+                    &x,
+                    &self.column(|r| r.m_orb),
+                    Style::LinePoints,
+                    Color::RED,
+                )
+                .trace(
+                    "m_spin",
+                    &x,
+                    &self.column(|r| r.m_spin),
+                    Style::LinePoints,
+                    Color::BLUE,
+                );
+            panels.push(panel("XMCD amplitude", self.column(|r| r.amplitude)));
+            panels.push(moments);
+            panels.push(panel("edge jump", self.column(|r| r.edge_jump)));
+        }
+        Figure::grid(panels, 2)
+    }
+
+    /// Renders the figure into the file of `options`.
+    pub fn plot(&self, options: &PlotOptions) -> Result<(), Error> {
+        self.figure().save(options)
+    }
+
+    /// The table as an [`Export`], one column per quantity.
+    pub fn export(&self) -> Export {
+        Export::from_series(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Ini;
+    use crate::elem::Database;
+    use crate::pipeline::Smooth;
+    use crate::synthetic::Synthetic;
+
+    fn data() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("data")
+    }
+
+    fn co() -> ElementConfig {
+        let ini = Ini::new(include_str!("../data/element.ini").as_bytes()).unwrap();
+        ElementConfig::new(&ini, "Co").unwrap()
+    }
+
+    #[test]
+    fn table_recovers_synthetic_moments() {
+        let config = co();
+        let db = Database::load(data().join("elem.dat")).unwrap();
+        let mut series = Series::new("m_orb", &config);
+        for &m_orb in &[0.1, 0.2, 0.3] {
+            let mut synthetic = Synthetic::new(&db, &config).unwrap();
+            synthetic.m_orb = m_orb;
+            let spectra = synthetic.generate().unwrap();
+            series
+                .push(m_orb, spectra.mu_plus().clone(), spectra.mu_minus().clone())
+                .unwrap();
+        }
+        let table = series.table().unwrap();
+        for row in table.rows() {
+            assert!((row.m_orb - row.value).abs() < 1e-6, "{:?}", row);
+            assert!(row.asymmetry.is_finite() && row.edge_jump > 0.0);
+        }
+
+        // A scan over the first edge alone has no XMCD.
+        let point = &series.points()[0];
+        let (lo, hi) = (config.energy_l3 - 5.0, config.energy_l3 + 5.0);
+        let mut short = Series::new("m_orb", &config);
+        short
+            .push(
+                0.1,
+                point.plus().crop(lo, hi).unwrap(),
+                point.minus().crop(lo, hi).unwrap(),
+            )
+            .unwrap();
+        let row = short.table().unwrap().rows()[0];
+        assert!(short.points()[0].xmcd().is_none());
+        assert!(row.m_orb.is_nan() && row.amplitude.is_nan());
+        assert!((row.peak_position - table.rows()[0].peak_position).abs() < 1.0);
+    }
+
+    #[test]
+    fn manifest_lines_are_checked() {
+        let data = data();
+        let pipeline = Pipeline::new().step(Smooth { half_width: 1 });
+        let manifest = "# fluence and scan\n19 19mj\n1 01mj # lowest\n";
+        let series =
+            Series::from_manifest("fluence", manifest.as_bytes(), &data, &co(), &pipeline).unwrap();
+        let values = series
+            .points()
+            .iter()
+            .map(|p| p.value())
+            .collect::<Vec<_>>();
+        assert_eq!(values, [1.0, 19.0]);
+        let table = series.table().unwrap();
+        assert_eq!(
+            crate::pipeline::provenance(table.metadata()),
+            ["smooth half_width=1"]
+        );
+        assert!(!table.metadata().contains_key("Scan.file"));
+
+        let parse = |text: &str| {
+            Series::from_manifest("fluence", text.as_bytes(), &data, &co(), &pipeline)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(parse("1 01mj\n2\n").contains("manifest line 2"));
+        assert!(parse("1 01mj extra\n").contains("manifest line 1"));
+        assert!(parse("x 01mj\n").contains("manifest line 1"));
+        assert!(parse("1 missing\n").contains("missing"));
+        assert_eq!(parse("# nothing\n"), "Empty manifest");
+    }
+}
@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+use crate::columns::Columns;
+use crate::spectrum::{Metadata, Spectrum};
+use crate::Error;
+
+/// One `#S` block of a SPEC file. The columns are named by the `#L` line;
+/// their metadata holds `Scan.number`, `Scan.command`, `Scan.date`, the
+/// `#O`/`#P` motor positions as `Motor.name` and the `#C` lines of the file
+/// header and the scan as `Comment.NN`.
+#[derive(Debug, Clone)]
+pub struct Scan {
+    pub number: usize,
+    /// 1 for the first scan of this number in the file, 2 for the second;
+    /// SPEC restarts numbering when a file is reopened.
+    pub occurrence: usize,
+    /// The scan command, e.g. `ascan  energy 700 740 80 1`.
+    pub command: String,
+    pub columns: Columns,
+}
+
+impl Scan {
+    pub fn metadata(&self) -> &Metadata {
+        &self.columns.metadata
+    }
+
+    /// The number, with `.occurrence` after the first, e.g. `12` or `12.2`.
+    pub fn id(&self) -> String {
+        ScanRange::single(self.number, self.occurrence).to_string()
+    }
+}
+
+/// File of SPEC scans in the order they appear.
+#[derive(Debug, Clone, Default)]
+pub struct SpecFile {
+    pub scans: Vec<Scan>,
+}
+
+/// State of the scan being read.
+#[derive(Default)]
+struct Pending {
+    number: usize,
+    command: String,
+    labels: Option<String>,
+    metadata: Metadata,
+    comments: Vec<String>,
+    positions: Vec<f64>,
+    rows: Vec<Vec<f64>>,
+}
+
+impl SpecFile {
+    /// Reads every scan; `@A` MCA lines and unknown headers are skipped. A
+    /// new `#E` header drops the comments of the previous one, and each
+    /// `#O0` line starts a new list of motor names.
+    pub fn read<R: BufRead>(input: R) -> Result<SpecFile, Error> {
+        let mut scans = Vec::new();
+        let mut motors: Vec<String> = Vec::new();
+        let mut header_comments: Vec<String> = Vec::new();
+        let mut scan: Option<Pending> = None;
+        for (n, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                if let Some(pending) = scan.take() {
+                    scans.push(finish(pending, &motors)?);
+                }
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('#') {
+                let (key, value) = match header.find(char::is_whitespace) {
+                    Some(i) => (&header[..i], header[i..].trim()),
+                    None => (header, ""),
+                };
+                if matches!(key, "S" | "E" | "F") {
+                    if let Some(pending) = scan.take() {
+                        scans.push(finish(pending, &motors)?);
+                    }
+                }
+                if key == "S" {
+                    let mut words = value.splitn(2, char::is_whitespace);
+                    let number = words
+                        .next()
+                        .unwrap_or_default()
+                        .parse()
+                        .map_err(|_| error!("line {}: `#S` needs a scan number", n + 1))?;
+                    let mut pending = Pending {
+                        number,
+                        command: words.next().unwrap_or_default().trim().to_string(),
+                        ..Pending::default()
+                    };
+                    pending.comments = header_comments.clone();
+                    scan = Some(pending);
+                    continue;
+                }
+                match (&mut scan, key) {
+                    (None, "E") => header_comments.clear(),
+                    (None, "O0") => motors = labels(value),
+                    (None, "C") => header_comments.push(value.to_string()),
+                    (None, key) if key.starts_with('O') => motors.extend(labels(value)),
+                    (Some(pending), "D") => {
+                        pending
+                            .metadata
+                            .insert("Scan.date".to_string(), value.to_string());
+                    }
+                    (Some(pending), "C") => pending.comments.push(value.to_string()),
+                    (Some(pending), "L") => pending.labels = Some(value.to_string()),
+                    (Some(pending), key) if key.starts_with('P') => {
+                        for word in value.split_whitespace() {
+                            pending.positions.push(word.parse().map_err(|e| {
+                                error!("line {}: motor position {:?}: {}", n + 1, word, e)
+                            })?);
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            if line.starts_with('@') {
+                continue;
+            }
+            let pending = match &mut scan {
+                Some(pending) => pending,
+                None => bail!("line {}: data before the first `#S`", n + 1),
+            };
+            let row = line
+                .split_whitespace()
+                .map(|f| f.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| error!("line {}: {}", n + 1, e))?;
+            if let Some(first) = pending.rows.first() {
+                if row.len() != first.len() {
+                    bail!(
+                        "line {}: expected {} columns, found {}",
+                        n + 1,
+                        first.len(),
+                        row.len()
+                    );
+                }
+            }
+            pending.rows.push(row);
+        }
+        if let Some(pending) = scan.take() {
+            scans.push(finish(pending, &motors)?);
+        }
+        let mut seen = HashMap::new();
+        for scan in &mut scans {
+            let count = seen.entry(scan.number).or_insert(0);
+            *count += 1;
+            scan.occurrence = *count;
+            let id = scan.id();
+            scan.columns.metadata.insert("Scan.number".to_string(), id);
+        }
+        Ok(SpecFile { scans })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SpecFile, Error> {
+        let file = fs::File::open(path)?;
+        SpecFile::read(io::BufReader::new(file))
+    }
+
+    /// The `occurrence`-th scan numbered `number`.
+    pub fn scan(&self, number: usize, occurrence: usize) -> Result<&Scan, Error> {
+        self.scans
+            .iter()
+            .find(|scan| scan.number == number && scan.occurrence == occurrence)
+            .ok_or_else(|| error!("No scan {}", ScanRange::single(number, occurrence)))
+    }
+
+    /// The scans of each of `ranges` in turn, by number within a range.
+    /// Only the scans present in the file are visited, so a range may be
+    /// wide, but every number in it must be there.
+    pub fn select(&self, ranges: &[ScanRange]) -> Result<Vec<&Scan>, Error> {
+        let mut selected = Vec::new();
+        for range in ranges {
+            let mut scans = self
+                .scans
+                .iter()
+                .filter(|scan| range.contains(scan))
+                .collect::<Vec<_>>();
+            scans.sort_by_key(|scan| scan.number);
+            let missing = scans
+                .iter()
+                .enumerate()
+                .find(|(i, scan)| scan.number != range.first + i)
+                .map(|(i, _)| range.first + i)
+                .or_else(|| match scans.last() {
+                    Some(last) if last.number == range.last => None,
+                    Some(last) => Some(last.number + 1),
+                    None => Some(range.first),
+                });
+            if let Some(number) = missing {
+                bail!("No scan {}", ScanRange::single(number, range.occurrence));
+            }
+            selected.extend(scans);
+        }
+        Ok(selected)
+    }
+}
+
+/// Closes a scan; scans without data, such as aborted ones, keep empty
+/// columns.
+fn finish(pending: Pending, motors: &[String]) -> Result<Scan, Error> {
+    let Pending {
+        number,
+        command,
+        labels: label_line,
+        mut metadata,
+        comments,
+        positions,
+        rows,
+    } = pending;
+    metadata.insert("Scan.number".to_string(), number.to_string());
+    metadata.insert("Scan.command".to_string(), command.clone());
+    for (motor, position) in motors.iter().zip(&positions) {
+        metadata.insert(
+            format!("Motor.{}", motor.replace(' ', "_")),
+            position.to_string(),
+        );
+    }
+    for (i, comment) in comments.into_iter().enumerate() {
+        metadata.insert(format!("Comment.{:02}", i + 1), comment);
+    }
+
+    let width = rows.first().map_or(0, |row| row.len());
+    let mut data = vec![Vec::with_capacity(rows.len()); width];
+    for row in rows {
+        for (column, value) in data.iter_mut().zip(row) {
+            column.push(value);
+        }
+    }
+    // Fall back to single spaces for files written by other programs.
+    let line = label_line.as_deref();
+    let names = line
+        .map(labels)
+        .filter(|names| names.len() == width)
+        .or_else(|| Some(line?.split_whitespace().map(str::to_string).collect()))
+        .filter(|names: &Vec<String>| names.len() == width)
+        .unwrap_or_default();
+    let mut columns = Columns::new(names, data)?;
+    columns.metadata = metadata;
+    Ok(Scan {
+        number,
+        occurrence: 1,
+        command,
+        columns,
+    })
+}
+
+/// Names separated by two or more spaces, as SPEC writes `#L` and `#O`
+/// lines so that single spaces may appear within a name.
+fn labels(line: &str) -> Vec<String> {
+    line.split("  ")
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Scans of a SPEC file written as `file.spec:12-18,20`, or `12.2-18.2`
+/// for the second scans of those numbers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub path: PathBuf,
+    pub ranges: Vec<ScanRange>,
+}
+
+impl Selection {
+    /// `None` if `text` has no `:` followed by scan numbers.
+    pub fn parse(text: &str) -> Option<Result<Selection, Error>> {
+        let colon = text.rfind(':')?;
+        let (path, ranges) = (&text[..colon], &text[colon + 1..]);
+        if path.is_empty() || !ranges.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        Some(parse_ranges(ranges).map(|ranges| Selection {
+            path: PathBuf::from(path),
+            ranges,
+        }))
+    }
+
+    /// The selected scans merged with [`merge`] on their `energy` column.
+    pub fn load(&self, energy: &str) -> Result<Columns, Error> {
+        let file =
+            SpecFile::load(&self.path).map_err(|e| error!("{}: {}", self.path.display(), e))?;
+        let scans = file
+            .select(&self.ranges)
+            .map_err(|e| error!("{}: {}", self.path.display(), e))?;
+        let mut columns = merge(&scans, energy)?;
+        columns
+            .metadata
+            .insert("Scan.file".to_string(), self.path.display().to_string());
+        Ok(columns)
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.path.display(), format_ranges(&self.ranges))
+    }
+}
+
+impl std::str::FromStr for Selection {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Selection::parse(s)
+            .unwrap_or_else(|| bail!("Expected FILE:SCANS, e.g. data.spec:12-18, got {:?}", s))
+    }
+}
+
+/// Scans `first..=last` at their `occurrence`-th appearance in a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScanRange {
+    pub first: usize,
+    pub last: usize,
+    pub occurrence: usize,
+}
+
+impl ScanRange {
+    pub fn single(number: usize, occurrence: usize) -> ScanRange {
+        ScanRange {
+            first: number,
+            last: number,
+            occurrence,
+        }
+    }
+
+    pub fn contains(&self, scan: &Scan) -> bool {
+        scan.occurrence == self.occurrence && (self.first..=self.last).contains(&scan.number)
+    }
+
+    /// Runs of consecutive numbers of the same occurrence among `scans`.
+    pub fn cover(scans: &[&Scan]) -> Vec<ScanRange> {
+        let mut ranges: Vec<ScanRange> = Vec::new();
+        for scan in scans {
+            match ranges.last_mut() {
+                Some(range)
+                    if range.occurrence == scan.occurrence && range.last + 1 == scan.number =>
+                {
+                    range.last = scan.number
+                }
+                _ => ranges.push(ScanRange::single(scan.number, scan.occurrence)),
+            }
+        }
+        ranges
+    }
+}
+
+impl fmt::Display for ScanRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let suffix = match self.occurrence {
+            1 => String::new(),
+            n => format!(".{}", n),
+        };
+        if self.first == self.last {
+            write!(f, "{}{}", self.first, suffix)
+        } else {
+            write!(f, "{}{}-{}{}", self.first, suffix, self.last, suffix)
+        }
+    }
+}
+
+/// `12-18,20.2` as the ranges 12 to 18 and the second scan 20.
+pub fn parse_ranges(text: &str) -> Result<Vec<ScanRange>, Error> {
+    let mut ranges = Vec::new();
+    for part in text.split(',') {
+        let part = part.trim();
+        let invalid = || error!("Invalid scan range {:?}", part);
+        let bound = |text: &str| -> Result<(usize, Option<usize>), Error> {
+            let mut words = text.trim().splitn(2, '.');
+            let number = words
+                .next()
+                .unwrap_or_default()
+                .parse()
+                .map_err(|_| invalid())?;
+            match words.next() {
+                Some(occurrence) => match occurrence.parse() {
+                    Ok(0) | Err(_) => Err(invalid()),
+                    Ok(occurrence) => Ok((number, Some(occurrence))),
+                },
+                None => Ok((number, None)),
+            }
+        };
+        let mut bounds = part.splitn(2, '-');
+        let (first, a) = bound(bounds.next().unwrap_or_default())?;
+        let (last, b) = match bounds.next() {
+            Some(last) => bound(last)?,
+            None => (first, a),
+        };
+        let occurrence = match (a, b) {
+            (Some(a), Some(b)) if a != b => {
+                bail!("Scan range {:?} mixes occurrences {} and {}", part, a, b)
+            }
+            (a, b) => a.or(b).unwrap_or(1),
+        };
+        if last < first {
+            bail!("Scan range {:?} runs backwards", part);
+        }
+        ranges.push(ScanRange {
+            first,
+            last,
+            occurrence,
+        });
+    }
+    Ok(ranges)
+}
+
+/// Ranges separated by commas, e.g. `12-18,20.2`.
+pub fn format_ranges(ranges: &[ScanRange]) -> String {
+    ranges
+        .iter()
+        .map(ScanRange::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Average of `scans` on the energy grid of the first one, limited to the
+/// range all scans cover. Columns are matched by their `#L` names, or by
+/// position if the first scan has none. The metadata is that of the first
+/// scan with `Scan.number` listing all of them.
+pub fn merge(scans: &[&Scan], energy: &str) -> Result<Columns, Error> {
+    let first = match scans.first() {
+        Some(first) => &first.columns,
+        None => bail!("No scans to merge"),
+    };
+    let energy_of = |scan: &Scan| -> Result<Vec<f64>, Error> {
+        let i = scan.columns.index(energy)?;
+        Ok(scan.columns.data[i].clone())
+    };
+    let (mut lo, mut hi) = (f64::NEG_INFINITY, f64::INFINITY);
+    for scan in scans {
+        let e = energy_of(scan).map_err(|e| error!("scan {}: {}", scan.id(), e))?;
+        if e.is_empty() {
+            bail!("scan {} has no data", scan.id());
+        }
+        lo = lo.max(e.iter().cloned().fold(f64::INFINITY, f64::min));
+        hi = hi.min(e.iter().cloned().fold(f64::NEG_INFINITY, f64::max));
+    }
+    let e = first.index(energy)?;
+    let grid = Spectrum::from_unsorted(&first.data[e], &first.data[e])?
+        .crop(lo, hi)
+        .map_err(|_| error!("The scans share no energy range"))?
+        .into_parts()
+        .0;
+
+    let weight = 1.0 / scans.len() as f64;
+    let mut data = vec![vec![0.0; grid.len()]; first.data.len()];
+    data[e] = grid.clone();
+    for scan in scans {
+        let columns = &scan.columns;
+        let energy = &columns.data[columns.index(energy)?];
+        for i in (0..first.data.len()).filter(|&i| i != e) {
+            let j = match first.names.get(i) {
+                Some(name) => columns.index(name),
+                None if i < columns.data.len() => Ok(i),
+                None => Err(error!("No column {}", i)),
+            }
+            .map_err(|e| error!("scan {}: {}", scan.id(), e))?;
+            let values = Spectrum::from_unsorted(energy, &columns.data[j])?.resample(&grid)?;
+            for (sum, value) in data[i].iter_mut().zip(values.values()) {
+                *sum += weight * value;
+            }
+        }
+    }
+
+    let mut merged = Columns::new(first.names.clone(), data)?;
+    merged.metadata = first.metadata.clone();
+    merged.metadata.insert(
+        "Scan.number".to_string(),
+        format_ranges(&ScanRange::cover(scans)),
+    );
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "#F test\n\n\
+        #S 1 ascan energy 700 702 2 1\n#L energy  mu+  mu-\n700 1 2\n701 2 3\n702 3 4\n\n\
+        #S 2 ascan energy 700 702 2 1\n#L energy  mu+  mu-\n700 3 4\n701 4 5\n702 5 6\n\n\
+        #F test\n\n\
+        #S 1 ascan energy 700 702 2 1\n#L energy  mu+  mu-\n700 5 6\n701 6 7\n702 7 8\n";
+
+    #[test]
+    fn repeated_numbers_are_told_apart() {
+        let file = SpecFile::read(FILE.as_bytes()).unwrap();
+        assert_eq!(file.scan(1, 2).unwrap().id(), "1.2");
+        assert_eq!(file.scan(1, 2).unwrap().columns.data[1][0], 5.0);
+
+        let ranges = parse_ranges("1-2,1.2").unwrap();
+        assert_eq!(format_ranges(&ranges), "1-2,1.2");
+        let scans = file.select(&ranges).unwrap();
+        let merged = merge(&scans, "energy").unwrap();
+        assert_eq!(merged.data[1], vec![3.0, 4.0, 5.0]);
+        assert_eq!(merged.metadata["Scan.number"], "1-2,1.2");
+        assert!(parse_ranges("1.2-2.3").is_err());
+        assert!(parse_ranges("1.0").is_err());
+    }
+
+    #[test]
+    fn wide_ranges_stop_at_the_scans_in_the_file() {
+        let file = SpecFile::read(FILE.as_bytes()).unwrap();
+        let ranges = parse_ranges("1-4000000000").unwrap();
+        let error = file.select(&ranges).unwrap_err().to_string();
+        assert_eq!(error, "No scan 3");
+        assert!(file.select(&parse_ranges("2.2").unwrap()).is_err());
+    }
+}
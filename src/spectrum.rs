@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+use std::ops::{Add, Sub};
+
+use crate::math;
+use crate::Error;
+
+/// Free-form `key = value` annotations of a spectrum, e.g. `Element.symbol`
+/// or `Scan.start_time`; sorted by key.
+pub type Metadata = BTreeMap<String, String>;
+
+/// Values on a strictly increasing energy grid, with optional one-sigma
+/// uncertainties and metadata. All values are finite.
+///
+/// Arithmetic between spectra works on the part of the left operand's grid
+/// covered by the right one, which is interpolated onto it; uncertainties
+/// add in quadrature. The result keeps the metadata of the left operand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spectrum {
+    energy: Vec<f64>,
+    values: Vec<f64>,
+    uncertainty: Option<Vec<f64>>,
+    pub metadata: Metadata,
+}
+
+impl Spectrum {
+    pub fn new(energy: Vec<f64>, values: Vec<f64>) -> Result<Spectrum, Error> {
+        if energy.is_empty() {
+            bail!("Empty spectrum");
+        }
+        if energy.len() != values.len() {
+            bail!("{} energies for {} values", energy.len(), values.len());
+        }
+        if let Some(i) = energy.iter().chain(&values).position(|v| !v.is_finite()) {
+            let i = i % energy.len();
+            bail!("Non-finite point {} at E = {}", i, energy[i]);
+        }
+        if let Some(i) = energy.windows(2).position(|w| w[1] <= w[0]) {
+            bail!(
+                "Energies must increase: {} follows {}",
+                energy[i + 1],
+                energy[i]
+            );
+        }
+        Ok(Spectrum {
+            energy,
+            values,
+            uncertainty: None,
+            metadata: Metadata::new(),
+        })
+    }
+
+    /// Sorts the points by energy and averages repeated energies before
+    /// the checks of [`Spectrum::new`].
+    pub fn from_unsorted(energy: &[f64], values: &[f64]) -> Result<Spectrum, Error> {
+        if energy.len() != values.len() {
+            bail!("{} energies for {} values", energy.len(), values.len());
+        }
+        let mut points = energy
+            .iter()
+            .cloned()
+            .zip(values.iter().cloned())
+            .collect::<Vec<_>>();
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        let mut e: Vec<f64> = Vec::with_capacity(points.len());
+        let mut v: Vec<f64> = Vec::with_capacity(points.len());
+        let mut count = 0.0;
+        for (x, y) in points {
+            match e.last() {
+                Some(&last) if last == x => {
+                    count += 1.0;
+                    let mean = v.last_mut().unwrap();
+                    *mean += (y - *mean) / count;
+                }
+                _ => {
+                    e.push(x);
+                    v.push(y);
+                    count = 1.0;
+                }
+            }
+        }
+        Spectrum::new(e, v)
+    }
+
+    /// Attaches one-sigma uncertainties, which must be finite and not
+    /// negative.
+    pub fn with_uncertainty(mut self, sigma: Vec<f64>) -> Result<Spectrum, Error> {
+        if sigma.len() != self.len() {
+            bail!("{} uncertainties for {} values", sigma.len(), self.len());
+        }
+        if sigma.iter().any(|s| !s.is_finite() || *s < 0.0) {
+            bail!("Uncertainties must be finite and not negative");
+        }
+        self.uncertainty = Some(sigma);
+        Ok(self)
+    }
+
+    pub fn with_metadata(mut self, key: &str, value: &str) -> Spectrum {
+        self.metadata.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn energy(&self) -> &[f64] {
+        &self.energy
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    pub fn uncertainty(&self) -> Option<&[f64]> {
+        self.uncertainty.as_deref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.energy.len()
+    }
+
+    /// Always false; a spectrum holds at least one point.
+    pub fn is_empty(&self) -> bool {
+        self.energy.is_empty()
+    }
+
+    /// First and last energy.
+    pub fn range(&self) -> (f64, f64) {
+        (self.energy[0], self.energy[self.len() - 1])
+    }
+
+    /// Energy, values and uncertainties.
+    pub fn into_parts(self) -> (Vec<f64>, Vec<f64>, Option<Vec<f64>>) {
+        (self.energy, self.values, self.uncertainty)
+    }
+
+    /// Linearly interpolated value at `energy`, constant beyond the ends.
+    pub fn at(&self, energy: f64) -> f64 {
+        math::interp(&self.energy, &self.values, energy)
+    }
+
+    /// Maps every value, keeping grid, uncertainties and metadata.
+    pub fn map<F: Fn(f64, f64) -> f64>(&self, f: F) -> Result<Spectrum, Error> {
+        let values = self
+            .energy
+            .iter()
+            .zip(&self.values)
+            .map(|(&e, &v)| f(e, v))
+            .collect();
+        self.rebuild(self.energy.clone(), values, self.uncertainty.clone())
+    }
+
+    /// The points with `lo <= E <= hi`.
+    pub fn crop(&self, lo: f64, hi: f64) -> Result<Spectrum, Error> {
+        let start = self.energy.iter().position(|&e| e >= lo);
+        let end = self.energy.iter().rposition(|&e| e <= hi);
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) if start <= end => (start, end + 1),
+            _ => bail!("No points between {} and {} eV", lo, hi),
+        };
+        let sigma = self.uncertainty.as_ref().map(|s| s[start..end].to_vec());
+        self.rebuild(
+            self.energy[start..end].to_vec(),
+            self.values[start..end].to_vec(),
+            sigma,
+        )
+    }
+
+    /// Linear interpolation onto `grid`, which must lie inside the range of
+    /// the spectrum.
+    pub fn resample(&self, grid: &[f64]) -> Result<Spectrum, Error> {
+        let (lo, hi) = self.range();
+        if let Some(&e) = grid.iter().find(|&&e| e < lo || e > hi) {
+            bail!("Cannot resample at {} eV, outside {}–{} eV", e, lo, hi);
+        }
+        let values = math::resample(&self.energy, &self.values, grid);
+        let sigma = self
+            .uncertainty
+            .as_ref()
+            .map(|s| math::resample(&self.energy, s, grid));
+        self.rebuild(grid.to_vec(), values, sigma)
+    }
+
+    /// New values on the same grid, keeping the metadata; the uncertainties
+    /// of the old values are dropped.
+    pub fn with_values(&self, values: Vec<f64>) -> Result<Spectrum, Error> {
+        self.rebuild(self.energy.clone(), values, None)
+    }
+
+    /// New values and uncertainties on the same grid, keeping the metadata.
+    pub fn with_values_and_uncertainty(
+        &self,
+        values: Vec<f64>,
+        sigma: Option<Vec<f64>>,
+    ) -> Result<Spectrum, Error> {
+        let spectrum = self.with_values(values)?;
+        match sigma {
+            Some(sigma) => spectrum.with_uncertainty(sigma),
+            None => Ok(spectrum),
+        }
+    }
+
+    /// The spectrum moved by `delta` eV.
+    pub fn shift(&self, delta: f64) -> Result<Spectrum, Error> {
+        let energy = self.energy.iter().map(|e| e + delta).collect();
+        self.rebuild(energy, self.values.clone(), self.uncertainty.clone())
+    }
+
+    /// Values and uncertainties multiplied by `factor`.
+    pub fn scale(&self, factor: f64) -> Result<Spectrum, Error> {
+        if !factor.is_finite() {
+            bail!("Cannot scale by {}", factor);
+        }
+        let values = self.values.iter().map(|v| v * factor).collect();
+        let sigma = self
+            .uncertainty
+            .as_ref()
+            .map(|s| s.iter().map(|s| s * factor.abs()).collect());
+        self.rebuild(self.energy.clone(), values, sigma)
+    }
+
+    /// Trapezoidal integral over the points with `lo <= E <= hi`.
+    pub fn integrate(&self, lo: f64, hi: f64) -> f64 {
+        math::integrate(&self.energy, &self.values, lo, hi)
+    }
+
+    fn rebuild(
+        &self,
+        energy: Vec<f64>,
+        values: Vec<f64>,
+        uncertainty: Option<Vec<f64>>,
+    ) -> Result<Spectrum, Error> {
+        let mut spectrum = Spectrum::new(energy, values)?;
+        spectrum.uncertainty = uncertainty;
+        spectrum.metadata = self.metadata.clone();
+        Ok(spectrum)
+    }
+
+    /// Both spectra on the part of the grid of `self` covered by `other`.
+    pub fn matched(&self, other: &Spectrum) -> Result<(Spectrum, Spectrum), Error> {
+        let (lo, hi) = other.range();
+        let left = self.crop(lo, hi)?;
+        let right = if other.energy == left.energy {
+            other.clone()
+        } else {
+            other.resample(left.energy())?
+        };
+        Ok((left, right))
+    }
+
+    fn combine(&self, other: &Spectrum, op: impl Fn(f64, f64) -> f64) -> Result<Spectrum, Error> {
+        let (left, right) = self.matched(other)?;
+        let values = left
+            .values
+            .iter()
+            .zip(&right.values)
+            .map(|(&a, &b)| op(a, b))
+            .collect();
+        let sigma = match (&left.uncertainty, &right.uncertainty) {
+            (None, None) => None,
+            (a, b) => Some(
+                (0..left.len())
+                    .map(|i| {
+                        let a = a.as_ref().map_or(0.0, |s| s[i]);
+                        let b = b.as_ref().map_or(0.0, |s| s[i]);
+                        a.hypot(b)
+                    })
+                    .collect(),
+            ),
+        };
+        left.rebuild(left.energy.clone(), values, sigma)
+    }
+}
+
+impl<'a> Add for &'a Spectrum {
+    type Output = Result<Spectrum, Error>;
+    fn add(self, other: &'a Spectrum) -> Self::Output {
+        self.combine(other, |a, b| a + b)
+    }
+}
+
+impl<'a> Sub for &'a Spectrum {
+    type Output = Result<Spectrum, Error>;
+    fn sub(self, other: &'a Spectrum) -> Self::Output {
+        self.combine(other, |a, b| a - b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn construction_enforces_the_invariants() {
+        assert!(Spectrum::new(vec![], vec![]).is_err());
+        assert!(Spectrum::new(vec![1.0, 2.0], vec![1.0]).is_err());
+        assert!(Spectrum::new(vec![1.0, 2.0], vec![1.0, f64::NAN]).is_err());
+        assert!(Spectrum::new(vec![1.0, 1.0], vec![1.0, 2.0]).is_err());
+
+        let s = Spectrum::from_unsorted(&[2.0, 1.0, 2.0], &[4.0, 1.0, 6.0]).unwrap();
+        assert_eq!(s.energy(), &[1.0, 2.0]);
+        assert_eq!(s.values(), &[1.0, 5.0]);
+        assert!(s.clone().with_uncertainty(vec![0.1]).is_err());
+        assert!(s.clone().with_uncertainty(vec![0.1, -0.1]).is_err());
+        assert!(s.map(|_, v| v / 0.0).is_err());
+        assert!(s.resample(&[0.5]).is_err());
+        assert!(s.crop(3.0, 4.0).is_err());
+    }
+
+    #[test]
+    fn arithmetic_works_on_the_overlap() {
+        let a = Spectrum::new(vec![0.0, 1.0, 2.0, 3.0], vec![0.0, 1.0, 2.0, 3.0])
+            .unwrap()
+            .with_uncertainty(vec![0.3; 4])
+            .unwrap()
+            .with_metadata("Scan.file", "a");
+        let b = Spectrum::new(vec![0.5, 2.5], vec![1.0, 1.0])
+            .unwrap()
+            .with_uncertainty(vec![0.4; 2])
+            .unwrap();
+        let d = (&a - &b).unwrap();
+        assert_eq!(d.energy(), &[1.0, 2.0]);
+        assert_eq!(d.values(), &[0.0, 1.0]);
+        assert_eq!(d.metadata["Scan.file"], "a");
+        for sigma in d.uncertainty().unwrap() {
+            assert!((sigma - 0.5).abs() < 1e-12);
+        }
+        for sigma in d.scale(-2.0).unwrap().uncertainty().unwrap() {
+            assert!((sigma - 1.0).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::math;
+use crate::xmcd::Xmcd;
+
+/// XMCD sum-rule integrals and moments (Thole/Carra, as applied by
+/// Chen et al., PRL 75, 152 (1995)), in μB per atom. `p` and `q` integrate
+/// the measured XMCD; the corrected moments are scaled by the polarization
+/// and angle correction of the spectrum.
+///
+/// The prefactors follow from the core and valence orbital quantum numbers
+/// of the config, so the same code covers L2,3 edges of 3d metals, M4,5
+/// edges of rare earths and K edges. The spin moment is the effective one
+/// divided by `1 + k·⟨Tz⟩/⟨Sz⟩` and scaled by the spin correction; K edges
+/// have no spin-orbit split core level and give the orbital moment only.
+#[derive(Debug, Clone, Copy)]
+pub struct SumRules {
+    /// XMCD integral over the first (L3, M5) window.
+    pub p: f64,
+    /// XMCD integral over both windows.
+    pub q: f64,
+    /// Integral of μ+ + μ− minus background over both windows.
+    pub r: f64,
+    pub m_orb: f64,
+    /// NaN for K edges.
+    pub m_spin: f64,
+    pub m_orb_raw: f64,
+    pub m_spin_raw: f64,
+}
+
+/// Sum-rule prefactors for a core level `c` and valence shell `l`:
+///
+/// `m_orb = −orbital·q·nh/r`,
+/// `m_spin,eff = −spin·((2c + 1)·p − (c + 1)·q)·nh/r` and
+/// `m_spin,eff = m_spin·(1 + tz·⟨Tz⟩/⟨Sz⟩)`.
+///
+/// For L2,3 edges these are 4/3, 2 and 7/2; for M4,5 edges 2, 1 and 3.
+#[derive(Debug, Clone, Copy)]
+pub struct Prefactors {
+    pub orbital: f64,
+    /// NaN for `c = 0`.
+    pub spin: f64,
+    pub tz: f64,
+}
+
+impl Prefactors {
+    pub fn new(core_l: u32, valence_l: u32) -> Prefactors {
+        let c = (core_l * (core_l + 1)) as f64;
+        let l = (valence_l * (valence_l + 1)) as f64;
+        let orbital = 2.0 / 3.0 * 2.0 * l / (l + 2.0 - c);
+        if core_l == 0 {
+            return Prefactors {
+                orbital,
+                spin: f64::NAN,
+                tz: f64::NAN,
+            };
+        }
+        let a = l - 2.0 - c;
+        let cc = core_l as f64;
+        let b = l * (l + 2.0 * c + 4.0) - 3.0 * (cc - 1.0).powi(2) * (cc + 2.0).powi(2);
+        Prefactors {
+            orbital,
+            spin: 4.0 / a,
+            tz: b / (2.0 * l * a),
+        }
+    }
+}
+
+impl SumRules {
+    pub fn new(xmcd: &Xmcd) -> SumRules {
+        let config = xmcd.config();
+        let (l3_lo, l3_hi) = config.l3_window();
+        let (l2_lo, l2_hi) = config.l2_window();
+        let (energy, dichroism) = (xmcd.energy(), xmcd.xmcd_raw().values());
+
+        let white_line = xmcd.white_line();
+
+        let p = math::integrate(energy, dichroism, l3_lo, l3_hi);
+        let q = p + math::integrate(energy, dichroism, l2_lo.max(l3_hi), l2_hi);
+        let r = math::integrate(energy, &white_line, l3_lo, l3_hi)
+            + math::integrate(energy, &white_line, l2_lo.max(l3_hi), l2_hi);
+
+        let holes = config.holes;
+        let factors = Prefactors::new(config.core_l, config.valence_l);
+        let c = config.core_l as f64;
+        let m_orb_raw = -factors.orbital * q * holes / r;
+        let m_spin_raw = -factors.spin * ((2.0 * c + 1.0) * p - (c + 1.0) * q) * holes / r
+            * config.spin_correction
+            / (1.0 + factors.tz * config.tz_ratio);
+        SumRules {
+            p,
+            q,
+            r,
+            m_orb: m_orb_raw * xmcd.correction(),
+            m_spin: m_spin_raw * xmcd.correction(),
+            m_orb_raw,
+            m_spin_raw,
+        }
+    }
+
+    pub fn ratio(&self) -> f64 {
+        self.m_orb / self.m_spin
+    }
+}
+
+impl std::fmt::Display for SumRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "p = {}  q = {}  r = {}", self.p, self.q, self.r)?;
+        if self.m_spin.is_nan() {
+            return write!(f, "m_orb  = {} (raw {}) μB", self.m_orb, self.m_orb_raw);
+        }
+        writeln!(f, "m_orb  = {} (raw {}) μB", self.m_orb, self.m_orb_raw)?;
+        writeln!(f, "m_spin = {} (raw {}) μB", self.m_spin, self.m_spin_raw)?;
+        write!(f, "m_orb/m_spin = {}", self.ratio())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::config::{ElementConfig, Ini};
+    use crate::elem::Database;
+    use crate::synthetic::Synthetic;
+
+    #[test]
+    fn prefactors_of_l_and_m_edges() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-12;
+        let l23 = Prefactors::new(1, 2);
+        assert!(close(l23.orbital, 4.0 / 3.0) && close(l23.spin, 2.0) && close(l23.tz, 3.5));
+        let m45 = Prefactors::new(2, 3);
+        assert!(close(m45.orbital, 2.0) && close(m45.spin, 1.0) && close(m45.tz, 3.0));
+        assert!(Prefactors::new(0, 1).spin.is_nan());
+    }
+
+    #[test]
+    fn moments_of_a_synthetic_spectrum() {
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+        let db = Database::load(data.join("elem.dat")).unwrap();
+        let ini = Ini::load(data.join("element.ini")).unwrap();
+        let mut config = ElementConfig::new(&ini, "Co").unwrap();
+        config.pc = 0.8;
+        let mut synthetic = Synthetic::new(&db, &config).unwrap();
+        synthetic.m_orb = 0.2;
+        synthetic.m_spin = 1.5;
+        let xmcd = synthetic.generate().unwrap().xmcd().unwrap();
+        let rules = SumRules::new(&xmcd);
+        assert!((rules.m_orb - 0.2).abs() < 1e-6, "{}", rules);
+        assert!((rules.m_spin - 1.5).abs() < 1e-6, "{}", rules);
+        assert!((rules.m_orb_raw - 0.2 * 0.8).abs() < 1e-6, "{}", rules);
+        assert!((rules.ratio() - 0.2 / 1.5).abs() < 1e-6);
+    }
+}
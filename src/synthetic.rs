@@ -0,0 +1,315 @@
+use std::f64::consts::PI;
+use std::io::Write;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::config::ElementConfig;
+use crate::elem::Database;
+use crate::math;
+use crate::spectrum::Spectrum;
+use crate::sumrules::{Prefactors, SumRules};
+use crate::xmcd::{self, Xmcd};
+use crate::Error;
+
+/// Counts below which Poisson deviates are drawn exactly; above it the
+/// normal approximation is used.
+const POISSON_EXACT: f64 = 30.0;
+
+/// Most corrections of the generating moments in [`Synthetic::generate`].
+const CALIBRATION_STEPS: usize = 50;
+
+/// White-line profile, normalized to unit area.
+#[derive(Debug, Clone, Copy)]
+pub enum LineShape {
+    Lorentzian,
+    /// Pseudo-Voigt (Thompson–Cox–Hastings) with the given Gaussian σ in eV.
+    Voigt {
+        sigma: f64,
+    },
+}
+
+impl LineShape {
+    /// Profile centred at zero with Lorentzian half width `gamma`.
+    pub fn eval(self, x: f64, gamma: f64) -> f64 {
+        let lorentz = |x: f64, g: f64| g / (PI * (x * x + g * g));
+        match self {
+            LineShape::Lorentzian => lorentz(x, gamma),
+            LineShape::Voigt { sigma } => {
+                let fg = 2.0 * sigma * (2.0 * 2f64.ln()).sqrt();
+                let fl = 2.0 * gamma;
+                let f = (fg.powi(5)
+                    + 2.69269 * fg.powi(4) * fl
+                    + 2.42843 * fg.powi(3) * fl.powi(2)
+                    + 4.47163 * fg.powi(2) * fl.powi(3)
+                    + 0.07842 * fg * fl.powi(4)
+                    + fl.powi(5))
+                .powf(0.2);
+                let r = fl / f;
+                let eta = 1.36603 * r - 0.47719 * r * r + 0.11116 * r * r * r;
+                let s = f / (2.0 * (2.0 * 2f64.ln()).sqrt());
+                let gauss = (-x * x / (2.0 * s * s)).exp() / (s * (2.0 * PI).sqrt());
+                eta * lorentz(x, f / 2.0) + (1.0 - eta) * gauss
+            }
+        }
+    }
+}
+
+/// Generator of synthetic XMCD spectra with known moments.
+///
+/// The absorption is `pre_edge` plus the two-step background of the
+/// pipeline and white lines at the `elem.dat` energies of the configured
+/// edges in the statistical `(2c + 2):2c` ratio, 2:1 for L3,L2. The
+/// dichroism amplitudes at both edges are solved for so that the sum-rule
+/// integrals over the configured windows give `m_orb` and `m_spin`, reduced
+/// by `Pc·cos θ` as in a measurement. Single (K) edges take `m_orb` only.
+///
+/// The pipeline normalizes each helicity on its own edge levels, which the
+/// line tails shift, so the moments used to generate the spectra are
+/// corrected until the noiseless spectra give `m_orb` and `m_spin`.
+#[derive(Debug, Clone)]
+pub struct Synthetic {
+    /// Grid, edges, windows, step ratio and holes; the edge energies are
+    /// set from `elem.dat`.
+    pub config: ElementConfig,
+    pub shape: LineShape,
+    /// Lorentzian half width in eV.
+    pub width: f64,
+    /// Area of both white lines, in edge jumps × eV.
+    pub white_line: f64,
+    pub m_orb: f64,
+    pub m_spin: f64,
+    /// Absorption below the edge, in edge jumps.
+    pub pre_edge: f64,
+    /// Expected counts at unit absorption; `None` gives noiseless spectra.
+    pub counts: Option<f64>,
+    pub seed: u64,
+}
+
+/// Generated helicity pair.
+#[derive(Debug, Clone)]
+pub struct SyntheticSpectra {
+    config: ElementConfig,
+    mu_plus: Spectrum,
+    mu_minus: Spectrum,
+}
+
+impl Synthetic {
+    pub fn new(db: &Database, config: &ElementConfig) -> Result<Synthetic, Error> {
+        let element = db.require(&config.element)?;
+        let edge = |name: &str| {
+            element
+                .edge(name)
+                .map(|e| e.energy)
+                .ok_or_else(|| error!("{} has no {} edge", element.symbol, name))
+        };
+        let mut config = config.clone();
+        config.energy_l3 = edge(&config.edges[0])?;
+        config.energy_l2 = match config.edges.get(1) {
+            Some(name) => edge(name)?,
+            None => config.energy_l3,
+        };
+        Ok(Synthetic {
+            config,
+            shape: LineShape::Lorentzian,
+            width: 1.0,
+            white_line: 10.0,
+            m_orb: 0.15,
+            m_spin: 1.6,
+            pre_edge: 0.5,
+            counts: None,
+            seed: 0,
+        })
+    }
+
+    pub fn generate(&self) -> Result<SyntheticSpectra, Error> {
+        let (mut m_orb, mut m_spin) = (self.m_orb, self.m_spin);
+        for _ in 0..CALIBRATION_STEPS {
+            let rules = SumRules::new(&self.pair(m_orb, m_spin, None)?.xmcd()?);
+            let d_orb = self.m_orb - rules.m_orb;
+            let d_spin = if self.config.core_l == 0 {
+                0.0
+            } else {
+                self.m_spin - rules.m_spin
+            };
+            if !d_orb.is_finite() || !d_spin.is_finite() {
+                bail!("The sum rules of the synthetic spectra are not finite");
+            }
+            if d_orb.abs() + d_spin.abs() < 1e-12 * (1.0 + self.m_orb.abs() + self.m_spin.abs()) {
+                break;
+            }
+            m_orb += d_orb;
+            m_spin += d_spin;
+        }
+        self.pair(m_orb, m_spin, self.counts)
+    }
+
+    /// Spectra generated for `m_orb` and `m_spin` before normalization.
+    fn pair(
+        &self,
+        m_orb: f64,
+        m_spin: f64,
+        counts: Option<f64>,
+    ) -> Result<SyntheticSpectra, Error> {
+        let config = &self.config;
+        if config.holes <= 0.0 {
+            bail!("Number of holes must be positive");
+        }
+        let energy = math::arange(config.start_energy, config.end_energy, config.step_energy);
+        let profile = |edge: f64| {
+            energy
+                .iter()
+                .map(|&e| self.shape.eval(e - edge, self.width))
+                .collect::<Vec<_>>()
+        };
+        let l3 = profile(config.energy_l3);
+        let l2 = profile(config.energy_l2);
+
+        // Window integrals of the unit profiles, as the sum rules take them.
+        let (l3_lo, l3_hi) = config.l3_window();
+        let (l2_lo, l2_hi) = config.l2_window();
+        let in_l3 = |y: &[f64]| math::integrate(&energy, y, l3_lo, l3_hi);
+        let in_l2 = |y: &[f64]| math::integrate(&energy, y, l2_lo.max(l3_hi), l2_hi);
+        let (a33, a32) = (in_l3(&l3), in_l3(&l2));
+        let (a23, a22) = (in_l2(&l3), in_l2(&l2));
+
+        let c = config.core_l as f64;
+        let (w3, w2) = if config.core_l == 0 {
+            (self.white_line, 0.0)
+        } else {
+            let first = (2.0 * c + 2.0) / (4.0 * c + 2.0);
+            (first * self.white_line, (1.0 - first) * self.white_line)
+        };
+        let r = 2.0 * (w3 * (a33 + a23) + w2 * (a32 + a22));
+        // Inverse of the sum rules, reduced to the measured dichroism.
+        let factors = Prefactors::new(config.core_l, config.valence_l);
+        let reduction = 1.0 / config.xmcd_correction()?;
+        let scale = r * reduction / config.holes;
+        let q = -m_orb * scale / factors.orbital;
+        let (x3, x2) = if config.core_l == 0 {
+            (q / a33, 0.0)
+        } else {
+            let effective = m_spin * (1.0 + factors.tz * config.tz_ratio) / config.spin_correction;
+            let p = ((c + 1.0) * q - effective * scale / factors.spin) / (2.0 * c + 1.0);
+            let det = a33 * a22 - a32 * a23;
+            if det.abs() < 1e-12 {
+                bail!("The windows cannot separate the two edges");
+            }
+            (
+                (p * a22 - (q - p) * a32) / det,
+                (a33 * (q - p) - a23 * p) / det,
+            )
+        };
+
+        let background = xmcd::two_step(&energy, config);
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut sample = |mu: f64| match counts {
+            Some(counts) => poisson(&mut rng, counts * mu.max(0.0)) / counts,
+            None => mu,
+        };
+        let mut mu_plus = Vec::with_capacity(energy.len());
+        let mut mu_minus = Vec::with_capacity(energy.len());
+        for i in 0..energy.len() {
+            let xas = self.pre_edge + background[i] + w3 * l3[i] + w2 * l2[i];
+            let dichroism = x3 * l3[i] + x2 * l2[i];
+            mu_plus.push(sample(xas + 0.5 * dichroism));
+            mu_minus.push(sample(xas - 0.5 * dichroism));
+        }
+
+        Ok(SyntheticSpectra {
+            config: config.clone(),
+            mu_plus: Spectrum::new(energy.clone(), mu_plus)?,
+            mu_minus: Spectrum::new(energy, mu_minus)?,
+        })
+    }
+}
+
+impl SyntheticSpectra {
+    /// The generating config, with the edge energies from `elem.dat`.
+    pub fn config(&self) -> &ElementConfig {
+        &self.config
+    }
+
+    pub fn mu_plus(&self) -> &Spectrum {
+        &self.mu_plus
+    }
+
+    pub fn mu_minus(&self) -> &Spectrum {
+        &self.mu_minus
+    }
+
+    /// Writes `energy mu_plus mu_minus` lines, as read by
+    /// [`Xas::load_pair`](crate::xas::Xas::load_pair).
+    pub fn write<W: Write>(&self, mut out: W) -> Result<(), Error> {
+        let energy = self.mu_plus.energy();
+        let (plus, minus) = (self.mu_plus.values(), self.mu_minus.values());
+        for i in 0..energy.len() {
+            writeln!(out, "{} {} {}", energy[i], plus[i], minus[i])?;
+        }
+        Ok(())
+    }
+
+    /// Runs the spectra through the pipeline with the generating config.
+    pub fn xmcd(&self) -> Result<Xmcd, Error> {
+        Xmcd::new(&self.mu_plus, &self.mu_minus, &self.config)
+    }
+}
+
+/// Poisson deviate with mean `lambda`.
+fn poisson<R: Rng>(rng: &mut R, lambda: f64) -> f64 {
+    if lambda < POISSON_EXACT {
+        let limit = (-lambda).exp();
+        let mut n = 0.0;
+        let mut product = rng.gen::<f64>();
+        while product > limit {
+            n += 1.0;
+            product *= rng.gen::<f64>();
+        }
+        n
+    } else {
+        // Box–Muller.
+        let u = 1.0 - rng.gen::<f64>();
+        let v = rng.gen::<f64>();
+        let normal = (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos();
+        (lambda + lambda.sqrt() * normal).round().max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::config::Ini;
+
+    #[test]
+    fn sum_rules_recover_the_generating_moments() {
+        let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("data");
+        let db = Database::load(data.join("elem.dat")).unwrap();
+        let ini = Ini::load(data.join("element.ini")).unwrap();
+        for &(element, m_orb, m_spin) in &[("Co", 0.15, 1.6), ("Tb", 1.2, 5.8)] {
+            let mut config = ElementConfig::new(&ini, element).unwrap();
+            config.pc = 0.9;
+            config.angle = 30.0;
+            let mut synthetic = Synthetic::new(&db, &config).unwrap();
+            synthetic.shape = LineShape::Voigt { sigma: 0.3 };
+            synthetic.m_orb = m_orb;
+            synthetic.m_spin = m_spin;
+            let spectra = synthetic.generate().unwrap();
+            let xmcd = Xmcd::new(spectra.mu_plus(), spectra.mu_minus(), spectra.config()).unwrap();
+            let rules = SumRules::new(&xmcd);
+            assert!(
+                (rules.m_orb - m_orb).abs() < 1e-6,
+                "{} {}",
+                element,
+                rules.m_orb
+            );
+            assert!(
+                (rules.m_spin - m_spin).abs() < 1e-6,
+                "{} {}",
+                element,
+                rules.m_spin
+            );
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::elem::{Composition, Database};
+use crate::pipeline::Step;
+use crate::selfabs::Absorber;
+use crate::spectrum::Spectrum;
+use crate::xmcd::Xmcd;
+use crate::Error;
+
+/// Source of the absolute absorption scale.
+#[derive(Debug, Clone)]
+pub enum Attenuation {
+    /// Cross sections of the sample composition from `elem.dat`.
+    Tables {
+        db: Database,
+        composition: Composition,
+        absorber: String,
+        edge: String,
+    },
+    /// Scale of the measured spectrum itself: attenuation below the edge
+    /// and the edge jump, both in 1/cm.
+    Measured { pre_edge: f64, edge_jump: f64 },
+}
+
+/// Saturation correction of total-electron-yield spectra.
+///
+/// Electrons created at depth `z` escape with probability `exp(−z/λe)`, so
+/// for a thick sample the yield is
+///
+/// `Y(E) ∝ μa(E)·λe / (μt(E)·λe + cos θ)`
+///
+/// with `θ` the incidence angle from the surface normal. This is inverted
+/// for `μa(E)`, normalized to one at the normalization energy.
+///
+/// As a pipeline [`Step`] it needs the spectrum normalized to unit edge
+/// jump; a recipe only accepts it after a `normalize` step.
+#[derive(Debug, Clone)]
+pub struct TeySaturation {
+    pub attenuation: Attenuation,
+    /// Electron escape depth in nm.
+    pub escape_depth: f64,
+    /// Incidence angle from the surface normal, in degrees.
+    pub angle_from_normal: f64,
+    /// Energy at which a spectrum corrected as a pipeline step is
+    /// normalized to one; `None` takes its highest energy.
+    pub norm_energy: Option<f64>,
+}
+
+impl TeySaturation {
+    /// Corrects one normalized spectrum; `norm_energy` is where it equals
+    /// one.
+    pub fn correct(&self, spectrum: &Spectrum, norm_energy: f64) -> Result<Spectrum, Error> {
+        let (energy, normalized) = (spectrum.energy(), spectrum.values());
+        if self.escape_depth <= 0.0 {
+            bail!("Escape depth must be positive");
+        }
+        let cos = self.angle_from_normal.to_radians().cos();
+        if cos <= 0.0 {
+            bail!("Incidence angle must be below 90°");
+        }
+        // Escape term cos θ / λe, in 1/cm.
+        let escape = cos / (self.escape_depth * 1e-7);
+
+        let (mu_n, beta_n, betas) = match &self.attenuation {
+            Attenuation::Tables {
+                db,
+                composition,
+                absorber,
+                edge,
+            } => {
+                let absorber = Absorber::new(db, composition, absorber, edge)?;
+                let betas = energy
+                    .iter()
+                    .map(|&e| Ok(absorber.background(e)? + escape))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                (
+                    absorber.edge_jump(norm_energy),
+                    absorber.background(norm_energy)? + escape,
+                    betas,
+                )
+            }
+            Attenuation::Measured {
+                pre_edge,
+                edge_jump,
+            } => (
+                *edge_jump,
+                pre_edge + escape,
+                vec![pre_edge + escape; energy.len()],
+            ),
+        };
+        if mu_n <= 0.0 {
+            bail!("Edge jump at {} eV must be positive", norm_energy);
+        }
+
+        // m = c·μa / (μa + β), with m(En) = 1.
+        let c = (mu_n + beta_n) / mu_n;
+        let mu_a = normalized
+            .iter()
+            .zip(&betas)
+            .map(|(&m, &beta)| {
+                if m >= c {
+                    bail!("Normalized yield {} exceeds the saturation limit {}", m, c);
+                }
+                Ok(m * beta / (c - m) / mu_n)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        spectrum.with_values(mu_a)
+    }
+
+    /// Corrects both helicities at their
+    /// [`norm_energy`](Xmcd::norm_energy); the result feeds the sum rules
+    /// directly.
+    pub fn apply(&self, xmcd: &Xmcd) -> Result<Xmcd, Error> {
+        let norm_energy = xmcd.norm_energy();
+        xmcd.correct_with(self.describe(), |mu| self.correct(mu, norm_energy))
+    }
+}
+
+impl Step for TeySaturation {
+    fn name(&self) -> &'static str {
+        "tey"
+    }
+
+    fn parameters(&self) -> Vec<(&'static str, f64)> {
+        let mut parameters = vec![
+            ("escape_depth", self.escape_depth),
+            ("angle_from_normal", self.angle_from_normal),
+        ];
+        match &self.attenuation {
+            Attenuation::Tables { composition, .. } => {
+                parameters.push(("density", composition.density))
+            }
+            Attenuation::Measured {
+                pre_edge,
+                edge_jump,
+            } => {
+                parameters.push(("pre_edge", *pre_edge));
+                parameters.push(("edge_jump", *edge_jump));
+            }
+        }
+        if let Some(norm_energy) = self.norm_energy {
+            parameters.push(("norm_energy", norm_energy));
+        }
+        parameters
+    }
+
+    fn apply(&self, spectrum: &Spectrum) -> Result<Spectrum, Error> {
+        let norm_energy = self.norm_energy.unwrap_or_else(|| spectrum.range().1);
+        self.correct(spectrum, norm_energy)
+    }
+
+    fn describe(&self) -> String {
+        let mut text = self.name().to_string();
+        if let Attenuation::Tables {
+            composition,
+            absorber,
+            edge,
+            ..
+        } = &self.attenuation
+        {
+            text += &format!(
+                " formula={} absorber={} edge={}",
+                composition, absorber, edge
+            );
+        }
+        for (key, value) in self.parameters() {
+            text += &format!(" {}={}", key, value);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math;
+
+    /// Normalized yield with a white line of 1.5 at 778.6 eV.
+    fn yield_spectrum() -> Spectrum {
+        let energy = (0..=400)
+            .map(|i| 770.0 + 0.1 * i as f64)
+            .collect::<Vec<_>>();
+        let values = energy
+            .iter()
+            .map(|&e| {
+                let step = 0.5 + ((e - 778.1) / 0.5).atan() / std::f64::consts::PI;
+                step + 0.5 * (-((e - 778.6) / 1.0).powi(2)).exp()
+            })
+            .collect();
+        Spectrum::new(energy, values).unwrap()
+    }
+
+    fn tey(escape_depth: f64, angle_from_normal: f64) -> TeySaturation {
+        TeySaturation {
+            attenuation: Attenuation::Measured {
+                pre_edge: 1e4,
+                edge_jump: 3e4,
+            },
+            escape_depth,
+            angle_from_normal,
+            norm_energy: Some(810.0),
+        }
+    }
+
+    #[test]
+    fn short_escape_depths_need_no_correction() {
+        let measured = yield_spectrum();
+        // λe·μt ≪ cos θ: the yield is proportional to μa.
+        let corrected = Step::apply(&tey(0.01, 0.0), &measured).unwrap();
+        for (m, c) in measured.values().iter().zip(corrected.values()) {
+            assert!((m - c).abs() < 1e-4 * m.abs().max(1.0), "{} {}", m, c);
+        }
+
+        // With β = 10⁴ + cos 60°/5 nm and c = (3·10⁴ + β)/3·10⁴ in 1/cm,
+        // μa/μa(En) = m·β / (c − m) / 3·10⁴; the white line grows.
+        let corrected = Step::apply(&tey(5.0, 60.0), &measured).unwrap();
+        let beta = 1e4 + 0.5 / 5e-7;
+        let c = (3e4 + beta) / 3e4;
+        let m = measured.at(810.0);
+        assert!((corrected.at(810.0) - m * beta / (c - m) / 3e4).abs() < 1e-12);
+        let i = math::argmax(measured.values()).unwrap();
+        let m = measured.values()[i];
+        let expected = m * beta / (c - m) / 3e4;
+        assert!((corrected.values()[i] - expected).abs() < 1e-12 * expected);
+        assert!(corrected.values()[i] > m);
+        assert!(Step::apply(&tey(5.0, 100.0), &measured).is_err());
+    }
+}
@@ -1,17 +1,21 @@
-use gnuplot::*;
-
 use nalgebra::DVector;
 use rbf_interp::{Basis, Scatter};
 
+use crate::config::ElementConfig;
+use crate::math;
+use crate::pipeline::{self, Pipeline};
+use crate::plot::{Color, Figure, Panel, PlotOptions, Style};
+use crate::spectrum::Spectrum;
+use crate::xdi::{self, Xdi};
+use crate::xmcd;
 use crate::Error;
 
 #[derive(Debug)]
 pub struct Xas {
-    fit_preedge: Vec<f64>,
-    ene: Vec<f64>,
-    mu: Vec<f64>,
-    pub energy: Vec<f64>,
-    pub mui: Vec<f64>,
+    /// Measured μ = I1 / I0, or as an XDI file labels it.
+    pub raw: Spectrum,
+    /// μ interpolated onto a regular grid.
+    pub spectrum: Spectrum,
     pub e0: f64,
 }
 
@@ -21,12 +25,10 @@ impl Xas {
         R: std::io::BufRead,
     {
         let step = 0.1;
-        let fit_preedge = Vec::new();
 
-        let (ene, i0, i1) = Xas::load_from_file(input)?;
-        assert!(ene.len() == i0.len() && i0.len() == i1.len());
+        let raw = Xas::read_raw(input)?;
+        let (ene, mu) = (raw.energy().to_vec(), raw.values().to_vec());
         let size = ene.len();
-        let mu = (0..size).map(|i| i1[i] / i0[i]).collect::<Vec<_>>();
 
         let start = ene[0].round();
         let stop = ene[size - 1].round();
@@ -45,16 +47,50 @@ impl Xas {
             mui = mui[0..size - diff].to_vec();
         }
 
-        Ok(Xas {
-            ene,
+        let mut spectrum = Spectrum::new(energy, mui)?;
+        spectrum.metadata = raw.metadata.clone();
+        let (first, last) = spectrum.range();
+        let description = format!(
+            "interpolate start={} stop={} points={}",
+            first,
+            last,
+            spectrum.len()
+        );
+        pipeline::record(&mut spectrum.metadata, description);
+        Ok(Xas { raw, spectrum, e0 })
+    }
 
-            mu,
-            energy,
-            mui,
-            e0,
+    /// Measured μ passed through `pipeline` instead of the fixed
+    /// interpolation of [`Xas::new`]; `e0` is the energy of the maximum of
+    /// the result.
+    pub fn from_pipeline<R>(input: R, pipeline: &Pipeline) -> Result<Xas, Error>
+    where
+        R: std::io::BufRead,
+    {
+        let raw = Xas::read_raw(input)?;
+        let spectrum = pipeline.run(&raw)?;
+        let e0 = Xas::find_max_energy(spectrum.values().to_vec(), spectrum.energy())?;
+        Ok(Xas { raw, spectrum, e0 })
+    }
 
-            fit_preedge,
-        })
+    /// Interpolated μ scaled to zero at the pre-edge and one at the
+    /// post-edge level of `config`, with the step recorded.
+    pub fn normalized(&self, config: &ElementConfig) -> Result<Spectrum, Error> {
+        let values = xmcd::normalize(self.spectrum.energy(), self.spectrum.values(), config)?;
+        let mut normalized = self.spectrum.with_values(values)?;
+        let description = format!(
+            "normalize pre_start={} pre_end={} post_start={}",
+            config.preedge_start,
+            config.preedge_start + config.preedge_width,
+            config.post_start()
+        );
+        pipeline::record(&mut normalized.metadata, description);
+        Ok(normalized)
+    }
+
+    /// Interpolated μ at `energy`, constant beyond the measured range.
+    pub fn resampled(&self, energy: &[f64]) -> Vec<f64> {
+        math::resample(self.spectrum.energy(), self.spectrum.values(), energy)
     }
 
     pub fn get_elem(&self) -> ! {
@@ -97,20 +133,56 @@ impl Xas {
         Ok(energy[max_index])
     }
 
+    /// μ = I1 / I0 from energy, I0 and I1 columns, or from an XDI file
+    /// with its header fields as metadata.
+    fn read_raw<R>(mut input: R) -> Result<Spectrum, Error>
+    where
+        R: std::io::BufRead,
+    {
+        if xdi::peek(&mut input)? {
+            return Xdi::read(input)?.spectrum();
+        }
+        let (ene, i0, i1) = Xas::load_from_file(input)?;
+        let mu = i1
+            .iter()
+            .zip(&i0)
+            .map(|(i1, i0)| i1 / i0)
+            .collect::<Vec<_>>();
+        Spectrum::from_unsorted(&ene, &mu)
+    }
+
+    /// The first three columns; of an XDI file, in its column order.
     pub fn load_from_file<R>(mut input: R) -> Result<(Vec<f64>, Vec<f64>, Vec<f64>), Error>
     where
         R: std::io::BufRead,
     {
+        if xdi::peek(&mut input)? {
+            let mut data = Xdi::read(input)?.columns.data;
+            if data.len() < 3 {
+                bail!("Expected three columns, found {}", data.len());
+            }
+            data.truncate(3);
+            let i1 = data.pop().unwrap();
+            let i0 = data.pop().unwrap();
+            return Ok((data.pop().unwrap(), i0, i1));
+        }
         let mut ene = Vec::new();
         let mut i0 = Vec::new();
         let mut i1 = Vec::new();
 
         let mut buffer = String::new();
         while input.read_line(&mut buffer)? > 0 {
+            if buffer.trim().is_empty() || buffer.starts_with('#') {
+                buffer.clear();
+                continue;
+            }
             let line = buffer
                 .split(' ')
-                .map(|s| s.trim().parse::<f64>().expect("Cannot parse to float"))
-                .collect::<Vec<_>>();
+                .map(|s| s.trim().parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()?;
+            if line.len() < 3 {
+                bail!("Expected three columns, found {}", line.len());
+            }
             ene.push(line[0]);
             i0.push(line[1]);
             i1.push(line[2]);
@@ -120,36 +192,39 @@ impl Xas {
         Ok((ene, i0, i1))
     }
 
-    pub fn plot(&self) -> Result<(), Error> {
-        let mut fg = Figure::new();
-        fg.set_terminal("wxt size 1200,800", "out");
-        let x = &self.ene;
-        let x = x.iter2();
-        let y = &self.mu;
-        let y = y.iter2();
-
-        let x2 = &self.energy;
-        let x2 = x2.iter2();
-        let y2 = &self.mui;
-        let y2 = y2.iter2();
-
-        fg.axes2d()
-            .set_size(1.0, 0.7)
-            .set_pos(0.0, 0.2)
-            .points(
-                x,
-                y,
-                &[
-                    PointSymbol('o'),
-                    Color("blue"),
-                    BorderColor("red"),
-                    LineWidth(2.0),
-                ],
+    /// The second and third columns against the first as two spectra,
+    /// e.g. μ+ and μ−, read as by [`Xas::load_from_file`].
+    pub fn load_pair<R>(input: R) -> Result<(Spectrum, Spectrum), Error>
+    where
+        R: std::io::BufRead,
+    {
+        let (energy, a, b) = Xas::load_from_file(input)?;
+        Ok((Spectrum::new(energy.clone(), a)?, Spectrum::new(energy, b)?))
+    }
+
+    /// Raw μ points and the interpolated line.
+    pub fn figure(&self) -> Figure {
+        let panel = Panel::new("Energy (eV)", "μ")
+            .trace(
+                "μ",
+                self.raw.energy(),
+                self.raw.values(),
+                Style::Points,
+                Color::BLUE,
             )
-            .lines(x2, y2, &[Color("red"), BorderColor("red")]);
+            .trace(
+                "interpolated",
+                self.spectrum.energy(),
+                self.spectrum.values(),
+                Style::Line,
+                Color::RED,
+            );
+        Figure::new(vec![panel])
+    }
 
-        fg.show().unwrap();
-        Ok(())
+    /// Renders the figure into the file of `options`.
+    pub fn plot(&self, options: &PlotOptions) -> Result<(), Error> {
+        self.figure().save(options)
     }
 }
 
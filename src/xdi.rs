@@ -0,0 +1,302 @@
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+use crate::columns::{Columns, Detection};
+use crate::spectrum::{Metadata, Spectrum};
+use crate::Error;
+
+/// `hc` in eV·Å, for energies from monochromator angles.
+const HC: f64 = 12_398.419_843;
+
+/// Does `line` open an XDI file?
+pub fn is_xdi(line: &str) -> bool {
+    line.trim_start().starts_with("# XDI/")
+}
+
+/// Does the unread part of `input` open an XDI file? Nothing is consumed.
+pub fn peek<R: BufRead>(input: &mut R) -> Result<bool, Error> {
+    Ok(is_xdi(&String::from_utf8_lossy(input.fill_buf()?)))
+}
+
+/// File in the XAS Data Interchange format: a `# XDI/1.0` version line,
+/// `# Namespace.tag: value` fields, free comments after `# ///`, and
+/// whitespace-separated rows after `#----`. Columns are named by their
+/// `Column.N` fields.
+#[derive(Debug, Clone)]
+pub struct Xdi {
+    /// The version line without the leading `# `.
+    pub version: String,
+    /// Every field, including `Column.N`.
+    pub fields: Metadata,
+    pub comments: Vec<String>,
+    pub columns: Columns,
+    /// Unit of each column, empty if not given.
+    pub units: Vec<String>,
+}
+
+impl Xdi {
+    pub fn read<R: BufRead>(input: R) -> Result<Xdi, Error> {
+        let mut lines = input.lines().enumerate();
+        let version = loop {
+            match lines.next() {
+                Some((_, line)) => {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    if !is_xdi(&line) {
+                        bail!("Not an XDI file: the first line must start with `# XDI/`");
+                    }
+                    break line.trim().trim_start_matches('#').trim().to_string();
+                }
+                None => bail!("Empty XDI file"),
+            }
+        };
+
+        let mut fields = Metadata::new();
+        let mut comments = Vec::new();
+        let mut in_comments = false;
+        let mut labels: Vec<String> = Vec::new();
+        let mut data: Vec<Vec<f64>> = Vec::new();
+        let mut in_data = false;
+        let mut labeled = false;
+        for (n, line) in lines {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(text) = line.strip_prefix('#') {
+                let text = text.trim();
+                if in_data {
+                    // The first comment after `#----` labels the columns.
+                    if !labeled && data.is_empty() {
+                        labels = text.split_whitespace().map(str::to_string).collect();
+                        labeled = true;
+                    }
+                    continue;
+                }
+                if text.starts_with("----") {
+                    in_data = true;
+                } else if text.starts_with("///") {
+                    in_comments = true;
+                } else if in_comments {
+                    comments.push(text.to_string());
+                } else if let Some(colon) = text.find(':') {
+                    let key = text[..colon].trim();
+                    if !key.contains('.') {
+                        bail!("line {}: expected `Namespace.tag: value`", n + 1);
+                    }
+                    let key = normalize_key(key);
+                    fields.insert(key, text[colon + 1..].trim().to_string());
+                } else {
+                    // Label line right before the data without a `#----`.
+                    labels = text.split_whitespace().map(str::to_string).collect();
+                }
+                continue;
+            }
+            in_data = true;
+            let row = line
+                .split_whitespace()
+                .map(|f| f.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| error!("line {}: {}", n + 1, e))?;
+            if data.is_empty() {
+                data = vec![Vec::new(); row.len()];
+            }
+            if row.len() != data.len() {
+                bail!(
+                    "line {}: expected {} columns, found {}",
+                    n + 1,
+                    data.len(),
+                    row.len()
+                );
+            }
+            for (column, value) in data.iter_mut().zip(row) {
+                column.push(value);
+            }
+        }
+        if data.is_empty() {
+            bail!("No data rows");
+        }
+
+        let mut names = Vec::with_capacity(data.len());
+        let mut units = Vec::with_capacity(data.len());
+        for i in 0..data.len() {
+            let (name, unit) = match fields.get(&format!("Column.{}", i + 1)) {
+                Some(value) => {
+                    let mut words = value.split_whitespace();
+                    let name = words.next().unwrap_or_default().to_string();
+                    (name, words.collect::<Vec<_>>().join(" "))
+                }
+                None => (
+                    labels
+                        .get(i)
+                        .cloned()
+                        .unwrap_or_else(|| format!("col{}", i)),
+                    String::new(),
+                ),
+            };
+            names.push(name.to_lowercase());
+            units.push(unit);
+        }
+        let mut columns = Columns::new(names, data)?;
+        columns.metadata = fields
+            .iter()
+            .filter(|(key, _)| !key.starts_with("Column."))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        Ok(Xdi {
+            version,
+            fields,
+            comments,
+            columns,
+            units,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Xdi, Error> {
+        let file = fs::File::open(path)?;
+        Xdi::read(io::BufReader::new(file))
+    }
+
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields.get(&normalize_key(key)).map(String::as_str)
+    }
+
+    pub fn element(&self) -> Option<&str> {
+        self.field("Element.symbol")
+    }
+
+    pub fn edge(&self) -> Option<&str> {
+        self.field("Element.edge")
+    }
+
+    /// Lattice spacing of the monochromator crystal, in Å.
+    pub fn d_spacing(&self) -> Option<f64> {
+        self.field("Mono.d_spacing")
+            .and_then(|d| d.split_whitespace().next())
+            .and_then(|d| d.parse().ok())
+    }
+
+    fn find(&self, labels: &[&str]) -> Option<usize> {
+        labels
+            .iter()
+            .filter_map(|label| self.columns.names.iter().position(|n| n == label))
+            .next()
+    }
+
+    /// Detection mode and the I0 and signal columns, from the standard
+    /// labels: a ready μ (`mutrans`, `mufluor`, `normtrans`, `normfluor`)
+    /// is preferred over `itrans` or `ifluor` with `i0`, and electron
+    /// yield is taken from `iyield`, `itey` or `tey`.
+    pub fn detection(&self) -> Result<(Detection, Option<usize>, usize), Error> {
+        if let Some(mu) = self.find(&["mutrans", "mufluor", "mu", "normtrans", "normfluor"]) {
+            return Ok((Detection::Mu, None, mu));
+        }
+        let i0 = self.find(&["i0"]);
+        let intensities = [
+            (Detection::Transmission, &["itrans", "i1"][..]),
+            (Detection::Fluorescence, &["ifluor", "if"][..]),
+            (Detection::Yield, &["iyield", "itey", "tey"][..]),
+        ];
+        for (mode, labels) in intensities.iter() {
+            if let (Some(i0), Some(signal)) = (i0, self.find(labels)) {
+                return Ok((*mode, Some(i0), signal));
+            }
+        }
+        bail!(
+            "No μ or I0 with itrans, ifluor or iyield among the columns {}",
+            self.columns.names.join(", ")
+        )
+    }
+
+    /// Energy in eV, from an `energy` column in eV or keV, or from an
+    /// `angle` column in degrees or radians with `Mono.d_spacing`.
+    pub fn energy(&self) -> Result<Vec<f64>, Error> {
+        if let Some(i) = self.find(&["energy"]) {
+            let scale = match self.units[i].to_lowercase().as_str() {
+                "" | "ev" => 1.0,
+                "kev" => 1000.0,
+                unit => bail!("Unknown energy unit {:?}", unit),
+            };
+            return Ok(self.columns.data[i].iter().map(|e| e * scale).collect());
+        }
+        if let Some(i) = self.find(&["angle"]) {
+            let d = self
+                .d_spacing()
+                .ok_or_else(|| error!("An angle column needs Mono.d_spacing"))?;
+            let radians = match self.units[i].to_lowercase().as_str() {
+                "" | "deg" | "degrees" => false,
+                "rad" | "radians" => true,
+                unit => bail!("Unknown angle unit {:?}", unit),
+            };
+            let energy = self.columns.data[i]
+                .iter()
+                .map(|&a| {
+                    let theta = if radians { a } else { a.to_radians() };
+                    HC / (2.0 * d * theta.sin())
+                })
+                .collect();
+            return Ok(energy);
+        }
+        bail!("No energy or angle column")
+    }
+
+    /// μ against energy as [`Xdi::detection`] maps the columns, with every
+    /// field except `Column.N` as metadata.
+    pub fn spectrum(&self) -> Result<Spectrum, Error> {
+        let energy = self.energy()?;
+        let (mode, i0, signal) = self.detection()?;
+        let signal = &self.columns.data[signal];
+        let mu = match i0 {
+            Some(i0) => self.columns.data[i0]
+                .iter()
+                .zip(signal)
+                .map(|(&i0, &s)| mode.mu(i0, s))
+                .collect::<Vec<_>>(),
+            None => signal.clone(),
+        };
+        let mut spectrum = Spectrum::from_unsorted(&energy, &mu)?;
+        spectrum.metadata = self.columns.metadata.clone();
+        Ok(spectrum.with_metadata("Detection.mode", &mode.to_string()))
+    }
+}
+
+/// Namespaces and tags are case-insensitive; capitalize the namespace and
+/// lowercase the tag, e.g. `ELEMENT.Symbol` becomes `Element.symbol`.
+fn normalize_key(key: &str) -> String {
+    let mut parts = key.splitn(2, '.');
+    let namespace = parts.next().unwrap_or_default();
+    let tag = parts.next().unwrap_or_default();
+    let mut chars = namespace.chars();
+    let namespace = match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    };
+    format!("{}.{}", namespace, tag.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_fold_case_and_labels_follow_the_separator() {
+        let text = "# XDI/1.0\n# ELEMENT.SYMBOL: Co\n# Mono.D_Spacing: 3.1356\n\
+            # ///\n# a comment\n#----\n# energy i0 itrans\n7700 2 1\n7701 2 0.5\n";
+        let xdi = Xdi::read(text.as_bytes()).unwrap();
+        assert_eq!(xdi.element(), Some("Co"));
+        assert_eq!(xdi.fields["Mono.d_spacing"], "3.1356");
+        assert_eq!(xdi.d_spacing(), Some(3.1356));
+        assert_eq!(xdi.comments, vec!["a comment"]);
+        assert_eq!(xdi.columns.names, vec!["energy", "i0", "itrans"]);
+        let (mode, _, _) = xdi.detection().unwrap();
+        assert_eq!(mode, Detection::Transmission);
+    }
+}
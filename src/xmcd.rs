@@ -0,0 +1,496 @@
+use std::f64::consts::PI;
+
+use crate::config::ElementConfig;
+use crate::math;
+use crate::pipeline;
+use crate::plot::{Color, Figure, Panel, PlotOptions, Style};
+use crate::spectrum::{Metadata, Spectrum};
+use crate::sumrules::SumRules;
+use crate::xas::Xas;
+use crate::Error;
+
+/// Width (eV) of the arctangent steps of the two-step background.
+const STEP_WIDTH: f64 = 0.5;
+
+/// A pair of opposite-helicity spectra resampled onto a common grid and
+/// normalized to unit edge jump.
+#[derive(Debug, Clone)]
+pub struct Xmcd {
+    config: ElementConfig,
+    mu_plus: Spectrum,
+    mu_minus: Spectrum,
+    xas: Spectrum,
+    xmcd: Spectrum,
+    xmcd_raw: Spectrum,
+    correction: f64,
+    background: Spectrum,
+    edge_jump: f64,
+    metadata: Metadata,
+}
+
+impl Xmcd {
+    /// Combines two helicity spectra on the part of the grid of `plus`
+    /// covered by `minus`. Each normalized spectrum keeps the metadata of
+    /// its input; see [`metadata`](Xmcd::metadata) for that of the result.
+    pub fn new(plus: &Spectrum, minus: &Spectrum, config: &ElementConfig) -> Result<Xmcd, Error> {
+        let NormalizedPair {
+            a: mu_plus,
+            b: mu_minus,
+            edge_jump,
+        } = normalize_pair(plus, minus, config)?;
+        let mut metadata = merged(&plus.metadata, &minus.metadata, ("_plus", "_minus"));
+        pipeline::record(&mut metadata, normalization(&mu_plus, config));
+        pipeline::record(
+            &mut metadata,
+            format!(
+                "background ratio={} l3={} l2={} width={}",
+                config.ratio, config.energy_l3, config.energy_l2, STEP_WIDTH
+            ),
+        );
+        pipeline::record(
+            &mut metadata,
+            format!(
+                "correction pc={} angle={} factor={}",
+                config.pc,
+                config.angle,
+                config.xmcd_correction()?
+            ),
+        );
+        Xmcd::from_normalized(config, mu_plus, mu_minus, edge_jump, metadata)
+    }
+
+    fn from_normalized(
+        config: &ElementConfig,
+        mu_plus: Spectrum,
+        mu_minus: Spectrum,
+        edge_jump: f64,
+        metadata: Metadata,
+    ) -> Result<Xmcd, Error> {
+        // On a common grid; uncertainties add in quadrature.
+        let xas = (&mu_plus + &mu_minus)?.scale(0.5)?;
+        let correction = config.xmcd_correction()?;
+        let xmcd_raw = (&mu_plus - &mu_minus)?;
+        let xmcd = xmcd_raw.scale(correction)?;
+        let background = mu_plus.with_values(two_step(mu_plus.energy(), config))?;
+
+        Ok(Xmcd {
+            config: config.clone(),
+            mu_plus,
+            mu_minus,
+            xas,
+            xmcd,
+            xmcd_raw,
+            correction,
+            background,
+            edge_jump,
+            metadata,
+        })
+    }
+
+    /// Applies a correction to each normalized helicity spectrum and
+    /// recomputes the average and the dichroism; the correction is recorded
+    /// in the metadata as `description`. `step` must keep the energy grid.
+    pub fn correct_with<F>(&self, description: String, step: F) -> Result<Xmcd, Error>
+    where
+        F: Fn(&Spectrum) -> Result<Spectrum, Error>,
+    {
+        let mu_plus = step(&self.mu_plus)?;
+        let mu_minus = step(&self.mu_minus)?;
+        if mu_plus.energy() != self.energy() || mu_minus.energy() != self.energy() {
+            bail!("A correction must keep the energy grid");
+        }
+        let mut metadata = self.metadata.clone();
+        pipeline::record(&mut metadata, description);
+        Xmcd::from_normalized(&self.config, mu_plus, mu_minus, self.edge_jump, metadata)
+    }
+
+    pub fn config(&self) -> &ElementConfig {
+        &self.config
+    }
+
+    /// The common energy grid.
+    pub fn energy(&self) -> &[f64] {
+        self.xas.energy()
+    }
+
+    pub fn mu_plus(&self) -> &Spectrum {
+        &self.mu_plus
+    }
+
+    pub fn mu_minus(&self) -> &Spectrum {
+        &self.mu_minus
+    }
+
+    /// Helicity-averaged absorption, (μ+ + μ−) / 2.
+    pub fn xas(&self) -> &Spectrum {
+        &self.xas
+    }
+
+    /// Dichroism corrected for polarization degree and angle,
+    /// (μ+ − μ−) / (Pc·cos θ).
+    pub fn xmcd(&self) -> &Spectrum {
+        &self.xmcd
+    }
+
+    /// Measured dichroism, μ+ − μ−.
+    pub fn xmcd_raw(&self) -> &Spectrum {
+        &self.xmcd_raw
+    }
+
+    /// Factor applied to the measured dichroism, 1 / (Pc·cos θ).
+    pub fn correction(&self) -> f64 {
+        self.correction
+    }
+
+    /// Two-step L3/L2 background under the averaged absorption.
+    pub fn background(&self) -> &Spectrum {
+        &self.background
+    }
+
+    /// Edge jump of the averaged spectrum before normalization.
+    pub fn edge_jump(&self) -> f64 {
+        self.edge_jump
+    }
+
+    /// Metadata of both inputs followed by the XMCD steps as `Process.NN`.
+    /// Entries the inputs disagree on, or that only one of them has, are
+    /// kept for each with a `_plus` or `_minus` suffix on the key.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Energy at which the normalized spectra are unity: the middle of the
+    /// post-edge region.
+    pub fn norm_energy(&self) -> f64 {
+        let last = self.xas.range().1;
+        let start = self.config.post_start();
+        if start < last {
+            0.5 * (start + last)
+        } else {
+            last
+        }
+    }
+
+    /// Reads a three-column file: energy, μ+ and μ−.
+    pub fn from_columns<R>(input: R, config: &ElementConfig) -> Result<Xmcd, Error>
+    where
+        R: std::io::BufRead,
+    {
+        let (plus, minus) = Xas::load_pair(input)?;
+        Xmcd::new(&plus, &minus, config)
+    }
+
+    /// Combines two separately measured helicity scans.
+    pub fn from_pair(plus: &Xas, minus: &Xas, config: &ElementConfig) -> Result<Xmcd, Error> {
+        Xmcd::new(&plus.spectrum, &minus.spectrum, config)
+    }
+
+    /// Summary of the analysis in three panels: μ+, μ−, their average and
+    /// the two-step background; the XMCD; and the running sum-rule
+    /// integrals over the shaded windows, annotated with `p`, read
+    /// `offset.p` eV from the second edge, and `q` and `r`, read at the end
+    /// of the second window.
+    pub fn figure(&self) -> Figure {
+        let e = self.energy();
+        let config = &self.config;
+        let spectra = Panel::new("Energy (eV)", "normalized μ")
+            .trace("μ+", e, self.mu_plus.values(), Style::Line, Color::RED)
+            .trace("μ−", e, self.mu_minus.values(), Style::Line, Color::BLUE)
+            .trace(
+                "(μ+ + μ−)/2",
+                e,
+                self.xas.values(),
+                Style::Line,
+                Color::BLACK,
+            )
+            .trace(
+                "background",
+                e,
+                self.background.values(),
+                Style::Dashed,
+                Color::GRAY,
+            );
+        let xmcd = Panel::new("Energy (eV)", "XMCD").trace(
+            "",
+            e,
+            self.xmcd.values(),
+            Style::Line,
+            Color::GREEN,
+        );
+
+        let (l3_lo, l3_hi) = config.l3_window();
+        let (l2_lo, l2_hi) = config.l2_window();
+        let l2_lo = l2_lo.max(l3_hi);
+        let in_windows = |e: f64| (e >= l3_lo && e <= l3_hi) || (e >= l2_lo && e <= l2_hi);
+        let running = |y: &[f64]| {
+            let masked = e
+                .iter()
+                .zip(y)
+                .map(|(&e, &y)| if in_windows(e) { y } else { 0.0 })
+                .collect::<Vec<_>>();
+            math::cumtrapz(e, &masked)
+        };
+        let xmcd_integral = running(self.xmcd_raw.values());
+        let white_line_integral = running(&self.white_line());
+        let rules = SumRules::new(self);
+        let at_p = config.energy_l2 + config.offset_p;
+        let end = l2_hi.max(l3_hi);
+        let integrals = Panel::new("Energy (eV)", "integral")
+            .span(l3_lo, l3_hi, Color::ORANGE)
+            .span(l2_lo, l2_hi, Color::BLUE)
+            .trace("∫XMCD", e, &xmcd_integral, Style::Line, Color::GREEN)
+            .trace(
+                "∫(μ+ + μ− − 2·bg)",
+                e,
+                &white_line_integral,
+                Style::Line,
+                Color::BLACK,
+            )
+            .note(&format!("p = {:.4}", rules.p), at_p, rules.p)
+            .note(&format!("q = {:.4}", rules.q), end, rules.q)
+            .note(&format!("r = {:.4}", rules.r), end, rules.r);
+        Figure::new(vec![spectra, xmcd, integrals])
+    }
+
+    /// Renders the figure into the file of `options`.
+    pub fn plot(&self, options: &PlotOptions) -> Result<(), Error> {
+        self.figure().save(options)
+    }
+
+    /// Helicity sum minus the two-step background, `μ+ + μ− − 2·bg`, the
+    /// integrand of `r`.
+    pub(crate) fn white_line(&self) -> Vec<f64> {
+        self.xas
+            .values()
+            .iter()
+            .zip(self.background.values())
+            .map(|(xas, bg)| 2.0 * (xas - bg))
+            .collect()
+    }
+
+    /// Energy of the absorption maximum.
+    pub fn peak_position(&self) -> f64 {
+        math::argmax(self.xas.values()).map_or(f64::NAN, |i| self.energy()[i])
+    }
+
+    /// Signed XMCD value of largest magnitude.
+    pub fn amplitude(&self) -> f64 {
+        let xmcd = self.xmcd.values();
+        let abs = xmcd.iter().map(|v| v.abs()).collect::<Vec<_>>();
+        math::argmax(&abs).map_or(f64::NAN, |i| xmcd[i])
+    }
+}
+
+/// The entries of `a` and `b`, with the suffixes appended to the key where
+/// they differ, e.g. `_plus` and `_minus`.
+pub(crate) fn merged(a: &Metadata, b: &Metadata, suffixes: (&str, &str)) -> Metadata {
+    let mut metadata = Metadata::new();
+    for key in a.keys().chain(b.keys()) {
+        match (a.get(key), b.get(key)) {
+            (Some(x), Some(y)) if x == y => {
+                metadata.insert(key.clone(), x.clone());
+            }
+            (x, y) => {
+                if let Some(x) = x {
+                    metadata.insert(format!("{}{}", key, suffixes.0), x.clone());
+                }
+                if let Some(y) = y {
+                    metadata.insert(format!("{}{}", key, suffixes.1), y.clone());
+                }
+            }
+        }
+    }
+    metadata
+}
+
+/// The `normalize` step that put `normalized` on its grid, as recorded in
+/// the metadata.
+pub(crate) fn normalization(normalized: &Spectrum, config: &ElementConfig) -> String {
+    let (start, stop) = normalized.range();
+    format!(
+        "normalize start={} stop={} step={} pre_start={} pre_end={} post_start={}",
+        start,
+        stop,
+        config.step_energy,
+        config.preedge_start,
+        config.preedge_start + config.preedge_width,
+        config.post_start()
+    )
+}
+
+/// Two spectra on a common grid, each normalized to unit edge jump, and the
+/// raw edge jump of their average.
+pub(crate) struct NormalizedPair {
+    pub a: Spectrum,
+    pub b: Spectrum,
+    pub edge_jump: f64,
+}
+
+/// Resamples two spectra onto the element's energy grid, within the part
+/// of `a` covered by `b`, and normalizes each to unit edge jump.
+pub(crate) fn normalize_pair(
+    a: &Spectrum,
+    b: &Spectrum,
+    config: &ElementConfig,
+) -> Result<NormalizedPair, Error> {
+    let (a, b) = a.matched(b)?;
+    if a.len() < 2 {
+        bail!("Need at least two points");
+    }
+    let (first, last) = a.range();
+    let start = config.start_energy.max(first);
+    let stop = config.end_energy.min(last);
+    if stop <= start {
+        bail!(
+            "Data ({}..{} eV) do not overlap the {} range",
+            first,
+            last,
+            config.element
+        );
+    }
+    let grid = math::arange(start, stop, config.step_energy);
+    let resample = |s: &Spectrum| -> Result<Spectrum, Error> {
+        let values = math::resample(s.energy(), s.values(), &grid);
+        let sigma = s
+            .uncertainty()
+            .map(|u| math::resample(s.energy(), u, &grid));
+        let mut resampled = Spectrum::new(grid.clone(), values)?;
+        if let Some(sigma) = sigma {
+            resampled = resampled.with_uncertainty(sigma)?;
+        }
+        resampled.metadata = s.metadata.clone();
+        Ok(resampled)
+    };
+    let a = resample(&a)?;
+    let b = resample(&b)?;
+    let avg = a
+        .values()
+        .iter()
+        .zip(b.values())
+        .map(|(a, b)| 0.5 * (a + b))
+        .collect::<Vec<_>>();
+
+    let (pre, post) = edge_levels(&grid, &avg, config)?;
+    let edge_jump = post - pre;
+    if edge_jump == 0.0 {
+        bail!("Zero edge jump");
+    }
+
+    let a = normalized(&a, config)?;
+    let b = normalized(&b, config)?;
+    Ok(NormalizedPair { a, b, edge_jump })
+}
+
+/// Mean μ in the pre-edge window, or between the first point and
+/// `pre_end` if that window is outside the data, and in the post-edge
+/// window.
+fn edge_levels(energy: &[f64], mu: &[f64], config: &ElementConfig) -> Result<(f64, f64), Error> {
+    let last = energy[energy.len() - 1];
+    let pre_start = config.preedge_start;
+    let pre_end = config.preedge_start + config.preedge_width;
+    let pre = math::mean_in(energy, mu, pre_start, pre_end)
+        .or_else(|| math::mean_in(energy, mu, energy[0], config.pre_end()))
+        .ok_or_else(|| {
+            error!(
+                "No points in the pre-edge window {}–{} eV; the data start at {} eV",
+                pre_start, pre_end, energy[0]
+            )
+        })?;
+    let post_start = config.post_start();
+    let post = math::mean_in(energy, mu, post_start, last).ok_or_else(|| {
+        error!(
+            "No points in the post-edge window from {} eV; the data end at {} eV",
+            post_start, last
+        )
+    })?;
+    Ok((pre, post))
+}
+
+pub(crate) fn normalize(
+    energy: &[f64],
+    mu: &[f64],
+    config: &ElementConfig,
+) -> Result<Vec<f64>, Error> {
+    let (pre, post) = edge_levels(energy, mu, config)?;
+    if post == pre {
+        bail!("Zero edge jump");
+    }
+    Ok(mu.iter().map(|y| (y - pre) / (post - pre)).collect())
+}
+
+/// `mu` scaled to zero at the pre-edge and one at the post-edge level; the
+/// uncertainties are divided by the same edge jump.
+fn normalized(mu: &Spectrum, config: &ElementConfig) -> Result<Spectrum, Error> {
+    let (pre, post) = edge_levels(mu.energy(), mu.values(), config)?;
+    if post == pre {
+        bail!("Zero edge jump");
+    }
+    let jump = post - pre;
+    let values = mu.values().iter().map(|y| (y - pre) / jump).collect();
+    let sigma = mu
+        .uncertainty()
+        .map(|s| s.iter().map(|s| s / jump.abs()).collect());
+    mu.with_values_and_uncertainty(values, sigma)
+}
+
+pub(crate) fn two_step(energy: &[f64], config: &ElementConfig) -> Vec<f64> {
+    let step = |e: f64, edge: f64| 0.5 + ((e - edge) / STEP_WIDTH).atan() / PI;
+    let h3 = 1.0 / (1.0 + config.ratio);
+    let h2 = config.ratio / (1.0 + config.ratio);
+    energy
+        .iter()
+        .map(|&e| h3 * step(e, config.energy_l3) + h2 * step(e, config.energy_l2))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Ini;
+
+    fn co() -> Xmcd {
+        let ini = Ini::new(include_str!("../data/element.ini").as_bytes()).unwrap();
+        let config = ElementConfig::new(&ini, "Co").unwrap();
+        Xmcd::from_columns(include_str!("../data/raw_mat.txt").as_bytes(), &config).unwrap()
+    }
+
+    #[test]
+    fn corrections_keep_the_grid() {
+        let xmcd = co();
+        let doubled = xmcd
+            .correct_with("scale".to_string(), |mu| mu.scale(2.0))
+            .unwrap();
+        assert_eq!(doubled.energy(), xmcd.energy());
+        let steps = pipeline::provenance(doubled.metadata());
+        assert_eq!(steps.len(), 4);
+        assert!(steps[0].starts_with("normalize"), "{:?}", steps);
+        assert_eq!(steps[3], "scale");
+        let (lo, hi) = xmcd.mu_plus().range();
+        let cropped = xmcd.correct_with("crop".to_string(), |mu| mu.crop(lo + 1.0, hi));
+        assert!(cropped.is_err());
+    }
+
+    #[test]
+    fn uncertainties_follow_the_normalization() {
+        let ini = Ini::new(include_str!("../data/element.ini").as_bytes()).unwrap();
+        let config = ElementConfig::new(&ini, "Co").unwrap();
+        let (plus, _) = Xas::load_pair(include_str!("../data/raw_mat.txt").as_bytes()).unwrap();
+        let plus = plus
+            .clone()
+            .with_uncertainty(vec![0.01; plus.len()])
+            .unwrap();
+        // Equal helicities share the edge jump of their average.
+        let xmcd = Xmcd::new(&plus, &plus, &config).unwrap();
+        let sigma = 0.01 / xmcd.edge_jump();
+        let check = |s: &Spectrum, expected: f64| {
+            for u in s.uncertainty().unwrap() {
+                assert!((u / expected - 1.0).abs() < 1e-9, "{} {}", u, expected);
+            }
+        };
+        check(xmcd.mu_plus(), sigma);
+        check(xmcd.xas(), sigma / 2f64.sqrt());
+        check(xmcd.xmcd_raw(), sigma * 2f64.sqrt());
+        check(xmcd.xmcd(), sigma * 2f64.sqrt() * xmcd.correction().abs());
+        assert!(xmcd.background().uncertainty().is_none());
+    }
+}
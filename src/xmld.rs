@@ -0,0 +1,323 @@
+use crate::config::ElementConfig;
+use crate::pipeline;
+use crate::plot::{Color, Figure, Panel, PlotOptions, Style};
+use crate::spectrum::{Metadata, Spectrum};
+use crate::xas::Xas;
+use crate::xmcd::{merged, normalization, normalize_pair, NormalizedPair};
+use crate::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinearDichroism {
+    /// XMLD: LH along the in-plane magnetization, LV perpendicular to it.
+    Magnetic,
+    /// XNLD: anisotropy between the surface normal and the sample plane.
+    Natural,
+}
+
+impl LinearDichroism {
+    /// `xmld` or `xnld`.
+    pub fn name(self) -> &'static str {
+        match self {
+            LinearDichroism::Magnetic => "xmld",
+            LinearDichroism::Natural => "xnld",
+        }
+    }
+
+    /// Fraction of the LH − LV difference seen at `angle_from_normal`
+    /// degrees of incidence from the surface normal. The LH field vector lies in the
+    /// scattering plane, so it projects as cos² onto the sample plane (XMLD)
+    /// and as sin² onto the normal (XNLD); LV always stays in-plane.
+    pub fn projection(self, angle_from_normal: f64) -> f64 {
+        let theta = angle_from_normal.to_radians();
+        match self {
+            LinearDichroism::Magnetic => theta.cos().powi(2),
+            LinearDichroism::Natural => theta.sin().powi(2),
+        }
+    }
+}
+
+/// A pair of linear-horizontal/linear-vertical scans normalized to unit edge
+/// jump, with the dichroism corrected for the incidence angle.
+#[derive(Debug, Clone)]
+pub struct Xmld {
+    kind: LinearDichroism,
+    config: ElementConfig,
+    angle_from_normal: f64,
+    mu_h: Spectrum,
+    mu_v: Spectrum,
+    xas: Spectrum,
+    raw: Spectrum,
+    dichroism: Spectrum,
+    edge_jump: f64,
+    metadata: Metadata,
+}
+
+impl Xmld {
+    /// Combines two linear-polarization spectra on the part of the grid of
+    /// `mu_h` covered by `mu_v`.
+    pub fn new(
+        kind: LinearDichroism,
+        mu_h: &Spectrum,
+        mu_v: &Spectrum,
+        angle_from_normal: f64,
+        config: &ElementConfig,
+    ) -> Result<Xmld, Error> {
+        let projection = kind.projection(angle_from_normal);
+        if projection.abs() < 1e-6 {
+            bail!(
+                "{:?} dichroism vanishes at {}° incidence",
+                kind,
+                angle_from_normal
+            );
+        }
+        Xmld::build(kind, mu_h, mu_v, angle_from_normal, projection, config)
+    }
+
+    /// Natural linear dichroism from two LH scans at different incidence
+    /// angles, both from the surface normal: `mu` is the scan at
+    /// `angle_from_normal` and `reference` the one at
+    /// `reference_angle_from_normal`, and the difference is divided by the
+    /// change in the out-of-plane projection between them.
+    pub fn from_angles(
+        mu: &Spectrum,
+        angle_from_normal: f64,
+        reference: &Spectrum,
+        reference_angle_from_normal: f64,
+        config: &ElementConfig,
+    ) -> Result<Xmld, Error> {
+        let kind = LinearDichroism::Natural;
+        let projection =
+            kind.projection(angle_from_normal) - kind.projection(reference_angle_from_normal);
+        if projection.abs() < 1e-6 {
+            bail!(
+                "Scans at {}° and {}° have the same projection",
+                angle_from_normal,
+                reference_angle_from_normal
+            );
+        }
+        Xmld::build(kind, mu, reference, angle_from_normal, projection, config)
+    }
+
+    fn build(
+        kind: LinearDichroism,
+        mu_h: &Spectrum,
+        mu_v: &Spectrum,
+        angle_from_normal: f64,
+        projection: f64,
+        config: &ElementConfig,
+    ) -> Result<Xmld, Error> {
+        let mut metadata = merged(&mu_h.metadata, &mu_v.metadata, ("_h", "_v"));
+        let NormalizedPair {
+            a: mu_h,
+            b: mu_v,
+            edge_jump,
+        } = normalize_pair(mu_h, mu_v, config)?;
+        let xas = (&mu_h + &mu_v)?.scale(0.5)?;
+        let raw = (&mu_h - &mu_v)?;
+        let dichroism = raw.scale(1.0 / projection)?;
+        pipeline::record(&mut metadata, normalization(&mu_h, config));
+        pipeline::record(
+            &mut metadata,
+            format!(
+                "{} angle_from_normal={} projection={}",
+                kind.name(),
+                angle_from_normal,
+                projection
+            ),
+        );
+
+        Ok(Xmld {
+            kind,
+            config: config.clone(),
+            angle_from_normal,
+            mu_h,
+            mu_v,
+            xas,
+            raw,
+            dichroism,
+            edge_jump,
+            metadata,
+        })
+    }
+
+    /// Reads a three-column file: energy, μH and μV.
+    pub fn from_columns<R>(
+        kind: LinearDichroism,
+        input: R,
+        angle_from_normal: f64,
+        config: &ElementConfig,
+    ) -> Result<Xmld, Error>
+    where
+        R: std::io::BufRead,
+    {
+        let (mu_h, mu_v) = Xas::load_pair(input)?;
+        Xmld::new(kind, &mu_h, &mu_v, angle_from_normal, config)
+    }
+
+    pub fn kind(&self) -> LinearDichroism {
+        self.kind
+    }
+
+    pub fn config(&self) -> &ElementConfig {
+        &self.config
+    }
+
+    /// Incidence angle from the surface normal, in degrees.
+    pub fn angle_from_normal(&self) -> f64 {
+        self.angle_from_normal
+    }
+
+    /// The common energy grid.
+    pub fn energy(&self) -> &[f64] {
+        self.xas.energy()
+    }
+
+    pub fn mu_h(&self) -> &Spectrum {
+        &self.mu_h
+    }
+
+    pub fn mu_v(&self) -> &Spectrum {
+        &self.mu_v
+    }
+
+    /// Polarization-averaged absorption, (μH + μV) / 2.
+    pub fn xas(&self) -> &Spectrum {
+        &self.xas
+    }
+
+    /// Uncorrected difference μH − μV.
+    pub fn raw(&self) -> &Spectrum {
+        &self.raw
+    }
+
+    /// Difference divided by the LH projection factor.
+    pub fn dichroism(&self) -> &Spectrum {
+        &self.dichroism
+    }
+
+    pub fn edge_jump(&self) -> f64 {
+        self.edge_jump
+    }
+
+    /// Metadata of both inputs followed by the normalization and the
+    /// projection correction as `Process.NN`. Entries the inputs disagree
+    /// on, or that only one of them has, are kept for each with an `_h` or
+    /// `_v` suffix on the key.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Normalized spectra with their average, over the corrected dichroism.
+    pub fn figure(&self) -> Figure {
+        let e = self.energy();
+        let spectra = Panel::new("Energy (eV)", "normalized μ")
+            .trace("μH", e, self.mu_h.values(), Style::Line, Color::RED)
+            .trace("μV", e, self.mu_v.values(), Style::Line, Color::BLUE)
+            .trace(
+                "(μH + μV)/2",
+                e,
+                self.xas.values(),
+                Style::Line,
+                Color::BLACK,
+            );
+        let label = self.kind.name().to_uppercase();
+        let dichroism = Panel::new("Energy (eV)", &label).trace(
+            "",
+            e,
+            self.dichroism.values(),
+            Style::Line,
+            Color::GREEN,
+        );
+        Figure::new(vec![spectra, dichroism])
+    }
+
+    /// Renders the figure into the file of `options`.
+    pub fn plot(&self, options: &PlotOptions) -> Result<(), Error> {
+        self.figure().save(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Ini;
+    use crate::math;
+    use crate::xmcd::two_step;
+
+    fn co() -> ElementConfig {
+        let ini = Ini::new(include_str!("../data/element.ini").as_bytes()).unwrap();
+        ElementConfig::new(&ini, "Co").unwrap()
+    }
+
+    /// `scale` times the two-step edge plus `white_line` times a Gaussian at
+    /// the first edge, over an offset.
+    fn scan(config: &ElementConfig, white_line: f64, scale: f64) -> Spectrum {
+        let energy = math::arange(config.start_energy, config.end_energy, 0.1);
+        let values = two_step(&energy, config)
+            .iter()
+            .zip(&energy)
+            .map(|(step, e)| {
+                let line = (-((e - config.energy_l3) / 1.5).powi(2)).exp();
+                0.2 + scale * (step + white_line * line)
+            })
+            .collect();
+        Spectrum::new(energy, values).unwrap()
+    }
+
+    #[test]
+    fn dichroism_is_normalized_and_signed() {
+        let config = co();
+        let (lh, lv) = (scan(&config, 3.0, 2.0), scan(&config, 2.0, 4.0));
+        let xmld = Xmld::new(LinearDichroism::Magnetic, &lh, &lv, 60.0, &config).unwrap();
+        let e = xmld.energy();
+        let pre_end = config.preedge_start + config.preedge_width;
+        for mu in &[xmld.mu_h(), xmld.mu_v()] {
+            let pre = math::mean_in(e, mu.values(), config.preedge_start, pre_end).unwrap();
+            let post = math::mean_in(e, mu.values(), config.post_start(), e[e.len() - 1]).unwrap();
+            assert!(
+                pre.abs() < 1e-9 && (post - 1.0).abs() < 1e-9,
+                "{} {}",
+                pre,
+                post
+            );
+        }
+        // LH has the stronger white line: positive at the edge, in units of
+        // the jump of the arctangent steps (the average is three of them),
+        // and divided by cos² 60° = 1/4.
+        let at_edge = xmld.raw().at(config.energy_l3) * xmld.edge_jump() / 3.0;
+        assert!((at_edge - 1.0).abs() < 1e-9, "{}", at_edge);
+        let at_edge = xmld.raw().at(config.energy_l3);
+        let corrected = xmld.dichroism().at(config.energy_l3);
+        assert!((corrected - 4.0 * at_edge).abs() < 1e-9, "{}", corrected);
+        let steps = pipeline::provenance(xmld.metadata());
+        assert!(
+            steps[1].starts_with("xmld angle_from_normal=60"),
+            "{:?}",
+            steps
+        );
+
+        let swapped = Xmld::new(LinearDichroism::Magnetic, &lv, &lh, 60.0, &config).unwrap();
+        assert!(swapped.dichroism().at(config.energy_l3) < 0.0);
+        assert!(Xmld::new(LinearDichroism::Magnetic, &lh, &lv, 90.0, &config).is_err());
+    }
+
+    #[test]
+    fn natural_dichroism_follows_the_projection() {
+        assert!((LinearDichroism::Natural.projection(0.0)).abs() < 1e-12);
+        assert!((LinearDichroism::Natural.projection(30.0) - 0.25).abs() < 1e-12);
+        assert!((LinearDichroism::Magnetic.projection(30.0) - 0.75).abs() < 1e-12);
+
+        // A white line of 1 in the plane and 1 + 0.8 along the normal,
+        // seen by LH as 1 + 0.8·sin² θ.
+        let config = co();
+        let at = |angle: f64| {
+            let white_line = 1.0 + 0.8 * LinearDichroism::Natural.projection(angle);
+            scan(&config, white_line, 1.0)
+        };
+        let xnld = Xmld::from_angles(&at(70.0), 70.0, &at(20.0), 20.0, &config).unwrap();
+        assert_eq!(xnld.kind(), LinearDichroism::Natural);
+        let anisotropy = xnld.dichroism().at(config.energy_l3) * xnld.edge_jump();
+        assert!((anisotropy - 0.8).abs() < 1e-9, "{}", anisotropy);
+        assert!(Xmld::from_angles(&at(30.0), 30.0, &at(30.0), -30.0, &config).is_err());
+    }
+}